@@ -0,0 +1,6 @@
+-- Menu management: prices in minor currency units, soft retirement and generated ids.
+ALTER TABLE menu_items ADD COLUMN price BIGINT NOT NULL DEFAULT 0 CHECK (price >= 0);
+ALTER TABLE menu_items ADD COLUMN retired_at TIMESTAMPTZ;
+
+ALTER TABLE menu_items ALTER COLUMN id ADD GENERATED BY DEFAULT AS IDENTITY;
+SELECT setval(pg_get_serial_sequence('menu_items', 'id'), COALESCE(MAX(id), 0) + 1, false) FROM menu_items;
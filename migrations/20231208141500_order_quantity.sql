@@ -0,0 +1,2 @@
+-- Each order row can hold several plates of the same menu item.
+ALTER TABLE orders ADD COLUMN quantity INTEGER NOT NULL DEFAULT 1 CHECK (quantity > 0);
@@ -0,0 +1,8 @@
+-- Order lifecycle: ordered -> cooking -> ready -> served, or cancelled before being served.
+CREATE TYPE order_status AS ENUM ('ordered', 'cooking', 'ready', 'served', 'cancelled');
+
+ALTER TABLE orders ADD COLUMN status order_status NOT NULL DEFAULT 'ordered';
+ALTER TABLE orders ADD COLUMN cooking_at TIMESTAMPTZ;
+ALTER TABLE orders ADD COLUMN ready_at TIMESTAMPTZ;
+ALTER TABLE orders ADD COLUMN served_at TIMESTAMPTZ;
+ALTER TABLE orders ADD COLUMN cancelled_at TIMESTAMPTZ;
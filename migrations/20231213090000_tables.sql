@@ -0,0 +1,14 @@
+-- Floor plan: every table of the restaurant with its number of seats and its section.
+CREATE TABLE IF NOT EXISTS tables (
+    table_number INT PRIMARY KEY,
+    seats INT NOT NULL CHECK (seats > 0),
+    section TEXT NOT NULL
+);
+
+-- Tables already used by orders join the floor plan, so the foreign key below holds.
+INSERT INTO tables (table_number, seats, section)
+SELECT DISTINCT table_number, 4, 'main' FROM orders
+ON CONFLICT DO NOTHING;
+
+ALTER TABLE orders ADD CONSTRAINT orders_table_number_fkey
+    FOREIGN KEY (table_number) REFERENCES tables (table_number);
@@ -0,0 +1,27 @@
+-- Table sessions: a party seated at a table, from the first order or seating until the guests leave.
+CREATE TABLE IF NOT EXISTS table_sessions (
+    id UUID PRIMARY KEY,
+    table_number INT NOT NULL REFERENCES tables (table_number),
+    party_size INT CHECK (party_size > 0),
+    opened_at TIMESTAMPTZ NOT NULL,
+    closed_at TIMESTAMPTZ
+);
+
+-- A table hosts a single party at a time.
+CREATE UNIQUE INDEX IF NOT EXISTS table_sessions_open_table_number_idx
+    ON table_sessions (table_number) WHERE closed_at IS NULL;
+
+ALTER TABLE orders ADD COLUMN session_id UUID REFERENCES table_sessions (id);
+CREATE INDEX IF NOT EXISTS orders_session_id_idx ON orders (session_id);
+
+-- Tables with orders still in progress get an open session holding them, finished orders stay unattached.
+INSERT INTO table_sessions (id, table_number, opened_at)
+SELECT gen_random_uuid(), table_number, MIN(created_at) FROM orders
+WHERE status IN ('ordered', 'cooking', 'ready')
+GROUP BY table_number;
+
+UPDATE orders SET session_id = table_sessions.id
+FROM table_sessions
+WHERE table_sessions.table_number = orders.table_number
+  AND table_sessions.closed_at IS NULL
+  AND orders.status IN ('ordered', 'cooking', 'ready');
@@ -0,0 +1,21 @@
+-- Payments of table sessions: partial payments with several tenders, change and refunds.
+CREATE TYPE tender AS ENUM ('cash', 'card', 'voucher');
+CREATE TYPE payment_kind AS ENUM ('payment', 'refund');
+
+CREATE TABLE IF NOT EXISTS payments (
+    id UUID PRIMARY KEY,
+    session_id UUID NOT NULL REFERENCES table_sessions (id),
+    kind payment_kind NOT NULL,
+    tender tender NOT NULL,
+    -- Minor units applied to the bill, or given back for a refund.
+    amount BIGINT NOT NULL CHECK (amount > 0),
+    -- Minor units handed over by the guest, the difference with the amount is the change.
+    tendered BIGINT NOT NULL CHECK (tendered >= amount),
+    change BIGINT NOT NULL CHECK (change >= 0),
+    refund_of UUID REFERENCES payments (id),
+    created_at TIMESTAMPTZ NOT NULL,
+    CHECK ((kind = 'refund') = (refund_of IS NOT NULL))
+);
+CREATE INDEX IF NOT EXISTS payments_session_id_idx ON payments (session_id);
+
+ALTER TABLE table_sessions ADD COLUMN settled_at TIMESTAMPTZ;
@@ -0,0 +1,5 @@
+-- Cancelling an order marks it instead of deleting it, recording why and by whom for waste tracking and disputes.
+ALTER TABLE orders ADD COLUMN cancel_reason TEXT;
+ALTER TABLE orders ADD COLUMN cancelled_by TEXT;
+
+CREATE INDEX orders_cancelled_at_idx ON orders (cancelled_at) WHERE cancelled_at IS NOT NULL;
@@ -0,0 +1,9 @@
+-- Idempotency-Key of order creations, so a retried request gets back the order it created the first time.
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+    key TEXT PRIMARY KEY,
+    -- Table, menu item and quantity of the request, a replay must ask for the same.
+    fingerprint TEXT NOT NULL,
+    order_id UUID NOT NULL REFERENCES orders (id),
+    -- Keys used longer ago than the window of the server can be used again.
+    created_at TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,30 @@
+-- Announce order changes on the 'order_changes' channel, so every server replica can tell its own clients.
+-- The payload carries the application_name of the writer: servers set it to their instance id and skip
+-- the changes they made themselves, which they already announced.
+CREATE OR REPLACE FUNCTION notify_order_change() RETURNS trigger AS $$
+DECLARE
+    changed orders;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        changed := OLD;
+    ELSE
+        changed := NEW;
+    END IF;
+    PERFORM pg_notify('order_changes', json_build_object(
+        'op', TG_OP,
+        'origin', current_setting('application_name'),
+        'order', row_to_json(changed)
+    )::text);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER orders_notify_insert_delete
+    AFTER INSERT OR DELETE ON orders
+    FOR EACH ROW EXECUTE FUNCTION notify_order_change();
+
+-- Only status changes, not quantity or table moves, matter to kitchen displays.
+CREATE TRIGGER orders_notify_status
+    AFTER UPDATE OF status ON orders
+    FOR EACH ROW WHEN (OLD.status IS DISTINCT FROM NEW.status)
+    EXECUTE FUNCTION notify_order_change();
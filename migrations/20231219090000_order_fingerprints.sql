@@ -0,0 +1,6 @@
+-- Table, menu item and quantity an order was first sent with, so a device syncing it again is compared with
+-- what it sent rather than with the order as changed since. Plates split off by a cancellation have none.
+ALTER TABLE orders ADD COLUMN IF NOT EXISTS fingerprint TEXT;
+
+UPDATE orders SET fingerprint = table_number || ':' || menu_item_id || ':' || quantity
+WHERE fingerprint IS NULL;
@@ -0,0 +1,37 @@
+-- Kitchen displays show the table, menu item and quantity of an order, so changing them is announced too,
+-- not only status changes as 'orders_notify_status' did. The payload also carries the status before an update,
+-- telling status changes apart from the other ones.
+CREATE OR REPLACE FUNCTION notify_order_change() RETURNS trigger AS $$
+DECLARE
+    changed orders;
+    previous_status order_status;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        changed := OLD;
+    ELSE
+        changed := NEW;
+    END IF;
+    IF TG_OP = 'UPDATE' THEN
+        previous_status := OLD.status;
+    END IF;
+    PERFORM pg_notify('order_changes', json_build_object(
+        'op', TG_OP,
+        'origin', current_setting('application_name'),
+        'previous_status', previous_status,
+        'order', row_to_json(changed)
+    )::text);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS orders_notify_status ON orders;
+
+CREATE TRIGGER orders_notify_update
+    AFTER UPDATE OF status, table_number, menu_item_id, quantity ON orders
+    FOR EACH ROW WHEN (
+        OLD.status IS DISTINCT FROM NEW.status
+        OR OLD.table_number IS DISTINCT FROM NEW.table_number
+        OR OLD.menu_item_id IS DISTINCT FROM NEW.menu_item_id
+        OR OLD.quantity IS DISTINCT FROM NEW.quantity
+    )
+    EXECUTE FUNCTION notify_order_change();
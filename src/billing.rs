@@ -0,0 +1,161 @@
+use crate::domain::{
+    CompleteOrder, FieldViolation, OrderStatus, Payment, PaymentError, PaymentKind, Tender,
+};
+use serde::Serialize;
+use uuid::Uuid;
+
+/// Basis points in a whole, rates are expressed in 1/100 of a percent so amounts stay integers.
+const BASIS_POINTS: i64 = 10_000;
+
+/// Rates added on top of the subtotal of a [Bill], in basis points: `1000` is 10%.
+#[derive(Debug, Clone, Copy)]
+pub struct BillingRates {
+    pub service_charge_bps: i64,
+    pub tax_rate_bps: i64,
+}
+
+/// One [CompleteOrder] on a [Bill]. Amounts are in minor currency units.
+#[derive(Serialize, Debug, Clone)]
+pub struct BillLine {
+    pub order_id: Uuid,
+    pub menu_item_id: i32,
+    pub item_name: String,
+    pub quantity: i32,
+    pub unit_price: i64,
+    pub amount: i64,
+}
+
+/// Itemized check of a table session. Amounts are in minor currency units.
+///
+/// The service charge is computed on the subtotal, and the tax on the subtotal plus the service charge.
+#[derive(Serialize, Debug, Clone)]
+pub struct Bill {
+    pub table_number: i32,
+    pub session_id: Option<Uuid>,
+    pub lines: Vec<BillLine>,
+    pub subtotal: i64,
+    pub service_charge_bps: i64,
+    pub service_charge: i64,
+    pub tax_rate_bps: i64,
+    pub tax: i64,
+    pub total: i64,
+    /// What each guest pays when the total is split, `None` if it isn't.
+    pub split: Option<Vec<i64>>,
+}
+
+/// How much of a [Bill] total the recorded [Payment]s cover. Amounts are in minor currency units.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Balance {
+    pub total: i64,
+    /// Payments minus refunds.
+    pub paid: i64,
+    /// What's left to pay, never negative.
+    pub due: i64,
+}
+
+/// Builds the [Bill] of a table from its orders. Cancelled orders aren't billed.
+///
+/// With `guests`, the total is split evenly and the leftover minor units go to the first guests,
+/// so the shares always add up to the total.
+pub fn build_bill(
+    table_number: i32,
+    orders: &[CompleteOrder],
+    rates: BillingRates,
+    guests: Option<i32>,
+) -> Bill {
+    let lines: Vec<BillLine> = orders
+        .iter()
+        .filter(|order| order.status != OrderStatus::Cancelled)
+        .map(|order| BillLine {
+            order_id: order.order_id,
+            menu_item_id: order.menu_item_id,
+            item_name: order.item_name.clone(),
+            quantity: order.quantity,
+            unit_price: order.price,
+            amount: order.price * order.quantity as i64,
+        })
+        .collect();
+    let subtotal: i64 = lines.iter().map(|line| line.amount).sum();
+    let service_charge = apply_rate(subtotal, rates.service_charge_bps);
+    let tax = apply_rate(subtotal + service_charge, rates.tax_rate_bps);
+    let total = subtotal + service_charge + tax;
+    Bill {
+        table_number,
+        session_id: orders.first().and_then(|order| order.session_id),
+        lines,
+        subtotal,
+        service_charge_bps: rates.service_charge_bps,
+        service_charge,
+        tax_rate_bps: rates.tax_rate_bps,
+        tax,
+        total,
+        split: guests.map(|guests| split(total, guests)),
+    }
+}
+
+/// Splits `total` in `guests` shares that differ by at most one minor unit.
+pub fn split(total: i64, guests: i32) -> Vec<i64> {
+    let guests = guests.max(1) as i64;
+    let (share, leftover) = (total / guests, total % guests);
+    (0..guests)
+        .map(|guest| share + i64::from(guest < leftover))
+        .collect()
+}
+
+/// Refuses to close a session with [PaymentError::NotCovered] while its [Balance] has something due.
+pub fn check_covered(balance: Balance) -> Result<(), PaymentError> {
+    if balance.due > 0 {
+        return Err(PaymentError::NotCovered {
+            total: balance.total,
+            paid: balance.paid,
+        });
+    }
+    Ok(())
+}
+
+/// Compares a [Bill] total with the payments and refunds recorded for it.
+pub fn balance(bill: &Bill, payments: &[Payment]) -> Balance {
+    let paid: i64 = payments
+        .iter()
+        .map(|payment| match payment.kind {
+            PaymentKind::Payment => payment.amount,
+            PaymentKind::Refund => -payment.amount,
+        })
+        .sum();
+    Balance {
+        total: bill.total,
+        paid,
+        due: (bill.total - paid).max(0),
+    }
+}
+
+/// Applies `amount` handed over with `tender` to what's `due`, returning the amount applied and the change.
+///
+/// Cash beyond what's due gives change, vouchers are only applied up to what's due and cards can't exceed it.
+pub fn tender_payment(due: i64, tender: Tender, amount: i64) -> Result<(i64, i64), PaymentError> {
+    if due <= 0 {
+        return Err(PaymentError::NothingDue);
+    }
+    match tender {
+        Tender::Card if amount > due => Err(PaymentError::Overpayment { due, amount }),
+        Tender::Cash => Ok((amount.min(due), (amount - due).max(0))),
+        Tender::Card | Tender::Voucher => Ok((amount.min(due), 0)),
+    }
+}
+
+/// Checks the number of guests a bill is split across.
+pub fn validate_split(guests: i32) -> Vec<FieldViolation> {
+    if guests < 1 {
+        vec![FieldViolation::new(
+            "split",
+            format!("A bill is split across at least 1 guest, got {}.", guests),
+        )]
+    } else {
+        vec![]
+    }
+}
+
+/// Applies a rate in basis points, rounding half up to the nearest minor unit.
+fn apply_rate(amount: i64, bps: i64) -> i64 {
+    (amount * bps + BASIS_POINTS / 2) / BASIS_POINTS
+}
@@ -0,0 +1,187 @@
+use crate::constants::{ORDER_CHANGES_CHANNEL, ORDER_CHANGES_RETRY_SECS};
+use crate::domain::{read_order_by_id, CompleteOrder, OrderStatus};
+use crate::events::{EventBus, OrderEvent, OrderEventKind};
+use crate::kitchen::KitchenScheduler;
+use crate::presentation::refresh_kitchen;
+use crate::repository::{OrderRepository, PgSqlOrderRepository, RepositoryError};
+use actix_web::rt::time::sleep;
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use serde::Deserialize;
+use sqlx::postgres::PgListener;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Row operation a trigger of the `orders` table notified.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+enum ChangeOp {
+    Insert,
+    Update,
+    Delete,
+}
+
+/// The columns of an `orders` row that make an [OrderEvent].
+#[derive(Deserialize, Debug)]
+struct OrderRow {
+    id: Uuid,
+    table_number: i32,
+    menu_item_id: i32,
+    quantity: i32,
+    status: OrderStatus,
+    created_at: DateTime<Utc>,
+    cooking_at: Option<DateTime<Utc>>,
+    ready_at: Option<DateTime<Utc>>,
+    served_at: Option<DateTime<Utc>>,
+    cancelled_at: Option<DateTime<Utc>>,
+}
+
+/// Payload of a notification on [ORDER_CHANGES_CHANNEL].
+#[derive(Deserialize, Debug)]
+struct OrderChange {
+    op: ChangeOp,
+    /// `application_name` of the connection that made the change.
+    origin: String,
+    /// Status of the order before an update, `None` for inserts and deletes.
+    #[serde(default)]
+    previous_status: Option<OrderStatus>,
+    order: OrderRow,
+}
+
+impl OrderChange {
+    fn into_event(self) -> OrderEvent {
+        let order = self.order;
+        let kind = match (self.op, order.status) {
+            (ChangeOp::Delete, _) | (_, OrderStatus::Cancelled) => OrderEventKind::OrderCancelled,
+            (ChangeOp::Insert, _) => OrderEventKind::OrderCreated,
+            // Moved to another table, or its menu item or quantity changed.
+            (ChangeOp::Update, status) if self.previous_status == Some(status) => {
+                OrderEventKind::OrderUpdated
+            }
+            (ChangeOp::Update, _) => OrderEventKind::OrderStatusChanged,
+        };
+        let at = match (self.op, order.status) {
+            (ChangeOp::Delete, _) => None,
+            _ if kind == OrderEventKind::OrderUpdated => None,
+            (_, OrderStatus::Ordered) => Some(order.created_at),
+            (_, OrderStatus::Cooking) => order.cooking_at,
+            (_, OrderStatus::Ready) => order.ready_at,
+            (_, OrderStatus::Served) => order.served_at,
+            (_, OrderStatus::Cancelled) => order.cancelled_at,
+        };
+        OrderEvent {
+            id: 0,
+            kind,
+            order_id: order.id,
+            table_number: order.table_number,
+            menu_item_id: order.menu_item_id,
+            quantity: order.quantity,
+            status: order.status,
+            at: at.unwrap_or_else(Utc::now),
+        }
+    }
+}
+
+/// The [OrderEvent] of a notification on [ORDER_CHANGES_CHANNEL], `None` if this server made the change.
+///
+/// Servers connect with their instance id as `application_name`, and publish their own changes
+/// as soon as they make them.
+pub fn order_event(
+    payload: &str,
+    instance_id: &str,
+) -> Result<Option<OrderEvent>, serde_json::Error> {
+    let change: OrderChange = serde_json::from_str(payload)?;
+    Ok((change.origin != instance_id).then(|| change.into_event()))
+}
+
+/// Follows the order changes made by the other servers sharing the database, for as long as this one runs:
+/// the kitchen queue is reloaded and the change published.
+///
+/// Listening starts over after [ORDER_CHANGES_RETRY_SECS] when the connection is lost, catching up with
+/// the changes made in the meantime, see [catch_up].
+pub async fn listen_order_changes(
+    repo: PgSqlOrderRepository,
+    instance_id: String,
+    kitchen: KitchenScheduler,
+    events: EventBus,
+) {
+    loop {
+        if let Err(error) = forward_order_changes(&repo, &instance_id, &kitchen, &events).await {
+            error!("Lost the order changes feed: {:?}", error);
+        }
+        sleep(Duration::from_secs(ORDER_CHANGES_RETRY_SECS)).await;
+    }
+}
+
+async fn forward_order_changes(
+    repo: &PgSqlOrderRepository,
+    instance_id: &str,
+    kitchen: &KitchenScheduler,
+    events: &EventBus,
+) -> Result<(), sqlx::Error> {
+    let mut listener = PgListener::connect_with(repo.pool()).await?;
+    listener.listen(ORDER_CHANGES_CHANNEL).await?;
+    info!("Listening to order changes ... OK");
+    catch_up(repo, kitchen, events).await;
+    loop {
+        let notification = listener.recv().await?;
+        match order_event(notification.payload(), instance_id) {
+            Ok(Some(event)) => {
+                refresh_kitchen(kitchen, repo).await;
+                events.publish(event);
+            }
+            Ok(None) => {}
+            Err(error) => error!(
+                "Unreadable order change {}: {:?}",
+                notification.payload(),
+                error
+            ),
+        }
+    }
+}
+
+/// Reloads the kitchen queue, publishing the latest change of every open order that changed since it was
+/// last loaded, including the ones that left it. Changes are not notified while the feed is down.
+///
+/// Each order gets one event, telling its current status, even if it changed several times: an order
+/// still in the status it was known in gets an [OrderEventKind::OrderUpdated].
+pub(crate) async fn catch_up<R: OrderRepository<ErrT = RepositoryError>>(
+    repo: &R,
+    kitchen: &KitchenScheduler,
+    events: &EventBus,
+) {
+    let before = kitchen.open_orders();
+    refresh_kitchen(kitchen, repo).await;
+    let after = kitchen.open_orders();
+    let mut changed: Vec<_> = after
+        .iter()
+        .filter(|order| !before.iter().any(|known| same_event_fields(known, order)))
+        .cloned()
+        .collect();
+    for left in before
+        .iter()
+        .filter(|known| !after.iter().any(|order| order.order_id == known.order_id))
+    {
+        match read_order_by_id(repo, left.order_id).await {
+            Ok(Some(order)) => changed.push(order),
+            Ok(None) => {}
+            Err(error) => error!(
+                "Failed to catch up with order {}: {:?}",
+                left.order_id, error
+            ),
+        }
+    }
+    for order in changed {
+        let previous = before.iter().find(|known| known.order_id == order.order_id);
+        events.publish(OrderEvent::latest(&order, previous));
+    }
+}
+
+/// Whether two versions of an order make the same [OrderEvent], apart from its time.
+fn same_event_fields(known: &CompleteOrder, order: &CompleteOrder) -> bool {
+    known.order_id == order.order_id
+        && known.status == order.status
+        && known.table_number == order.table_number
+        && known.menu_item_id == order.menu_item_id
+        && known.quantity == order.quantity
+}
@@ -0,0 +1,87 @@
+use crate::billing::BillingRates;
+use crate::constants::{
+    DEFAULT_COOKING_SLOTS, DEFAULT_IDEMPOTENCY_WINDOW_SECS, DEFAULT_MAX_TABLE_NUMBER,
+    DEFAULT_MIN_TABLE_NUMBER, DEFAULT_SERVICE_CHARGE_BPS, DEFAULT_TAX_RATE_BPS,
+};
+use chrono::Duration;
+use std::env;
+use std::ops::RangeInclusive;
+use uuid::Uuid;
+
+/// Runtime settings of the restaurant server.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// How many orders the kitchen can cook in parallel, see `KITCHEN_COOKING_SLOTS`.
+    pub cooking_slots: usize,
+    /// Lowest table number of the floor plan, see `FLOOR_PLAN_MIN_TABLE`.
+    pub min_table_number: i32,
+    /// Highest table number of the floor plan, see `FLOOR_PLAN_MAX_TABLE`.
+    pub max_table_number: i32,
+    /// Tax rate of bills in basis points, see `BILL_TAX_RATE_BPS`.
+    pub tax_rate_bps: i64,
+    /// Service charge of bills in basis points, see `BILL_SERVICE_CHARGE_BPS`.
+    pub service_charge_bps: i64,
+    /// Seconds an `Idempotency-Key` of an order creation is remembered, see `IDEMPOTENCY_KEY_TTL_SECS`.
+    pub idempotency_window_secs: i64,
+    /// Tells this server apart from the other replicas sharing the database, generated at startup.
+    pub instance_id: String,
+}
+
+impl Config {
+    /// Loads the settings from environment variables, falling back to the defaults for unset or invalid values.
+    pub fn from_env() -> Self {
+        let default = Config::default();
+        Config {
+            cooking_slots: env_or("KITCHEN_COOKING_SLOTS", default.cooking_slots),
+            min_table_number: env_or("FLOOR_PLAN_MIN_TABLE", default.min_table_number),
+            max_table_number: env_or("FLOOR_PLAN_MAX_TABLE", default.max_table_number),
+            tax_rate_bps: env_or("BILL_TAX_RATE_BPS", default.tax_rate_bps),
+            service_charge_bps: env_or("BILL_SERVICE_CHARGE_BPS", default.service_charge_bps),
+            idempotency_window_secs: env_or(
+                "IDEMPOTENCY_KEY_TTL_SECS",
+                default.idempotency_window_secs,
+            ),
+            instance_id: default.instance_id,
+        }
+    }
+
+    /// Table numbers the floor plan can hold. A fresh database starts with a table for each of them.
+    pub fn table_numbers(&self) -> RangeInclusive<i32> {
+        self.min_table_number..=self.max_table_number
+    }
+
+    /// Rates added on top of the subtotal of every bill.
+    pub fn billing_rates(&self) -> BillingRates {
+        BillingRates {
+            service_charge_bps: self.service_charge_bps,
+            tax_rate_bps: self.tax_rate_bps,
+        }
+    }
+
+    /// How long a retried order creation returns the order created the first time.
+    pub fn idempotency_window(&self) -> Duration {
+        Duration::seconds(self.idempotency_window_secs)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            cooking_slots: DEFAULT_COOKING_SLOTS,
+            min_table_number: DEFAULT_MIN_TABLE_NUMBER,
+            max_table_number: DEFAULT_MAX_TABLE_NUMBER,
+            tax_rate_bps: DEFAULT_TAX_RATE_BPS,
+            service_charge_bps: DEFAULT_SERVICE_CHARGE_BPS,
+            idempotency_window_secs: DEFAULT_IDEMPOTENCY_WINDOW_SECS,
+            instance_id: format!("restaurant-{}", Uuid::new_v4()),
+        }
+    }
+}
+
+/// Parses an environment variable, or returns `default` if it's missing or can't be parsed.
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
@@ -51,3 +51,54 @@ pub const FOOD_ITEMS: [&str; 50] = [
     "Kushiage",
     "Yatsuhashi",
 ];
+
+/// Number of orders the kitchen cooks in parallel when `KITCHEN_COOKING_SLOTS` isn't set.
+pub const DEFAULT_COOKING_SLOTS: usize = 4;
+
+/// Lowest table number of the floor plan when `FLOOR_PLAN_MIN_TABLE` isn't set.
+pub const DEFAULT_MIN_TABLE_NUMBER: i32 = 1;
+
+/// Highest table number of the floor plan when `FLOOR_PLAN_MAX_TABLE` isn't set.
+pub const DEFAULT_MAX_TABLE_NUMBER: i32 = 500;
+
+/// Seats of every table of the default floor plan.
+pub const DEFAULT_TABLE_SEATS: i32 = 4;
+
+/// Section of every table of the default floor plan.
+pub const DEFAULT_TABLE_SECTION: &str = "main";
+
+/// Tax rate in basis points when `BILL_TAX_RATE_BPS` isn't set.
+pub const DEFAULT_TAX_RATE_BPS: i64 = 0;
+
+/// Service charge in basis points when `BILL_SERVICE_CHARGE_BPS` isn't set.
+pub const DEFAULT_SERVICE_CHARGE_BPS: i64 = 0;
+
+/// Number of orders per page when a listing doesn't set `limit`.
+pub const DEFAULT_PAGE_LIMIT: i64 = 50;
+
+/// Most orders a single page can hold.
+pub const MAX_PAGE_LIMIT: i64 = 200;
+
+/// Longest reason or actor recorded when cancelling an order.
+pub const MAX_CANCELLATION_TEXT_LENGTH: usize = 500;
+
+/// Seconds an `Idempotency-Key` is remembered when `IDEMPOTENCY_KEY_TTL_SECS` isn't set.
+pub const DEFAULT_IDEMPOTENCY_WINDOW_SECS: i64 = 24 * 60 * 60;
+
+/// Longest `Idempotency-Key` header accepted.
+pub const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+
+/// Number of latest order events kept for clients resuming with `Last-Event-ID`.
+pub const EVENT_HISTORY_SIZE: usize = 1024;
+
+/// Events queued for a streaming client before it's considered too slow and disconnected.
+pub const EVENT_SUBSCRIBER_BUFFER: usize = 256;
+
+/// Seconds without events before a keep-alive comment is streamed.
+pub const EVENT_KEEP_ALIVE_SECS: u64 = 15;
+
+/// Postgres channel the order triggers notify on, see the `order_change_notifications` migration.
+pub const ORDER_CHANGES_CHANNEL: &str = "order_changes";
+
+/// Seconds before listening to order changes again after losing the database connection.
+pub const ORDER_CHANGES_RETRY_SECS: u64 = 5;
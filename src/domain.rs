@@ -1,34 +1,643 @@
-use crate::repository::OrderRepository;
-use chrono::{DateTime, Utc};
-use serde::Serialize;
+use crate::billing::{balance, build_bill, tender_payment, Balance, BillingRates};
+use crate::constants::{MAX_CANCELLATION_TEXT_LENGTH, MAX_IDEMPOTENCY_KEY_LENGTH, MAX_PAGE_LIMIT};
+use crate::repository::{MenuRepository, OrderRepository, PaymentRepository, TableRepository};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
 use uuid::Uuid;
 
 /// Every item created for a table number is defined as a restaurant [Order]
+///
+/// [Self::quantity] is the number of plates of the same menu item, so "four gyoza" is a single order.
+///
+/// Each [OrderStatus] reached after [OrderStatus::Ordered] records when it happened.
+///
+/// [Self::session_id] is assigned by the repository: the [TableSession] open at the table, opened on the first order if needed.
 #[derive(Debug, Clone)]
 pub struct Order {
     pub id: Uuid,
     pub table_number: i32,
+    pub session_id: Option<Uuid>,
     pub menu_item_id: i32,
     pub created_at: DateTime<Utc>,
+    pub quantity: i32,
+    pub status: OrderStatus,
+    pub cooking_at: Option<DateTime<Utc>>,
+    pub ready_at: Option<DateTime<Utc>>,
+    pub served_at: Option<DateTime<Utc>>,
+    pub cancelled_at: Option<DateTime<Utc>>,
+    pub cancel_reason: Option<String>,
+    /// Who cancelled the order, as given by the client.
+    pub cancelled_by: Option<String>,
+}
+
+/// Lifecycle of an [Order]: `ordered -> cooking -> ready -> served`.
+///
+/// An order can be cancelled at any point before it is served. Served and cancelled orders are final.
+#[derive(Serialize, Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "order_status", rename_all = "snake_case")]
+pub enum OrderStatus {
+    Ordered,
+    Cooking,
+    Ready,
+    Served,
+    Cancelled,
+}
+
+impl OrderStatus {
+    /// Whether an [Order] in this status can legally move to `next`.
+    pub fn can_transition_to(self, next: OrderStatus) -> bool {
+        use OrderStatus::*;
+        matches!(
+            (self, next),
+            (Ordered, Cooking)
+                | (Cooking, Ready)
+                | (Ready, Served)
+                | (Ordered, Cancelled)
+                | (Cooking, Cancelled)
+                | (Ready, Cancelled)
+        )
+    }
+
+    /// The status a kitchen bump moves an [Order] to, `None` once it is served or cancelled.
+    pub fn next(self) -> Option<OrderStatus> {
+        use OrderStatus::*;
+        match self {
+            Ordered => Some(Cooking),
+            Cooking => Some(Ready),
+            Ready => Some(Served),
+            Served | Cancelled => None,
+        }
+    }
+}
+
+impl fmt::Display for OrderStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            OrderStatus::Ordered => "ordered",
+            OrderStatus::Cooking => "cooking",
+            OrderStatus::Ready => "ready",
+            OrderStatus::Served => "served",
+            OrderStatus::Cancelled => "cancelled",
+        };
+        f.write_str(status)
+    }
+}
+
+/// An [Order] was asked to move to a status it can't reach from its current one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IllegalTransition {
+    pub from: OrderStatus,
+    pub to: OrderStatus,
+}
+
+/// An `Idempotency-Key` was sent again with a different [Order] than the first time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdempotencyKeyReused {
+    pub key: String,
+}
+
+/// How a guest pays. Only cash gives change.
+#[derive(Serialize, Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "tender", rename_all = "snake_case")]
+pub enum Tender {
+    Cash,
+    Card,
+    Voucher,
+}
+
+/// Whether a [Payment] is money received for the bill or given back.
+#[derive(Serialize, Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "payment_kind", rename_all = "snake_case")]
+pub enum PaymentKind {
+    Payment,
+    Refund,
+}
+
+/// Money received for, or given back from, the bill of a [TableSession]. Amounts are in minor currency units.
+///
+/// [Self::amount] is what counts towards the bill, [Self::tendered] what the guest handed over
+/// and [Self::change] what was given back. Refunds reference the payment they give back with [Self::refund_of].
+#[derive(Serialize, sqlx::FromRow, Debug, Clone, PartialEq, Eq)]
+pub struct Payment {
+    pub id: Uuid,
+    pub session_id: Uuid,
+    pub kind: PaymentKind,
+    pub tender: Tender,
+    pub amount: i64,
+    pub tendered: i64,
+    pub change: i64,
+    pub refund_of: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A payment, refund or settlement refused because of the bill of the session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaymentError {
+    /// The table has no open session to pay for.
+    NoOpenSession(i32),
+    /// The bill of the session is already covered.
+    NothingDue,
+    /// Only cash can be handed over beyond what's due, the rest would need change.
+    Overpayment { due: i64, amount: i64 },
+    /// A refund can't give back more than what's left of the payment it references.
+    RefundExceedsPayment { refundable: i64, amount: i64 },
+    /// The recorded payments don't cover the bill yet.
+    NotCovered { total: i64, paid: i64 },
+}
+
+impl fmt::Display for PaymentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaymentError::NoOpenSession(table_number) => {
+                write!(f, "Table {} has no open session.", table_number)
+            }
+            PaymentError::NothingDue => write!(f, "The bill is already covered."),
+            PaymentError::Overpayment { due, amount } => write!(
+                f,
+                "Only cash can exceed what's due, {} due but got {}.",
+                due, amount
+            ),
+            PaymentError::RefundExceedsPayment { refundable, amount } => write!(
+                f,
+                "Only {} can be refunded from this payment, got {}.",
+                refundable, amount
+            ),
+            PaymentError::NotCovered { total, paid } => write!(
+                f,
+                "Payments don't cover the bill, {} paid out of {}.",
+                paid, total
+            ),
+        }
+    }
+}
+
+/// The [Payment]s of a [TableSession] and how much of its bill is left to pay.
+#[derive(Serialize, Debug, Clone)]
+pub struct SessionPayments {
+    pub session_id: Uuid,
+    #[serde(flatten)]
+    pub balance: Balance,
+    pub payments: Vec<Payment>,
 }
 
 /// Defines the food item options on the menu. These need to be pre-registered in the database before taking new [Order]s.
-#[derive(Debug, Clone)]
+///
+/// [Self::price] is stored in minor currency units. Retired items stay in the database so past [Order]s
+/// keep their details, but they can't be ordered anymore.
+#[derive(Serialize, sqlx::FromRow, Debug, Clone)]
 pub struct MenuItem {
     pub id: i32,
     pub item_name: String,
     pub cooking_time: i32,
+    pub price: i64,
+    pub retired_at: Option<DateTime<Utc>>,
+}
+
+/// A [MenuItem] to be added to the menu. Its id is generated by the repository.
+#[derive(Debug, Clone)]
+pub struct NewMenuItem {
+    pub item_name: String,
+    pub cooking_time: i32,
+    pub price: i64,
+}
+
+/// Changes to apply to an existing [MenuItem]. Fields left as `None` are kept as they are.
+#[derive(Debug, Clone, Default)]
+pub struct MenuItemPatch {
+    pub item_name: Option<String>,
+    pub cooking_time: Option<i32>,
+    pub price: Option<i64>,
+}
+
+impl From<&NewMenuItem> for MenuItemPatch {
+    fn from(item: &NewMenuItem) -> Self {
+        MenuItemPatch {
+            item_name: Some(item.item_name.clone()),
+            cooking_time: Some(item.cooking_time),
+            price: Some(item.price),
+        }
+    }
+}
+
+/// A table of the restaurant's floor plan. [Order]s can only be placed for tables on the floor plan.
+#[derive(Serialize, sqlx::FromRow, Debug, Clone, PartialEq, Eq)]
+pub struct RestaurantTable {
+    pub table_number: i32,
+    pub seats: i32,
+    pub section: String,
+}
+
+/// A party seated at a [RestaurantTable], from seating or its first [Order] until the guests leave.
+///
+/// A table has at most one open session, new orders for the table are attached to it.
+/// [Self::party_size] is unknown when the session was opened by an order rather than by seating guests.
+#[derive(Serialize, sqlx::FromRow, Debug, Clone, PartialEq, Eq)]
+pub struct TableSession {
+    pub id: Uuid,
+    pub table_number: i32,
+    pub party_size: Option<i32>,
+    pub opened_at: DateTime<Utc>,
+    pub closed_at: Option<DateTime<Utc>>,
+    /// When the bill was settled, which also closes the session.
+    pub settled_at: Option<DateTime<Utc>>,
 }
 
 /// Struct to map complete queries that joins [MenuItem]s info into the [Order]s.
+///
+/// [Self::estimated_ready_at] isn't stored: repositories leave it empty and the domain fills it
+/// in with [CompleteOrder::with_estimated_ready_at] before returning the order.
 #[derive(Serialize, sqlx::FromRow, Debug, Clone)]
 pub struct CompleteOrder {
     pub order_id: Uuid,
     pub table_number: i32,
+    pub session_id: Option<Uuid>,
     pub menu_item_id: i32,
     pub created_at: DateTime<Utc>,
+    pub quantity: i32,
+    pub status: OrderStatus,
+    pub cooking_at: Option<DateTime<Utc>>,
+    pub ready_at: Option<DateTime<Utc>>,
+    pub served_at: Option<DateTime<Utc>>,
+    pub cancelled_at: Option<DateTime<Utc>>,
+    pub cancel_reason: Option<String>,
+    pub cancelled_by: Option<String>,
     pub item_name: String,
     pub cooking_time: i32,
+    /// Price of one plate of the [MenuItem], in minor currency units.
+    pub price: i64,
+    pub estimated_ready_at: Option<DateTime<Utc>>,
+}
+
+/// How long until an open [Order] of a table is ready, for answering "how long?" at the table.
+#[derive(Serialize, Debug, Clone)]
+pub struct RemainingTime {
+    pub order_id: Uuid,
+    pub item_name: String,
+    pub quantity: i32,
+    pub status: OrderStatus,
+    pub estimated_ready_at: DateTime<Utc>,
+    pub remaining_minutes: i64,
+}
+
+impl CompleteOrder {
+    /// Fills in [Self::estimated_ready_at]: [Self::created_at] plus [Self::cooking_time] minutes
+    /// until the kitchen marks it ready, then the actual [Self::ready_at]. Cancelled orders will never be ready.
+    pub fn with_estimated_ready_at(mut self) -> Self {
+        self.estimated_ready_at = match self.status {
+            OrderStatus::Cancelled => None,
+            _ => self
+                .ready_at
+                .or_else(|| Some(self.created_at + Duration::minutes(self.cooking_time as i64))),
+        };
+        self
+    }
+
+    /// Whole minutes left until [Self::estimated_ready_at], rounded up and never negative.
+    ///
+    /// `None` if the order is already ready, served or cancelled.
+    pub fn remaining_time(&self, now: DateTime<Utc>) -> Option<RemainingTime> {
+        if !matches!(self.status, OrderStatus::Ordered | OrderStatus::Cooking) {
+            return None;
+        }
+        let estimated_ready_at = self.estimated_ready_at?;
+        let remaining_seconds = (estimated_ready_at - now).num_seconds().max(0);
+        Some(RemainingTime {
+            order_id: self.order_id,
+            item_name: self.item_name.clone(),
+            quantity: self.quantity,
+            status: self.status,
+            estimated_ready_at,
+            remaining_minutes: (remaining_seconds + 59) / 60,
+        })
+    }
+}
+
+/// Identifies the orders of a menu item at a table, e.g. to take the latest one back.
+///
+/// Fields are named so the table number and the menu item id can't be swapped silently,
+/// and it deserializes straight from the `/v1/tables/{table_number}/menu_items/{menu_item_id}` path.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TableItemKey {
+    pub table_number: i32,
+    pub menu_item_id: i32,
+}
+
+impl From<&Order> for TableItemKey {
+    fn from(order: &Order) -> Self {
+        TableItemKey {
+            table_number: order.table_number,
+            menu_item_id: order.menu_item_id,
+        }
+    }
+}
+
+impl From<&CompleteOrder> for TableItemKey {
+    fn from(order: &CompleteOrder) -> Self {
+        TableItemKey {
+            table_number: order.table_number,
+            menu_item_id: order.menu_item_id,
+        }
+    }
+}
+
+/// What storing an [Order] did when its id may have been stored already, e.g. by a device syncing after being offline.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncOutcome {
+    /// The order wasn't stored yet.
+    Created,
+    /// The same order was already stored under this id, nothing changed.
+    Unchanged,
+}
+
+/// The `Idempotency-Key` of an [Order] creation and the order it created.
+///
+/// While the key is within the window of the server, a retry gets [Self::order_id] back instead of a new order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdempotencyRecord {
+    pub key: String,
+    /// Table number, menu item and quantity asked for, a retry must ask for the same.
+    pub fingerprint: String,
+    pub order_id: Uuid,
+    pub created_at: DateTime<Utc>,
+}
+
+impl IdempotencyRecord {
+    /// Records the key of a request creating this [Order].
+    pub fn new(key: &str, order: &Order) -> Self {
+        IdempotencyRecord {
+            key: key.to_string(),
+            fingerprint: order.fingerprint(),
+            order_id: order.id,
+            created_at: order.created_at,
+        }
+    }
+
+    /// The [Order::id] to answer a request with this key, unless the request asked for something else.
+    fn replay(self, order: &Order) -> Result<Uuid, IdempotencyKeyReused> {
+        if self.fingerprint == order.fingerprint() {
+            Ok(self.order_id)
+        } else {
+            Err(IdempotencyKeyReused { key: self.key })
+        }
+    }
+}
+
+/// Why and by whom an [Order] is cancelled, both as given by the client.
+#[derive(Debug, Clone, Default)]
+pub struct Cancellation {
+    pub reason: Option<String>,
+    pub actor: Option<String>,
+}
+
+/// Which cancelled [CompleteOrder]s to list. Filters left as `None` match everything.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationFilter {
+    pub table_number: Option<i32>,
+    /// Inclusive lower bound of [CompleteOrder::cancelled_at].
+    pub since: Option<DateTime<Utc>>,
+    /// Exclusive upper bound of [CompleteOrder::cancelled_at].
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl CancellationFilter {
+    /// Whether a cancelled order passes the filters.
+    pub fn matches(&self, order: &CompleteOrder) -> bool {
+        let Some(cancelled_at) = order.cancelled_at else {
+            return false;
+        };
+        self.table_number
+            .is_none_or(|table_number| order.table_number == table_number)
+            && self.since.is_none_or(|since| cancelled_at >= since)
+            && self.until.is_none_or(|until| cancelled_at < until)
+    }
+}
+
+/// Changes to apply to an existing [Order]. Fields left as `None` are kept as they are.
+///
+/// [Order::id] and [Order::created_at] can't be changed, so an updated order keeps its place in the kitchen.
+#[derive(Debug, Clone, Default)]
+pub struct OrderPatch {
+    pub table_number: Option<i32>,
+    pub menu_item_id: Option<i32>,
+    pub quantity: Option<i32>,
+}
+
+impl From<&Order> for OrderPatch {
+    fn from(order: &Order) -> Self {
+        OrderPatch {
+            table_number: Some(order.table_number),
+            menu_item_id: Some(order.menu_item_id),
+            quantity: Some(order.quantity),
+        }
+    }
+}
+
+/// Order of a listing of [CompleteOrder]s. Ties are broken by [CompleteOrder::created_at], then by id,
+/// so pages never overlap.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderSort {
+    /// Oldest first.
+    #[default]
+    CreatedAt,
+    /// Quickest to cook first.
+    CookingTime,
+}
+
+impl OrderSort {
+    /// Position of an order in the listing, the same as the `ORDER BY` of [PgSqlOrderRepository](crate::repository::PgSqlOrderRepository).
+    pub fn key(
+        self,
+        cooking_time: i32,
+        created_at: DateTime<Utc>,
+        order_id: Uuid,
+    ) -> (i32, DateTime<Utc>, Uuid) {
+        match self {
+            OrderSort::CreatedAt => (0, created_at, order_id),
+            OrderSort::CookingTime => (cooking_time, created_at, order_id),
+        }
+    }
+}
+
+/// Position of the last [CompleteOrder] of a page, the next page starts right after it.
+///
+/// It carries every sort key instead of just the order id, so it stays valid if that order is deleted meanwhile.
+/// Clients get it as an opaque string, see [OrderPage::next_cursor].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OrderCursor {
+    pub cooking_time: i32,
+    pub created_at: DateTime<Utc>,
+    pub order_id: Uuid,
+}
+
+impl From<&CompleteOrder> for OrderCursor {
+    fn from(order: &CompleteOrder) -> Self {
+        OrderCursor {
+            cooking_time: order.cooking_time,
+            created_at: order.created_at,
+            order_id: order.order_id,
+        }
+    }
+}
+
+impl OrderCursor {
+    /// Whether `order` comes after this cursor in the `sort` order.
+    pub fn precedes(&self, sort: OrderSort, order: &CompleteOrder) -> bool {
+        sort.key(self.cooking_time, self.created_at, self.order_id)
+            < sort.key(order.cooking_time, order.created_at, order.order_id)
+    }
+}
+
+impl fmt::Display for OrderCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Nanoseconds, so in-memory timestamps round-trip exactly.
+        let created_at = self.created_at.timestamp_nanos_opt().unwrap_or(i64::MAX);
+        write!(
+            f,
+            "{}_{}_{}",
+            self.cooking_time,
+            created_at,
+            self.order_id.simple()
+        )
+    }
+}
+
+impl FromStr for OrderCursor {
+    type Err = FieldViolation;
+
+    fn from_str(cursor: &str) -> Result<Self, Self::Err> {
+        let invalid = || FieldViolation::new("cursor", format!("Invalid cursor '{}'.", cursor));
+        let mut parts = cursor.splitn(3, '_');
+        let (Some(cooking_time), Some(created_at), Some(order_id)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        Ok(OrderCursor {
+            cooking_time: cooking_time.parse().map_err(|_| invalid())?,
+            created_at: Utc.timestamp_nanos(created_at.parse().map_err(|_| invalid())?),
+            order_id: order_id.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Which [CompleteOrder]s of a table to list, and in which order.
+#[derive(Debug, Clone, Default)]
+pub struct TableOrdersFilter {
+    /// Defaults to the [TableSession] open at the table.
+    pub session_id: Option<Uuid>,
+    pub menu_item_id: Option<i32>,
+    /// Inclusive lower bound of [CompleteOrder::created_at].
+    pub created_from: Option<DateTime<Utc>>,
+    /// Exclusive upper bound of [CompleteOrder::created_at].
+    pub created_to: Option<DateTime<Utc>>,
+    pub sort: OrderSort,
+    /// Only list orders after this one.
+    pub cursor: Option<OrderCursor>,
+}
+
+impl TableOrdersFilter {
+    /// Whether an order of the table passes the filters, ignoring the session.
+    pub fn matches(&self, order: &CompleteOrder) -> bool {
+        self.menu_item_id.is_none_or(|id| order.menu_item_id == id)
+            && self
+                .created_from
+                .is_none_or(|from| order.created_at >= from)
+            && self.created_to.is_none_or(|to| order.created_at < to)
+            && self
+                .cursor
+                .is_none_or(|cursor| cursor.precedes(self.sort, order))
+    }
+}
+
+/// Which [CompleteOrder]s to find across every table, and in which order. Filters left as `None` match everything.
+#[derive(Debug, Clone, Default)]
+pub struct OrderSearch {
+    pub menu_item_id: Option<i32>,
+    /// Inclusive lowest table number.
+    pub table_from: Option<i32>,
+    /// Inclusive highest table number.
+    pub table_to: Option<i32>,
+    /// Inclusive lower bound of [CompleteOrder::created_at].
+    pub created_from: Option<DateTime<Utc>>,
+    /// Exclusive upper bound of [CompleteOrder::created_at].
+    pub created_to: Option<DateTime<Utc>>,
+    /// Cancelled orders are left out unless asked for with [OrderStatus::Cancelled].
+    pub status: Option<OrderStatus>,
+    /// Case-insensitive part of [CompleteOrder::item_name].
+    pub item_name: Option<String>,
+    pub sort: OrderSort,
+    /// Only list orders after this one.
+    pub cursor: Option<OrderCursor>,
+}
+
+impl OrderSearch {
+    /// Whether an order passes the filters.
+    pub fn matches(&self, order: &CompleteOrder) -> bool {
+        self.menu_item_id.is_none_or(|id| order.menu_item_id == id)
+            && self
+                .table_from
+                .is_none_or(|from| order.table_number >= from)
+            && self.table_to.is_none_or(|to| order.table_number <= to)
+            && self
+                .created_from
+                .is_none_or(|from| order.created_at >= from)
+            && self.created_to.is_none_or(|to| order.created_at < to)
+            // Cancelled orders are only found when asked for.
+            && self.status.map_or(order.status != OrderStatus::Cancelled, |status| {
+                order.status == status
+            })
+            && self.item_name.as_ref().is_none_or(|name| {
+                order
+                    .item_name
+                    .to_lowercase()
+                    .contains(&name.to_lowercase())
+            })
+            && self
+                .cursor
+                .is_none_or(|cursor| cursor.precedes(self.sort, order))
+    }
+}
+
+/// A page of [CompleteOrder]s with what's needed to fetch the next one.
+#[derive(Serialize, Debug, Clone)]
+pub struct OrderPage {
+    pub orders: Vec<CompleteOrder>,
+    pub limit: i64,
+    pub has_more: bool,
+    /// Pass it back as `cursor` to get the next page, `None` on the last page.
+    pub next_cursor: Option<String>,
+}
+
+/// A field of a request that failed validation, e.g. a table number outside of the floor plan.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct FieldViolation {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldViolation {
+    pub fn new(field: &str, message: String) -> Self {
+        FieldViolation {
+            field: field.to_string(),
+            message,
+        }
+    }
+
+    /// Prefixes the field with the position of the item in a batch, e.g. `[2].menu_item_id`.
+    pub fn at_index(self, index: usize) -> Self {
+        FieldViolation {
+            field: format!("[{}].{}", index, self.field),
+            message: self.message,
+        }
+    }
 }
 
 impl Order {
@@ -38,17 +647,54 @@ impl Order {
     ///
     /// # Example
     /// ```
+    /// # use restaurant::domain::*;
     /// let order = Order::new(1, 25);
     /// ```
     pub fn new(table_number: i32, menu_item_id: i32) -> Order {
+        Order::with_quantity(table_number, menu_item_id, 1)
+    }
+
+    /// Creates an order of several plates of the same menu item with a random UUID.
+    ///
+    /// # Example
+    /// ```
+    /// # use restaurant::domain::*;
+    /// let order = Order::with_quantity(1, 29, 4);
+    /// ```
+    pub fn with_quantity(table_number: i32, menu_item_id: i32, quantity: i32) -> Order {
         Order {
             id: Uuid::new_v4(),
             table_number,
+            session_id: None,
             menu_item_id,
             created_at: chrono::offset::Utc::now(),
+            quantity,
+            status: OrderStatus::Ordered,
+            cooking_at: None,
+            ready_at: None,
+            served_at: None,
+            cancelled_at: None,
+            cancel_reason: None,
+            cancelled_by: None,
         }
     }
 
+    /// What a client asked for when creating the order, ids and timestamps aside.
+    pub(crate) fn fingerprint(&self) -> String {
+        format!(
+            "{}:{}:{}",
+            self.table_number, self.menu_item_id, self.quantity
+        )
+    }
+
+    /// Marks the order as cancelled now, recording the [Cancellation].
+    pub fn cancel(&mut self, cancellation: &Cancellation, at: DateTime<Utc>) {
+        self.status = OrderStatus::Cancelled;
+        self.cancelled_at = Some(at);
+        self.cancel_reason = cancellation.reason.clone();
+        self.cancelled_by = cancellation.actor.clone();
+    }
+
     /// Creates an order and inserts into the connected database.
     ///
     /// # Examples
@@ -56,74 +702,1207 @@ impl Order {
     /// Basic usage:
     ///
     /// ```
+    /// # use restaurant::domain::*;
+    /// # use restaurant::repository::InMemoryOrderRepository;
+    /// # actix_web::rt::System::new().block_on(async {
+    /// # let repo = InMemoryOrderRepository::new();
     /// let order = Order::new(1, 25);
-    /// let uuid = order.create(&pg_sql_order_repository).await?;
+    /// let uuid = order.create(&repo).await?;
     /// assert_eq!(4, uuid.get_version_num());
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// # }).unwrap();
     /// ```
     pub async fn create<O: OrderRepository>(&self, repo: &O) -> Result<Uuid, O::ErrT> {
-        let uuid = repo.create(&self).await?;
+        let uuid = repo.create(self).await?;
         Ok(uuid)
     }
 }
 
-/// Get all orders from a table number.
+/// Get the [Order::id] created earlier with the same `Idempotency-Key`, if the key was used since `since`.
+///
+/// Fails with [IdempotencyKeyReused] if the key was used for a different order.
 ///
 /// # Examples
 ///
 /// Basic usage:
 ///
 /// ```
-/// let orders: Vec<Order> = read_orders_by_table(&pg_sql_order_repository, table_number).await?;
-/// assert!(orders.len() >= 0);
+/// # use restaurant::domain::*;
+/// # use restaurant::repository::InMemoryOrderRepository;
+/// # actix_web::rt::System::new().block_on(async {
+/// # let repo = InMemoryOrderRepository::new();
+/// # let since = chrono::Utc::now() - chrono::Duration::days(1);
+/// let order = Order::new(1, 25);
+/// let (uuid, _) = create_order_once(&repo, "retry-1", &order, since).await?;
+/// let replayed = replay_order(&repo, "retry-1", &Order::new(1, 25), since).await?;
+/// assert_eq!(Some(uuid), replayed);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// # }).unwrap();
+/// ```
+pub async fn replay_order<O: OrderRepository>(
+    repo: &O,
+    key: &str,
+    order: &Order,
+    since: DateTime<Utc>,
+) -> Result<Option<Uuid>, O::ErrT>
+where
+    O::ErrT: From<IdempotencyKeyReused>,
+{
+    match repo.read_idempotency_key(key, since).await? {
+        Some(record) => Ok(Some(record.replay(order)?)),
+        None => Ok(None),
+    }
+}
+
+/// Creates an order once per `Idempotency-Key`: the key is stored along with the order, in the same transaction.
+///
+/// If a concurrent request with the same key got there first, its [Order::id] is returned and nothing is created.
+/// The [SyncOutcome] is [SyncOutcome::Created] only if this call stored the order.
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// # use restaurant::domain::*;
+/// # use restaurant::repository::InMemoryOrderRepository;
+/// # actix_web::rt::System::new().block_on(async {
+/// # let repo = InMemoryOrderRepository::new();
+/// # let since = chrono::Utc::now() - chrono::Duration::days(1);
+/// let order = Order::new(1, 25);
+/// let (uuid, outcome) = create_order_once(&repo, "retry-1", &order, since).await?;
+/// assert_eq!((order.id, SyncOutcome::Created), (uuid, outcome));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// # }).unwrap();
+/// ```
+pub async fn create_order_once<O: OrderRepository>(
+    repo: &O,
+    key: &str,
+    order: &Order,
+    since: DateTime<Utc>,
+) -> Result<(Uuid, SyncOutcome), O::ErrT>
+where
+    O::ErrT: From<IdempotencyKeyReused>,
+{
+    let record = IdempotencyRecord::new(key, order);
+    let (stored, outcome) = repo.create_once(order, &record, since).await?;
+    Ok((stored.replay(order)?, outcome))
+}
+
+/// Stores an order whose id was chosen by the client, unless the same order is already stored under this id.
+///
+/// Orders are compared as they were first sent, so re-syncing is still a no-op after the order was changed
+/// or a plate cancelled. Fails if a different order was stored under this id.
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// # use restaurant::domain::*;
+/// # use restaurant::repository::InMemoryOrderRepository;
+/// # actix_web::rt::System::new().block_on(async {
+/// # let repo = InMemoryOrderRepository::new();
+/// # let client_id = uuid::Uuid::new_v4();
+/// let order = Order { id: client_id, ..Order::new(1, 25) };
+/// assert_eq!(SyncOutcome::Created, sync_order(&repo, &order).await?);
+/// assert_eq!(SyncOutcome::Unchanged, sync_order(&repo, &order).await?);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// # }).unwrap();
+/// ```
+pub async fn sync_order<O: OrderRepository>(
+    repo: &O,
+    order: &Order,
+) -> Result<SyncOutcome, O::ErrT> {
+    repo.sync(order).await
+}
+
+/// Creates several orders at once and inserts them into the connected database in a single transaction.
+///
+/// If any [Order] is rejected (for example an unknown menu item), nothing is stored. Returns the [SyncOutcome]
+/// of each order, orders already stored under their id are left as they are.
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// # use restaurant::domain::*;
+/// # use restaurant::repository::InMemoryOrderRepository;
+/// # actix_web::rt::System::new().block_on(async {
+/// # let repo = InMemoryOrderRepository::new();
+/// let orders = vec![Order::new(1, 25), Order::with_quantity(1, 29, 4)];
+/// let outcomes = create_orders(&repo, &orders).await?;
+/// assert_eq!(vec![SyncOutcome::Created; 2], outcomes);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// # }).unwrap();
+/// ```
+pub async fn create_orders<O: OrderRepository>(
+    repo: &O,
+    orders: &[Order],
+) -> Result<Vec<SyncOutcome>, O::ErrT> {
+    repo.create_many(orders).await
+}
+
+/// Get all orders of the [TableSession] currently open at a table number, empty if no session is open.
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// # use restaurant::domain::*;
+/// # use restaurant::repository::InMemoryOrderRepository;
+/// # actix_web::rt::System::new().block_on(async {
+/// # let repo = InMemoryOrderRepository::new();
+/// # let table_number = 1;
+/// let orders: Vec<CompleteOrder> = read_orders_by_table(&repo, table_number).await?;
+/// assert!(orders.iter().all(|order| order.table_number == table_number));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// # }).unwrap();
 /// ```
 pub async fn read_orders_by_table<O: OrderRepository>(
     repo: &O,
     table_number: i32,
 ) -> Result<Vec<CompleteOrder>, O::ErrT> {
-    repo.read_orders_by_table(table_number).await
+    let orders = repo.read_orders_by_table(table_number).await?;
+    Ok(orders
+        .into_iter()
+        .map(CompleteOrder::with_estimated_ready_at)
+        .collect())
+}
+
+/// Get a page of at most `limit` orders of a table, filtered and sorted as asked.
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// # use restaurant::domain::*;
+/// # use restaurant::repository::InMemoryOrderRepository;
+/// # actix_web::rt::System::new().block_on(async {
+/// # let repo = InMemoryOrderRepository::new();
+/// # let table_number = 1;
+/// let page = read_orders_page(&repo, table_number, &TableOrdersFilter::default(), 20).await?;
+/// if let Some(cursor) = page.next_cursor {
+///     let filter = TableOrdersFilter { cursor: cursor.parse().ok(), ..Default::default() };
+///     let next = read_orders_page(&repo, table_number, &filter, 20).await?;
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// # }).unwrap();
+/// ```
+pub async fn read_orders_page<O: OrderRepository>(
+    repo: &O,
+    table_number: i32,
+    filter: &TableOrdersFilter,
+    limit: i64,
+) -> Result<OrderPage, O::ErrT> {
+    // One extra order tells whether there's a next page.
+    let orders = repo
+        .read_orders_page(table_number, filter, limit + 1)
+        .await?;
+    Ok(into_page(orders, limit))
+}
+
+/// Find a page of at most `limit` orders across every table, filtered and sorted as asked.
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// # use restaurant::domain::*;
+/// # use restaurant::repository::InMemoryOrderRepository;
+/// # actix_web::rt::System::new().block_on(async {
+/// # let repo = InMemoryOrderRepository::new();
+/// let search = OrderSearch { status: Some(OrderStatus::Ready), item_name: Some("ramen".to_string()), ..Default::default() };
+/// let page = search_orders(&repo, &search, 50).await?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// # }).unwrap();
+/// ```
+pub async fn search_orders<O: OrderRepository>(
+    repo: &O,
+    search: &OrderSearch,
+    limit: i64,
+) -> Result<OrderPage, O::ErrT> {
+    // One extra order tells whether there's a next page.
+    let orders = repo.search(search, limit + 1).await?;
+    Ok(into_page(orders, limit))
+}
+
+/// Keeps the first `limit` orders, the extra one only tells that there's a next page.
+fn into_page(mut orders: Vec<CompleteOrder>, limit: i64) -> OrderPage {
+    let has_more = orders.len() as i64 > limit;
+    orders.truncate(limit as usize);
+    let next_cursor = match has_more {
+        true => orders
+            .last()
+            .map(|order| OrderCursor::from(order).to_string()),
+        false => None,
+    };
+    OrderPage {
+        orders: orders
+            .into_iter()
+            .map(CompleteOrder::with_estimated_ready_at)
+            .collect(),
+        limit,
+        has_more,
+        next_cursor,
+    }
+}
+
+/// Get all orders of a [TableSession], open or closed, to look back at a past party.
+pub async fn read_orders_by_session<O: OrderRepository>(
+    repo: &O,
+    session_id: Uuid,
+) -> Result<Vec<CompleteOrder>, O::ErrT> {
+    let orders = repo.read_orders_by_session(session_id).await?;
+    Ok(orders
+        .into_iter()
+        .map(CompleteOrder::with_estimated_ready_at)
+        .collect())
+}
+
+/// Get how many minutes are left for every order of a table that is still being prepared.
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// # use restaurant::domain::*;
+/// # use restaurant::repository::InMemoryOrderRepository;
+/// # actix_web::rt::System::new().block_on(async {
+/// # let repo = InMemoryOrderRepository::new();
+/// # use chrono::Utc;
+/// # let table_number = 1;
+/// # Order::new(table_number, 3).create(&repo).await?;
+/// let remaining = read_remaining_time_by_table(&repo, table_number, Utc::now()).await?;
+/// assert!(remaining.iter().all(|order| order.remaining_minutes >= 0));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// # }).unwrap();
+/// ```
+pub async fn read_remaining_time_by_table<O: OrderRepository>(
+    repo: &O,
+    table_number: i32,
+    now: DateTime<Utc>,
+) -> Result<Vec<RemainingTime>, O::ErrT> {
+    let orders = read_orders_by_table(repo, table_number).await?;
+    let mut remaining: Vec<RemainingTime> = orders
+        .iter()
+        .filter_map(|order| order.remaining_time(now))
+        .collect();
+    remaining.sort_by_key(|order| order.estimated_ready_at);
+    Ok(remaining)
+}
+
+/// Get every order the kitchen still has to cook or is cooking, oldest first.
+pub async fn read_open_orders<O: OrderRepository>(repo: &O) -> Result<Vec<CompleteOrder>, O::ErrT> {
+    let orders = repo.read_open_orders().await?;
+    Ok(orders
+        .into_iter()
+        .map(CompleteOrder::with_estimated_ready_at)
+        .collect())
 }
 
-/// Get an order from a specific menu item and a specific table number.
+/// Get an order by its id, joined with its [MenuItem].
 ///
 /// # Examples
 ///
 /// Basic usage:
 ///
 /// ```
-/// let order = read_order_item_from_table(&pg_sql_order_repository, menu_item_id, table_num).await?;
+/// # use restaurant::domain::*;
+/// # use restaurant::repository::InMemoryOrderRepository;
+/// # actix_web::rt::System::new().block_on(async {
+/// # let repo = InMemoryOrderRepository::new();
+/// # let order_id = Order::new(1, 3).create(&repo).await?;
+/// let order = read_order_by_id(&repo, order_id).await?;
+/// assert_eq!(order_id, order.unwrap().order_id);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// # }).unwrap();
+/// ```
+pub async fn read_order_by_id<O: OrderRepository>(
+    repo: &O,
+    order_id: Uuid,
+) -> Result<Option<CompleteOrder>, O::ErrT> {
+    let order = repo.read_order_by_id(order_id).await?;
+    Ok(order.map(CompleteOrder::with_estimated_ready_at))
+}
+
+/// Get the latest order of a menu item in the session open at a table that isn't cancelled,
+/// the same one [cancel_order_item_from_table] takes a plate off.
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// # use restaurant::domain::*;
+/// # use restaurant::repository::InMemoryOrderRepository;
+/// # actix_web::rt::System::new().block_on(async {
+/// # let repo = InMemoryOrderRepository::new();
+/// # let (table_num, menu_item_id) = (1, 3);
+/// # Order::new(table_num, menu_item_id).create(&repo).await?;
+/// let key = TableItemKey { table_number: table_num, menu_item_id };
+/// let order = read_order_item_from_table(&repo, key).await?;
 /// assert!(order.is_some());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// # }).unwrap();
 /// ```
 pub async fn read_order_item_from_table<O: OrderRepository>(
     repo: &O,
-    menu_item_id: i32,
-    table_number: i32,
+    key: TableItemKey,
 ) -> Result<Option<CompleteOrder>, O::ErrT> {
-    repo.read_order_item_from_table(menu_item_id, table_number)
-        .await
+    let order = repo.read_order_item_from_table(key).await?;
+    Ok(order.map(CompleteOrder::with_estimated_ready_at))
 }
 
-/// Selects the latest created food item on a specified table and deletes it.
+/// Selects the latest food item on a specified table that isn't cancelled and cancels one plate of it.
+///
+/// The cancelled plate is kept as an order of its own, see [read_cancellations]. Returns `None` if there's no such order,
+/// and fails with [IllegalTransition] if it was already served.
 ///
 /// # Examples
 ///
 /// Basic usage:
 ///
 /// ```
-/// let rows_affected = repo.delete_order_item_from_table(menu_item_id, table_number).await?;
-/// assert!(rows_affected >= 0);
+/// # use restaurant::domain::*;
+/// # use restaurant::repository::InMemoryOrderRepository;
+/// # actix_web::rt::System::new().block_on(async {
+/// # let repo = InMemoryOrderRepository::new();
+/// # let (table_number, menu_item_id) = (1, 3);
+/// # Order::with_quantity(table_number, menu_item_id, 2).create(&repo).await?;
+/// let key = TableItemKey { table_number, menu_item_id };
+/// let cancellation = Cancellation { reason: Some("Allergy".to_string()), actor: Some("waiter-4".to_string()) };
+/// let plate = cancel_order_item_from_table(&repo, key, &cancellation).await?;
+/// assert_eq!(Some(1), plate.map(|plate| plate.quantity));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// # }).unwrap();
 /// ```
-pub async fn delete_order_item_from_table<O: OrderRepository>(
+pub async fn cancel_order_item_from_table<O: OrderRepository>(
     repo: &O,
-    menu_item_id: i32,
-    table_number: i32,
-) -> Result<u64, O::ErrT> {
-    repo.delete_order_item_from_table(menu_item_id, table_number)
-        .await
+    key: TableItemKey,
+    cancellation: &Cancellation,
+) -> Result<Option<CompleteOrder>, O::ErrT>
+where
+    O::ErrT: From<IllegalTransition>,
+{
+    let Some(order) = repo.read_order_item_from_table(key).await? else {
+        return Ok(None);
+    };
+    let from = check_cancellable(order.status)?;
+    // The repository only cancels the plate if the order is still in `from`.
+    match repo
+        .cancel_plate(order.order_id, from, cancellation, Utc::now())
+        .await?
+    {
+        Some(plate) => Ok(Some(plate)),
+        None => Err(IllegalTransition {
+            from,
+            to: OrderStatus::Cancelled,
+        }
+        .into()),
+    }
 }
 
-pub async fn delete_order_by_id<O: OrderRepository>(
+/// Moves an order to another table, swaps its menu item and/or changes its quantity, keeping its original [Order::created_at].
+///
+/// Returns `None` if no order exists with the given id. Only orders still [OrderStatus::Ordered] can change,
+/// others fail with [IllegalTransition], including when another request changed them concurrently.
+/// Orders can't leave or join a closed session, whose bill is final.
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// # use restaurant::domain::*;
+/// # use restaurant::repository::InMemoryOrderRepository;
+/// # actix_web::rt::System::new().block_on(async {
+/// # let repo = InMemoryOrderRepository::new();
+/// # let order_id = Order::new(1, 3).create(&repo).await?;
+/// let patch = OrderPatch { table_number: Some(2), ..Default::default() };
+/// let order = update_order(&repo, order_id, &patch).await?;
+/// assert_eq!(2, order.unwrap().table_number);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// # }).unwrap();
+/// ```
+pub async fn update_order<O: OrderRepository>(
     repo: &O,
     order_id: Uuid,
-) -> Result<u64, O::ErrT> {
-    repo.delete_order_by_id(order_id).await
+    patch: &OrderPatch,
+) -> Result<Option<CompleteOrder>, O::ErrT>
+where
+    O::ErrT: From<IllegalTransition>,
+{
+    let Some(order) = repo.read_order_by_id(order_id).await? else {
+        return Ok(None);
+    };
+    let from = order.status;
+    let not_ordered = IllegalTransition {
+        from,
+        to: OrderStatus::Ordered,
+    };
+    if from != OrderStatus::Ordered {
+        return Err(not_ordered.into());
+    }
+    // The repository only applies the patch if the order is still ordered.
+    match repo.update_order(order_id, patch).await? {
+        Some(order) => Ok(Some(order.with_estimated_ready_at())),
+        None => Err(not_ordered.into()),
+    }
+}
+
+/// Moves an order to the `to` [OrderStatus], recording when the transition happened.
+///
+/// Returns `None` if no order exists with the given id. Fails with [IllegalTransition] if the order
+/// can't reach `to` from its current status, including when another request changed it concurrently.
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// # use restaurant::domain::*;
+/// # use restaurant::repository::InMemoryOrderRepository;
+/// # actix_web::rt::System::new().block_on(async {
+/// # let repo = InMemoryOrderRepository::new();
+/// # let order_id = Order::new(1, 3).create(&repo).await?;
+/// let order = transition_order(&repo, order_id, OrderStatus::Cooking).await?;
+/// assert!(order.unwrap().cooking_at.is_some());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// # }).unwrap();
+/// ```
+pub async fn transition_order<O: OrderRepository>(
+    repo: &O,
+    order_id: Uuid,
+    to: OrderStatus,
+) -> Result<Option<CompleteOrder>, O::ErrT>
+where
+    O::ErrT: From<IllegalTransition>,
+{
+    if to == OrderStatus::Cancelled {
+        return cancel_order(repo, order_id, &Cancellation::default()).await;
+    }
+    let Some(order) = repo.read_order_by_id(order_id).await? else {
+        return Ok(None);
+    };
+    let from = order.status;
+    if !from.can_transition_to(to) {
+        return Err(IllegalTransition { from, to }.into());
+    }
+    // The repository only applies the change if the order is still in `from`.
+    match repo.update_status(order_id, from, to, Utc::now()).await? {
+        Some(order) => Ok(Some(order.with_estimated_ready_at())),
+        None => Err(IllegalTransition { from, to }.into()),
+    }
+}
+
+/// Moves an order to its [OrderStatus::next] status, as chefs do from the kitchen display.
+///
+/// Returns `None` if no order exists with the given id. Fails with [IllegalTransition] if the order
+/// was already served or cancelled, or if another request changed it concurrently.
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// # use restaurant::domain::*;
+/// # use restaurant::repository::InMemoryOrderRepository;
+/// # actix_web::rt::System::new().block_on(async {
+/// # let repo = InMemoryOrderRepository::new();
+/// # let order_id = Order::new(1, 3).create(&repo).await?;
+/// let order = bump_order(&repo, order_id).await?;
+/// assert_eq!(order.unwrap().status, OrderStatus::Cooking);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// # }).unwrap();
+/// ```
+pub async fn bump_order<O: OrderRepository>(
+    repo: &O,
+    order_id: Uuid,
+) -> Result<Option<CompleteOrder>, O::ErrT>
+where
+    O::ErrT: From<IllegalTransition>,
+{
+    let Some(order) = repo.read_order_by_id(order_id).await? else {
+        return Ok(None);
+    };
+    match order.status.next() {
+        Some(to) => transition_order(repo, order_id, to).await,
+        None => Err(IllegalTransition {
+            from: order.status,
+            to: OrderStatus::Served,
+        }
+        .into()),
+    }
+}
+
+/// Cancels every plate of an order, recording why and by whom. The order is kept, see [read_cancellations].
+///
+/// Returns `None` if no order exists with the given id. Fails with [IllegalTransition] if the order
+/// was already served or cancelled, including when another request changed it concurrently.
+pub async fn cancel_order<O: OrderRepository>(
+    repo: &O,
+    order_id: Uuid,
+    cancellation: &Cancellation,
+) -> Result<Option<CompleteOrder>, O::ErrT>
+where
+    O::ErrT: From<IllegalTransition>,
+{
+    let Some(order) = repo.read_order_by_id(order_id).await? else {
+        return Ok(None);
+    };
+    let from = check_cancellable(order.status)?;
+    // The repository only cancels the order if it's still in `from`.
+    match repo
+        .cancel_order(order_id, from, cancellation, Utc::now())
+        .await?
+    {
+        Some(order) => Ok(Some(order.with_estimated_ready_at())),
+        None => Err(IllegalTransition {
+            from,
+            to: OrderStatus::Cancelled,
+        }
+        .into()),
+    }
+}
+
+/// Returns `from` if an order in this status can be cancelled.
+fn check_cancellable(from: OrderStatus) -> Result<OrderStatus, IllegalTransition> {
+    match from.can_transition_to(OrderStatus::Cancelled) {
+        true => Ok(from),
+        false => Err(IllegalTransition {
+            from,
+            to: OrderStatus::Cancelled,
+        }),
+    }
+}
+
+/// Get the cancelled orders with their reasons, the latest cancelled first.
+pub async fn read_cancellations<O: OrderRepository>(
+    repo: &O,
+    filter: &CancellationFilter,
+) -> Result<Vec<CompleteOrder>, O::ErrT> {
+    repo.read_cancellations(filter).await
+}
+
+/// Get the menu. Retired [MenuItem]s are only listed when `include_retired` is set.
+pub async fn read_menu_items<M: MenuRepository>(
+    repo: &M,
+    include_retired: bool,
+) -> Result<Vec<MenuItem>, M::ErrT> {
+    repo.read_menu_items(include_retired).await
+}
+
+/// Get a single [MenuItem], retired or not.
+pub async fn read_menu_item<M: MenuRepository>(
+    repo: &M,
+    menu_item_id: i32,
+) -> Result<Option<MenuItem>, M::ErrT> {
+    repo.read_menu_item(menu_item_id).await
+}
+
+/// Adds a new item to the menu.
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// # use restaurant::domain::*;
+/// # use restaurant::repository::InMemoryOrderRepository;
+/// # actix_web::rt::System::new().block_on(async {
+/// # let pg_sql_menu_repository = InMemoryOrderRepository::new();
+/// let item = NewMenuItem { item_name: "Katsu Curry".to_string(), cooking_time: 12, price: 1200 };
+/// let item = create_menu_item(&pg_sql_menu_repository, &item).await?;
+/// assert!(item.retired_at.is_none());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// # }).unwrap();
+/// ```
+pub async fn create_menu_item<M: MenuRepository>(
+    repo: &M,
+    item: &NewMenuItem,
+) -> Result<MenuItem, M::ErrT> {
+    repo.create_menu_item(item).await
+}
+
+/// Renames, reprices or changes the cooking time of a [MenuItem]. Returns `None` if it doesn't exist.
+pub async fn update_menu_item<M: MenuRepository>(
+    repo: &M,
+    menu_item_id: i32,
+    patch: &MenuItemPatch,
+) -> Result<Option<MenuItem>, M::ErrT> {
+    repo.update_menu_item(menu_item_id, patch).await
+}
+
+/// Retires a [MenuItem] from the menu so it can't be ordered anymore. Returns `None` if it doesn't exist.
+pub async fn retire_menu_item<M: MenuRepository>(
+    repo: &M,
+    menu_item_id: i32,
+) -> Result<Option<MenuItem>, M::ErrT> {
+    repo.retire_menu_item(menu_item_id).await
+}
+
+/// Get the floor plan, every [RestaurantTable] ordered by table number.
+pub async fn read_tables<T: TableRepository>(repo: &T) -> Result<Vec<RestaurantTable>, T::ErrT> {
+    repo.read_tables().await
+}
+
+/// Get a single [RestaurantTable] by its table number.
+pub async fn read_table<T: TableRepository>(
+    repo: &T,
+    table_number: i32,
+) -> Result<Option<RestaurantTable>, T::ErrT> {
+    repo.read_table(table_number).await
+}
+
+/// Adds a [RestaurantTable] to the floor plan, or changes its seats and section if it's already there.
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// # use restaurant::domain::*;
+/// # use restaurant::repository::InMemoryOrderRepository;
+/// # actix_web::rt::System::new().block_on(async {
+/// # let pg_sql_table_repository = InMemoryOrderRepository::new();
+/// let table = RestaurantTable { table_number: 12, seats: 6, section: "terrace".to_string() };
+/// let table = define_table(&pg_sql_table_repository, &table).await?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// # }).unwrap();
+/// ```
+pub async fn define_table<T: TableRepository>(
+    repo: &T,
+    table: &RestaurantTable,
+) -> Result<RestaurantTable, T::ErrT> {
+    repo.upsert_table(table).await
+}
+
+/// Removes a [RestaurantTable] from the floor plan. Tables with [Order]s can't be removed.
+pub async fn remove_table<T: TableRepository>(repo: &T, table_number: i32) -> Result<u64, T::ErrT> {
+    repo.delete_table(table_number).await
+}
+
+/// Seats a party at a table: opens a [TableSession], or sets the party size of the session already open.
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// # use restaurant::domain::*;
+/// # use restaurant::repository::InMemoryOrderRepository;
+/// # actix_web::rt::System::new().block_on(async {
+/// # let pg_sql_table_repository = InMemoryOrderRepository::new();
+/// # use chrono::Utc;
+/// let session = open_table_session(&pg_sql_table_repository, 5, 4, Utc::now()).await?;
+/// assert_eq!(Some(4), session.party_size);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// # }).unwrap();
+/// ```
+pub async fn open_table_session<T: TableRepository>(
+    repo: &T,
+    table_number: i32,
+    party_size: i32,
+    at: DateTime<Utc>,
+) -> Result<TableSession, T::ErrT> {
+    repo.open_session(table_number, party_size, at).await
+}
+
+/// Get the [TableSession] open at a table, if any.
+pub async fn read_current_session<T: TableRepository>(
+    repo: &T,
+    table_number: i32,
+) -> Result<Option<TableSession>, T::ErrT> {
+    repo.read_current_session(table_number).await
+}
+
+/// Get every [TableSession] of a table, the latest first.
+pub async fn read_table_sessions<T: TableRepository>(
+    repo: &T,
+    table_number: i32,
+) -> Result<Vec<TableSession>, T::ErrT> {
+    repo.read_sessions(table_number).await
+}
+
+/// Closes the [TableSession] open at a table when the guests leave, clearing the table for the next party.
+///
+/// Returns `None` if no session is open. Refused with [PaymentError::NotCovered] while its bill isn't paid,
+/// like [settle_table]. Its [Order]s stay queryable with [read_orders_by_session].
+pub async fn close_table_session<T: TableRepository>(
+    tables: &T,
+    table_number: i32,
+    rates: BillingRates,
+    at: DateTime<Utc>,
+) -> Result<Option<TableSession>, T::ErrT> {
+    let Some(session) = tables.read_current_session(table_number).await? else {
+        return Ok(None);
+    };
+    // The repository checks the bill while no payment or order can reach the session.
+    tables.close_session(session.id, rates, at).await
+}
+
+/// Checks the page size and created-at range of an order listing.
+pub fn validate_order_listing(
+    limit: i64,
+    created_from: Option<DateTime<Utc>>,
+    created_to: Option<DateTime<Utc>>,
+) -> Vec<FieldViolation> {
+    let mut violations = vec![];
+    if !(1..=MAX_PAGE_LIMIT).contains(&limit) {
+        violations.push(FieldViolation::new(
+            "limit",
+            format!(
+                "Limit must be between 1 and {}, got {}.",
+                MAX_PAGE_LIMIT, limit
+            ),
+        ));
+    }
+    if let (Some(from), Some(to)) = (created_from, created_to) {
+        if from >= to {
+            violations.push(FieldViolation::new(
+                "created_to",
+                "created_to must be after created_from.".to_string(),
+            ));
+        }
+    }
+    violations
+}
+
+/// Checks an [OrderSearch] and its page size: the same rules as an order listing, and an ordered table range.
+pub fn validate_order_search(search: &OrderSearch, limit: i64) -> Vec<FieldViolation> {
+    let mut violations = validate_order_listing(limit, search.created_from, search.created_to);
+    if let (Some(from), Some(to)) = (search.table_from, search.table_to) {
+        if from > to {
+            violations.push(FieldViolation::new(
+                "table_to",
+                format!(
+                    "table_to must be at least table_from, got {} and {}.",
+                    from, to
+                ),
+            ));
+        }
+    }
+    if search
+        .item_name
+        .as_ref()
+        .is_some_and(|name| name.trim().is_empty())
+    {
+        violations.push(FieldViolation::new(
+            "item_name",
+            "Item name can't be empty.".to_string(),
+        ));
+    }
+    violations
+}
+
+/// Checks the reason and actor of a [Cancellation]: when given, they can't be blank or too long.
+pub fn validate_cancellation(cancellation: &Cancellation) -> Vec<FieldViolation> {
+    [
+        ("reason", &cancellation.reason),
+        ("actor", &cancellation.actor),
+    ]
+    .into_iter()
+    .filter_map(|(field, value)| {
+        let value = value.as_deref()?;
+        if value.trim().is_empty() {
+            Some(FieldViolation::new(
+                field,
+                format!("{} can't be blank.", field),
+            ))
+        } else if value.chars().count() > MAX_CANCELLATION_TEXT_LENGTH {
+            Some(FieldViolation::new(
+                field,
+                format!(
+                    "{} can't be longer than {} characters.",
+                    field, MAX_CANCELLATION_TEXT_LENGTH
+                ),
+            ))
+        } else {
+            None
+        }
+    })
+    .collect()
+}
+
+/// Checks an `Idempotency-Key` header: it can't be blank or too long.
+pub fn validate_idempotency_key(key: &str) -> Vec<FieldViolation> {
+    if key.trim().is_empty() {
+        vec![FieldViolation::new(
+            "Idempotency-Key",
+            "Idempotency key can't be blank.".to_string(),
+        )]
+    } else if key.len() > MAX_IDEMPOTENCY_KEY_LENGTH {
+        vec![FieldViolation::new(
+            "Idempotency-Key",
+            format!(
+                "Idempotency key can't be longer than {} characters.",
+                MAX_IDEMPOTENCY_KEY_LENGTH
+            ),
+        )]
+    } else {
+        vec![]
+    }
+}
+
+/// Checks the party size before seating guests.
+pub fn validate_party_size(party_size: i32) -> Vec<FieldViolation> {
+    if party_size < 1 {
+        vec![FieldViolation::new(
+            "party_size",
+            format!("Party size must be at least 1, got {}.", party_size),
+        )]
+    } else {
+        vec![]
+    }
+}
+
+/// Checks a [RestaurantTable] before it's added to the floor plan: its number must be within
+/// `table_numbers`, it needs at least one seat and a section.
+pub fn validate_table(
+    table_numbers: &RangeInclusive<i32>,
+    table: &RestaurantTable,
+) -> Vec<FieldViolation> {
+    let mut violations = vec![];
+    if !table_numbers.contains(&table.table_number) {
+        violations.push(out_of_floor_plan(table_numbers, table.table_number));
+    }
+    if table.seats < 1 {
+        violations.push(FieldViolation::new(
+            "seats",
+            format!("A table needs at least 1 seat, got {}.", table.seats),
+        ));
+    }
+    if table.section.trim().is_empty() {
+        violations.push(FieldViolation::new(
+            "section",
+            "Section can't be empty.".to_string(),
+        ));
+    }
+    violations
+}
+
+/// Checks the fields of a [MenuItem] before it's added or changed: a name, a positive cooking time
+/// and a price that isn't negative. Fields left unset aren't checked.
+pub fn validate_menu_item(fields: &MenuItemPatch) -> Vec<FieldViolation> {
+    let mut violations = vec![];
+    if fields
+        .item_name
+        .as_ref()
+        .is_some_and(|name| name.trim().is_empty())
+    {
+        violations.push(FieldViolation::new(
+            "item_name",
+            "Item name can't be empty.".to_string(),
+        ));
+    }
+    if let Some(cooking_time) = fields.cooking_time.filter(|minutes| *minutes < 1) {
+        violations.push(FieldViolation::new(
+            "cooking_time",
+            format!(
+                "Cooking time must be at least 1 minute, got {}.",
+                cooking_time
+            ),
+        ));
+    }
+    if let Some(price) = fields.price.filter(|price| *price < 0) {
+        violations.push(FieldViolation::new(
+            "price",
+            format!("Price can't be negative, got {}.", price),
+        ));
+    }
+    violations
+}
+
+fn out_of_floor_plan(table_numbers: &RangeInclusive<i32>, table_number: i32) -> FieldViolation {
+    FieldViolation::new(
+        "table_number",
+        format!(
+            "Table {} is out of the floor plan, tables go from {} to {}.",
+            table_number,
+            table_numbers.start(),
+            table_numbers.end()
+        ),
+    )
+}
+
+/// Checks the fields set on an [OrderPatch] before anything is written: the table number must be within
+/// `table_numbers` and a [RestaurantTable] of the floor plan, the quantity at least one plate and the
+/// menu item an active [MenuItem].
+///
+/// Returns every violation found, an empty list means the order can be written.
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// # use restaurant::domain::*;
+/// # use restaurant::repository::InMemoryOrderRepository;
+/// # actix_web::rt::System::new().block_on(async {
+/// # let menu_repo = InMemoryOrderRepository::new();
+/// # let table_repo = menu_repo.clone();
+/// let order = Order::with_quantity(0, 4, 2);
+/// let fields = OrderPatch::from(&order);
+/// let violations = validate_order(&menu_repo, &table_repo, &(1..=500), &fields).await?;
+/// assert_eq!("table_number", violations[0].field);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// # }).unwrap();
+/// ```
+pub async fn validate_order<M, T>(
+    menu_repo: &M,
+    table_repo: &T,
+    table_numbers: &RangeInclusive<i32>,
+    fields: &OrderPatch,
+) -> Result<Vec<FieldViolation>, M::ErrT>
+where
+    M: MenuRepository,
+    T: TableRepository<ErrT = M::ErrT>,
+{
+    let mut violations = vec![];
+    if let Some(table_number) = fields.table_number {
+        if !table_numbers.contains(&table_number) {
+            violations.push(out_of_floor_plan(table_numbers, table_number));
+        } else if table_repo.read_table(table_number).await?.is_none() {
+            violations.push(FieldViolation::new(
+                "table_number",
+                format!("Table {} isn't on the floor plan.", table_number),
+            ));
+        }
+    }
+    if let Some(quantity) = fields.quantity {
+        if quantity < 1 {
+            violations.push(FieldViolation::new(
+                "quantity",
+                format!("Quantity must be at least 1, got {}.", quantity),
+            ));
+        }
+    }
+    if let Some(menu_item_id) = fields.menu_item_id {
+        let orderable = menu_repo
+            .read_menu_item(menu_item_id)
+            .await?
+            .is_some_and(|item| item.retired_at.is_none());
+        if !orderable {
+            violations.push(FieldViolation::new(
+                "menu_item_id",
+                format!("Menu item {} isn't on the menu.", menu_item_id),
+            ));
+        }
+    }
+    Ok(violations)
+}
+
+/// Finds the session of a table: the open one, or a past one by id.
+async fn find_session<T: TableRepository>(
+    tables: &T,
+    table_number: i32,
+    session_id: Option<Uuid>,
+) -> Result<Option<TableSession>, T::ErrT> {
+    match session_id {
+        Some(session_id) => Ok(tables
+            .read_sessions(table_number)
+            .await?
+            .into_iter()
+            .find(|session| session.id == session_id)),
+        None => tables.read_current_session(table_number).await,
+    }
+}
+
+/// Computes the [Balance] of a session: its bill, built from the orders joined with their menu items, against its payments.
+async fn session_balance<O, P>(
+    orders: &O,
+    payments: &P,
+    session: &TableSession,
+    rates: BillingRates,
+) -> Result<(Balance, Vec<Payment>), P::ErrT>
+where
+    O: OrderRepository<ErrT = P::ErrT>,
+    P: PaymentRepository,
+{
+    let session_orders = read_orders_by_session(orders, session.id).await?;
+    let bill = build_bill(session.table_number, &session_orders, rates, None);
+    let session_payments = payments.read_payments(session.id).await?;
+    Ok((balance(&bill, &session_payments), session_payments))
+}
+
+/// Get the [Payment]s of the session open at a table, or of a past session, with what's left to pay.
+///
+/// Returns `None` if the table has no such session.
+pub async fn read_session_payments<O, T, P>(
+    orders: &O,
+    tables: &T,
+    payments: &P,
+    table_number: i32,
+    session_id: Option<Uuid>,
+    rates: BillingRates,
+) -> Result<Option<SessionPayments>, P::ErrT>
+where
+    O: OrderRepository<ErrT = P::ErrT>,
+    T: TableRepository<ErrT = P::ErrT>,
+    P: PaymentRepository,
+{
+    let Some(session) = find_session(tables, table_number, session_id).await? else {
+        return Ok(None);
+    };
+    let (balance, payments) = session_balance(orders, payments, &session, rates).await?;
+    Ok(Some(SessionPayments {
+        session_id: session.id,
+        balance,
+        payments,
+    }))
+}
+
+/// Records a payment of `amount` handed over with `tender` for the session open at a table.
+///
+/// Partial payments are fine. Cash beyond what's due gives change, vouchers are only applied up to what's due,
+/// and cards can't exceed it.
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// # use restaurant::domain::*;
+/// # use restaurant::repository::InMemoryOrderRepository;
+/// # actix_web::rt::System::new().block_on(async {
+/// # let orders = InMemoryOrderRepository::new();
+/// # let (tables, payments) = (orders.clone(), orders.clone());
+/// # let rates = restaurant::config::Config::default().billing_rates();
+/// # Order::new(5, 3).create(&orders).await?;
+/// let payment = record_payment(&orders, &tables, &payments, 5, Tender::Cash, 5000, rates).await?;
+/// assert_eq!(5000, payment.amount + payment.change);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// # }).unwrap();
+/// ```
+pub async fn record_payment<O, T, P>(
+    orders: &O,
+    tables: &T,
+    payments: &P,
+    table_number: i32,
+    tender: Tender,
+    amount: i64,
+    rates: BillingRates,
+) -> Result<Payment, P::ErrT>
+where
+    O: OrderRepository<ErrT = P::ErrT>,
+    T: TableRepository<ErrT = P::ErrT>,
+    P: PaymentRepository,
+    P::ErrT: From<PaymentError>,
+{
+    let Some(session) = tables.read_current_session(table_number).await? else {
+        return Err(PaymentError::NoOpenSession(table_number).into());
+    };
+    loop {
+        let (balance, recorded) = session_balance(orders, payments, &session, rates).await?;
+        let (applied, change) = tender_payment(balance.due, tender, amount)?;
+        let payment = Payment {
+            id: Uuid::new_v4(),
+            session_id: session.id,
+            kind: PaymentKind::Payment,
+            tender,
+            amount: applied,
+            tendered: amount,
+            change,
+            refund_of: None,
+            created_at: Utc::now(),
+        };
+        // Another payment was recorded in the meantime, check against what's due now.
+        if let Some(payment) = payments.create_payment(&payment, recorded.len()).await? {
+            return Ok(payment);
+        }
+    }
+}
+
+/// Gives back `amount` of a [Payment] with the same tender. Returns `None` if the payment doesn't exist.
+///
+/// Several refunds of the same payment can't give back more than its amount in total, and the bill
+/// of a closed session can't be refunded.
+pub async fn refund_payment<P>(
+    payments: &P,
+    payment_id: Uuid,
+    amount: i64,
+) -> Result<Option<Payment>, P::ErrT>
+where
+    P: PaymentRepository,
+    P::ErrT: From<PaymentError>,
+{
+    let Some(payment) = payments.read_payment(payment_id).await? else {
+        return Ok(None);
+    };
+    loop {
+        let recorded = payments.read_payments(payment.session_id).await?;
+        let refundable = match payment.kind {
+            PaymentKind::Payment => {
+                let refunded: i64 = recorded
+                    .iter()
+                    .filter(|refund| refund.refund_of == Some(payment.id))
+                    .map(|refund| refund.amount)
+                    .sum();
+                payment.amount - refunded
+            }
+            PaymentKind::Refund => 0,
+        };
+        if amount > refundable {
+            return Err(PaymentError::RefundExceedsPayment { refundable, amount }.into());
+        }
+        let refund = Payment {
+            id: Uuid::new_v4(),
+            session_id: payment.session_id,
+            kind: PaymentKind::Refund,
+            tender: payment.tender,
+            amount,
+            tendered: amount,
+            change: 0,
+            refund_of: Some(payment.id),
+            created_at: Utc::now(),
+        };
+        // Another refund may have been recorded in the meantime, check against what's left now.
+        if let Some(refund) = payments.create_payment(&refund, recorded.len()).await? {
+            return Ok(Some(refund));
+        }
+    }
+}
+
+/// Settles the bill of the session open at a table, closing the session.
+///
+/// Refused with [PaymentError::NotCovered] while the recorded payments don't cover the bill total.
+pub async fn settle_table<T, P>(
+    tables: &T,
+    payments: &P,
+    table_number: i32,
+    rates: BillingRates,
+) -> Result<TableSession, P::ErrT>
+where
+    T: TableRepository<ErrT = P::ErrT>,
+    P: PaymentRepository,
+    P::ErrT: From<PaymentError>,
+{
+    let not_open = || PaymentError::NoOpenSession(table_number).into();
+    let Some(session) = tables.read_current_session(table_number).await? else {
+        return Err(not_open());
+    };
+    // The repository checks the bill while no payment or order can reach the session,
+    // and someone else may have closed it in the meantime.
+    payments
+        .settle_session(session.id, rates, Utc::now())
+        .await?
+        .ok_or_else(not_open)
+}
+
+/// Checks the amount of a payment or refund.
+pub fn validate_amount(amount: i64) -> Vec<FieldViolation> {
+    if amount < 1 {
+        vec![FieldViolation::new(
+            "amount",
+            format!("Amount must be at least 1, got {}.", amount),
+        )]
+    } else {
+        vec![]
+    }
 }
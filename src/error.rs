@@ -0,0 +1,253 @@
+use crate::domain::{FieldViolation, IdempotencyKeyReused, IllegalTransition, PaymentError};
+use crate::repository::RepositoryError;
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use log::{debug, error};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fmt;
+use uuid::Uuid;
+
+/// SQLSTATE raised by PostgreSQL when a foreign key doesn't reference an existing row.
+const FOREIGN_KEY_VIOLATION: &str = "23503";
+/// SQLSTATE raised by PostgreSQL when a unique constraint is violated.
+const UNIQUE_VIOLATION: &str = "23505";
+
+/// Error returned by the HTTP handlers. Every variant is rendered as an [ErrorBody] with a stable `code`.
+#[derive(Debug)]
+pub enum AppError {
+    /// The request is well formed JSON but can't be processed as is, e.g. an empty batch of orders.
+    BadRequest(String),
+    /// Some fields of the request are invalid, every violation is listed in the details.
+    Validation(Vec<FieldViolation>),
+    /// The requested resource doesn't exist.
+    NotFound(String),
+    /// The menu item of an order doesn't exist or was retired.
+    MenuItemNotFound(i32),
+    /// The table of an order isn't on the floor plan.
+    TableNotFound(i32),
+    /// The table still has orders, so it can't be removed from the floor plan.
+    TableInUse(i32),
+    /// The quantity of an order isn't a positive number of plates.
+    InvalidQuantity(i32),
+    /// A different order was already created with the id chosen by the client.
+    OrderIdConflict(Uuid),
+    /// The order can't move between these statuses.
+    IllegalTransition(IllegalTransition),
+    /// The `Idempotency-Key` was already used to create a different order.
+    IdempotencyKeyReused(IdempotencyKeyReused),
+    /// A payment, refund or settlement is refused because of the bill of the session.
+    Payment(PaymentError),
+    /// The session at this table is closed, so its bill can't change anymore.
+    SessionClosed(i32),
+    /// A row references another one that doesn't exist (SQLSTATE 23503).
+    ForeignKeyViolation { constraint: Option<String> },
+    /// A row with the same unique key already exists (SQLSTATE 23505).
+    UniqueViolation { constraint: Option<String> },
+    /// No database connection could be acquired in time.
+    ServiceUnavailable,
+    /// Anything else, the details are only logged.
+    Internal(String),
+}
+
+/// JSON body of every error response, clients can switch on [ErrorBody::code].
+#[derive(Serialize)]
+pub struct ErrorBody {
+    pub code: &'static str,
+    pub message: String,
+    pub details: Value,
+}
+
+impl AppError {
+    /// Machine readable identifier of the error, kept stable across releases.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::BadRequest(_) => "bad_request",
+            AppError::Validation(_) => "validation_failed",
+            AppError::NotFound(_) => "not_found",
+            AppError::MenuItemNotFound(_) => "menu_item_not_found",
+            AppError::TableNotFound(_) => "table_not_found",
+            AppError::TableInUse(_) => "table_in_use",
+            AppError::InvalidQuantity(_) => "invalid_quantity",
+            AppError::OrderIdConflict(_) => "order_id_conflict",
+            AppError::IllegalTransition(_) => "illegal_transition",
+            AppError::IdempotencyKeyReused(_) => "idempotency_key_reused",
+            AppError::Payment(error) => match error {
+                PaymentError::NoOpenSession(_) => "no_open_session",
+                PaymentError::NothingDue => "nothing_due",
+                PaymentError::Overpayment { .. } => "overpayment",
+                PaymentError::RefundExceedsPayment { .. } => "refund_exceeds_payment",
+                PaymentError::NotCovered { .. } => "bill_not_covered",
+            },
+            AppError::SessionClosed(_) => "session_closed",
+            AppError::ForeignKeyViolation { .. } => "foreign_key_violation",
+            AppError::UniqueViolation { .. } => "unique_violation",
+            AppError::ServiceUnavailable => "service_unavailable",
+            AppError::Internal(_) => "internal_error",
+        }
+    }
+
+    /// The [ErrorBody] rendered for this error, also used for the items of a batch that fail on their own.
+    pub fn body(&self) -> ErrorBody {
+        ErrorBody {
+            code: self.code(),
+            message: self.to_string(),
+            details: self.details(),
+        }
+    }
+
+    fn details(&self) -> Value {
+        match self {
+            AppError::Validation(violations) => json!({ "violations": violations }),
+            AppError::MenuItemNotFound(menu_item_id) => json!({ "menu_item_id": menu_item_id }),
+            AppError::TableNotFound(table_number)
+            | AppError::TableInUse(table_number)
+            | AppError::SessionClosed(table_number) => {
+                json!({ "table_number": table_number })
+            }
+            AppError::InvalidQuantity(quantity) => json!({ "quantity": quantity }),
+            AppError::OrderIdConflict(order_id) => json!({ "order_id": order_id }),
+            AppError::IllegalTransition(IllegalTransition { from, to }) => {
+                json!({ "from": from, "to": to })
+            }
+            AppError::IdempotencyKeyReused(IdempotencyKeyReused { key }) => json!({ "key": key }),
+            AppError::Payment(error) => match error {
+                PaymentError::NoOpenSession(table_number) => {
+                    json!({ "table_number": table_number })
+                }
+                PaymentError::NothingDue => Value::Null,
+                PaymentError::Overpayment { due, amount } => {
+                    json!({ "due": due, "amount": amount })
+                }
+                PaymentError::RefundExceedsPayment { refundable, amount } => {
+                    json!({ "refundable": refundable, "amount": amount })
+                }
+                PaymentError::NotCovered { total, paid } => {
+                    json!({ "total": total, "paid": paid })
+                }
+            },
+            AppError::ForeignKeyViolation { constraint }
+            | AppError::UniqueViolation { constraint } => json!({ "constraint": constraint }),
+            _ => Value::Null,
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::BadRequest(message) | AppError::NotFound(message) => write!(f, "{}", message),
+            AppError::Validation(violations) => {
+                write!(f, "The request has {} invalid field(s).", violations.len())
+            }
+            AppError::MenuItemNotFound(id) => write!(f, "Menu item {} doesn't exist.", id),
+            AppError::TableNotFound(number) => {
+                write!(f, "Table {} isn't on the floor plan.", number)
+            }
+            AppError::TableInUse(number) => write!(f, "Table {} still has orders.", number),
+            AppError::InvalidQuantity(quantity) => {
+                write!(f, "Quantity must be at least 1, got {}.", quantity)
+            }
+            AppError::OrderIdConflict(order_id) => write!(
+                f,
+                "Order {} already exists with another table, menu item or quantity.",
+                order_id
+            ),
+            AppError::IllegalTransition(IllegalTransition { from, to }) => {
+                write!(f, "Order can't go from {} to {}.", from, to)
+            }
+            AppError::IdempotencyKeyReused(IdempotencyKeyReused { key }) => {
+                write!(f, "Idempotency key '{}' was used for another order.", key)
+            }
+            AppError::Payment(error) => write!(f, "{}", error),
+            AppError::SessionClosed(number) => {
+                write!(f, "The session at table {} is closed.", number)
+            }
+            AppError::ForeignKeyViolation { .. } => {
+                write!(f, "A referenced resource doesn't exist.")
+            }
+            AppError::UniqueViolation { .. } => write!(f, "The resource already exists."),
+            AppError::ServiceUnavailable => {
+                write!(f, "The service is temporarily unavailable, please retry.")
+            }
+            // Don't leak driver messages to clients.
+            AppError::Internal(_) => write!(f, "Internal server error."),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<sqlx::Error> for AppError {
+    /// Classifies driver errors by SQLSTATE, so only unexpected failures end up as 500s.
+    fn from(error: sqlx::Error) -> Self {
+        match &error {
+            sqlx::Error::RowNotFound => AppError::NotFound("No row found.".to_string()),
+            sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed => AppError::ServiceUnavailable,
+            sqlx::Error::Database(db_error) => {
+                let constraint = db_error.constraint().map(str::to_string);
+                match db_error.code().as_deref() {
+                    Some(FOREIGN_KEY_VIOLATION) => AppError::ForeignKeyViolation { constraint },
+                    Some(UNIQUE_VIOLATION) => AppError::UniqueViolation { constraint },
+                    _ => AppError::Internal(error.to_string()),
+                }
+            }
+            _ => AppError::Internal(error.to_string()),
+        }
+    }
+}
+
+impl From<RepositoryError> for AppError {
+    fn from(error: RepositoryError) -> Self {
+        match error {
+            RepositoryError::MenuItemNotFound(id) => AppError::MenuItemNotFound(id),
+            RepositoryError::TableNotFound(number) => AppError::TableNotFound(number),
+            RepositoryError::TableInUse(number) => AppError::TableInUse(number),
+            RepositoryError::InvalidQuantity(quantity) => AppError::InvalidQuantity(quantity),
+            RepositoryError::OrderIdConflict(order_id) => AppError::OrderIdConflict(order_id),
+            RepositoryError::IllegalTransition(transition) => {
+                AppError::IllegalTransition(transition)
+            }
+            RepositoryError::IdempotencyKeyReused(error) => AppError::IdempotencyKeyReused(error),
+            RepositoryError::Payment(error) => AppError::Payment(error),
+            RepositoryError::SessionClosed(number) => AppError::SessionClosed(number),
+            RepositoryError::Database(error) => error.into(),
+        }
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::BadRequest(_) | AppError::Validation(_) => StatusCode::BAD_REQUEST,
+            AppError::NotFound(_) | AppError::Payment(PaymentError::NoOpenSession(_)) => {
+                StatusCode::NOT_FOUND
+            }
+            AppError::MenuItemNotFound(_)
+            | AppError::TableNotFound(_)
+            | AppError::InvalidQuantity(_)
+            | AppError::IdempotencyKeyReused(_)
+            | AppError::Payment(PaymentError::Overpayment { .. })
+            | AppError::Payment(PaymentError::RefundExceedsPayment { .. })
+            | AppError::ForeignKeyViolation { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::IllegalTransition(_)
+            | AppError::OrderIdConflict(_)
+            | AppError::TableInUse(_)
+            | AppError::SessionClosed(_)
+            | AppError::Payment(PaymentError::NothingDue)
+            | AppError::Payment(PaymentError::NotCovered { .. })
+            | AppError::UniqueViolation { .. } => StatusCode::CONFLICT,
+            AppError::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        if status.is_server_error() {
+            error!("{:?}", self);
+        } else {
+            debug!("{:?}", self);
+        }
+        HttpResponse::build(status).json(self.body())
+    }
+}
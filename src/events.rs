@@ -0,0 +1,253 @@
+use crate::constants::{EVENT_HISTORY_SIZE, EVENT_KEEP_ALIVE_SECS, EVENT_SUBSCRIBER_BUFFER};
+use crate::domain::{CompleteOrder, Order, OrderStatus};
+use actix_web::rt::time::timeout;
+use chrono::{DateTime, Utc};
+use futures::channel::mpsc;
+use futures::{stream, Stream, StreamExt};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// What happened to an [Order], also the SSE `event` name.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+// Variants are named like the events clients receive.
+#[allow(clippy::enum_variant_names)]
+pub enum OrderEventKind {
+    OrderCreated,
+    OrderCancelled,
+    OrderStatusChanged,
+    OrderUpdated,
+}
+
+impl OrderEventKind {
+    fn name(&self) -> &'static str {
+        match self {
+            OrderEventKind::OrderCreated => "order_created",
+            OrderEventKind::OrderCancelled => "order_cancelled",
+            OrderEventKind::OrderStatusChanged => "order_status_changed",
+            OrderEventKind::OrderUpdated => "order_updated",
+        }
+    }
+}
+
+/// A change of an [Order], as streamed to kitchen displays.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct OrderEvent {
+    /// Assigned by the [EventBus] in publishing order, clients resume after the last one they got.
+    pub id: u64,
+    pub kind: OrderEventKind,
+    pub order_id: Uuid,
+    pub table_number: i32,
+    pub menu_item_id: i32,
+    pub quantity: i32,
+    pub status: OrderStatus,
+    pub at: DateTime<Utc>,
+}
+
+impl OrderEvent {
+    /// An [Order] was placed. The id is assigned when published.
+    pub fn created(order: &Order) -> Self {
+        OrderEvent {
+            id: 0,
+            kind: OrderEventKind::OrderCreated,
+            order_id: order.id,
+            table_number: order.table_number,
+            menu_item_id: order.menu_item_id,
+            quantity: order.quantity,
+            status: order.status,
+            at: order.created_at,
+        }
+    }
+
+    /// An order was cancelled, or one plate of it split off as a cancelled order. The id is assigned when published.
+    pub fn cancelled(order: &CompleteOrder) -> Self {
+        let at = order.cancelled_at.unwrap_or_else(Utc::now);
+        OrderEvent::of(OrderEventKind::OrderCancelled, order, at)
+    }
+
+    /// An order moved through the kitchen, e.g. bumped from cooking to ready. The id is assigned when published.
+    pub fn status_changed(order: &CompleteOrder) -> Self {
+        let at = match order.status {
+            OrderStatus::Cooking => order.cooking_at,
+            OrderStatus::Ready => order.ready_at,
+            OrderStatus::Served => order.served_at,
+            OrderStatus::Ordered | OrderStatus::Cancelled => None,
+        };
+        OrderEvent::of(
+            OrderEventKind::OrderStatusChanged,
+            order,
+            at.unwrap_or_else(Utc::now),
+        )
+    }
+
+    /// An order was moved to another table, or its menu item or quantity changed. The id is assigned when published.
+    pub fn updated(order: &CompleteOrder) -> Self {
+        OrderEvent::of(OrderEventKind::OrderUpdated, order, Utc::now())
+    }
+
+    /// The latest change of an order since it was `previous`, told by its current status when that changed
+    /// or the order wasn't known. The id is assigned when published.
+    pub fn latest(order: &CompleteOrder, previous: Option<&CompleteOrder>) -> Self {
+        if previous.is_some_and(|previous| previous.status == order.status) {
+            return OrderEvent::updated(order);
+        }
+        match order.status {
+            OrderStatus::Ordered => {
+                OrderEvent::of(OrderEventKind::OrderCreated, order, order.created_at)
+            }
+            OrderStatus::Cancelled => OrderEvent::cancelled(order),
+            OrderStatus::Cooking | OrderStatus::Ready | OrderStatus::Served => {
+                OrderEvent::status_changed(order)
+            }
+        }
+    }
+
+    fn of(kind: OrderEventKind, order: &CompleteOrder, at: DateTime<Utc>) -> Self {
+        OrderEvent {
+            id: 0,
+            kind,
+            order_id: order.order_id,
+            table_number: order.table_number,
+            menu_item_id: order.menu_item_id,
+            quantity: order.quantity,
+            status: order.status,
+            at,
+        }
+    }
+
+    /// The event as a Server-Sent Events message.
+    pub fn to_sse(&self) -> String {
+        let data = serde_json::to_string(self).expect("order events serialize to JSON");
+        format!(
+            "id: {}\nevent: {}\ndata: {}\n\n",
+            self.id,
+            self.kind.name(),
+            data
+        )
+    }
+}
+
+/// Which [OrderEvent]s a client wants. Filters left as `None` match everything.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EventFilter {
+    pub table_number: Option<i32>,
+    pub menu_item_id: Option<i32>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &OrderEvent) -> bool {
+        self.table_number
+            .is_none_or(|table| event.table_number == table)
+            && self
+                .menu_item_id
+                .is_none_or(|item| event.menu_item_id == item)
+    }
+}
+
+/// Fans [OrderEvent]s out to every connected client, keeping the latest ones so clients can resume.
+///
+/// Clones share the same bus, so every HTTP worker publishes to and streams from the same events.
+/// Ids start over when the server restarts.
+#[derive(Clone)]
+pub struct EventBus {
+    state: Arc<Mutex<BusState>>,
+}
+
+struct BusState {
+    next_id: u64,
+    /// The latest [EVENT_HISTORY_SIZE] events, oldest first.
+    history: VecDeque<OrderEvent>,
+    subscribers: Vec<mpsc::Sender<OrderEvent>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        EventBus {
+            state: Arc::new(Mutex::new(BusState {
+                next_id: 1,
+                history: VecDeque::with_capacity(EVENT_HISTORY_SIZE),
+                subscribers: vec![],
+            })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BusState> {
+        self.state.lock().expect("event bus lock poisoned")
+    }
+
+    /// Assigns the next id to the event, keeps it for resuming clients and sends it to the connected ones.
+    ///
+    /// A client too slow to keep up with [EVENT_SUBSCRIBER_BUFFER] events is disconnected, it can resume
+    /// from the last event it got.
+    pub fn publish(&self, mut event: OrderEvent) -> u64 {
+        let mut state = self.lock();
+        event.id = state.next_id;
+        state.next_id += 1;
+        if state.history.len() == EVENT_HISTORY_SIZE {
+            state.history.pop_front();
+        }
+        state.history.push_back(event.clone());
+        state
+            .subscribers
+            .retain_mut(|subscriber| subscriber.try_send(event.clone()).is_ok());
+        event.id
+    }
+
+    /// Connects a client: the kept events after `last_event_id` to replay, then the channel of new ones.
+    ///
+    /// Without `last_event_id` only new events are sent. An id this bus never issued comes from before
+    /// a restart, so every kept event is replayed.
+    pub fn subscribe(
+        &self,
+        last_event_id: Option<u64>,
+    ) -> (Vec<OrderEvent>, mpsc::Receiver<OrderEvent>) {
+        let mut state = self.lock();
+        let replay = match last_event_id {
+            Some(last) if last < state.next_id => state
+                .history
+                .iter()
+                .filter(|event| event.id > last)
+                .cloned()
+                .collect(),
+            Some(_) => state.history.iter().cloned().collect(),
+            None => vec![],
+        };
+        let (sender, receiver) = mpsc::channel(EVENT_SUBSCRIBER_BUFFER);
+        state.subscribers.push(sender);
+        (replay, receiver)
+    }
+
+    /// Stream of the events matching `filter`, replayed after `last_event_id` then live.
+    ///
+    /// `None` items are keep-alives, sent after [EVENT_KEEP_ALIVE_SECS] without events so proxies
+    /// don't close idle connections.
+    pub fn events(
+        &self,
+        filter: EventFilter,
+        last_event_id: Option<u64>,
+    ) -> impl Stream<Item = Option<OrderEvent>> {
+        let (replay, receiver) = self.subscribe(last_event_id);
+        let live = stream::unfold(receiver, |mut receiver| async move {
+            match timeout(Duration::from_secs(EVENT_KEEP_ALIVE_SECS), receiver.next()).await {
+                Ok(Some(event)) => Some((Some(event), receiver)),
+                Ok(None) => None,
+                Err(_) => Some((None, receiver)),
+            }
+        });
+        stream::iter(replay.into_iter().map(Some))
+            .chain(live)
+            .filter(move |event| {
+                let keep = event.as_ref().is_none_or(|event| filter.matches(event));
+                async move { keep }
+            })
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        EventBus::new()
+    }
+}
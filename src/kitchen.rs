@@ -0,0 +1,148 @@
+use crate::domain::{read_open_orders, CompleteOrder, OrderStatus};
+use crate::repository::OrderRepository;
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use std::sync::{Arc, RwLock};
+use uuid::Uuid;
+
+/// When an open [CompleteOrder] is expected to be cooked, given the kitchen's limited cooking slots.
+#[derive(Serialize, Debug, Clone)]
+pub struct ScheduledOrder {
+    pub order_id: Uuid,
+    pub table_number: i32,
+    pub menu_item_id: i32,
+    pub item_name: String,
+    pub quantity: i32,
+    pub status: OrderStatus,
+    /// Index of the cooking slot the order is assigned to, from 0 to the number of slots - 1.
+    pub slot: usize,
+    pub starts_at: DateTime<Utc>,
+    pub ready_at: DateTime<Utc>,
+}
+
+/// The kitchen's cooking plan for every open order.
+#[derive(Serialize, Debug, Clone)]
+pub struct KitchenQueue {
+    pub cooking_slots: usize,
+    pub orders: Vec<ScheduledOrder>,
+}
+
+/// Keeps the queue of open orders (ordered or cooking) and schedules them over the kitchen's cooking slots.
+///
+/// The queue is reloaded from the repository with [KitchenScheduler::refresh] whenever orders change.
+/// Clones share the same queue, so every HTTP worker sees the same kitchen.
+#[derive(Clone)]
+pub struct KitchenScheduler {
+    cooking_slots: usize,
+    queue: Arc<RwLock<Vec<CompleteOrder>>>,
+}
+
+impl KitchenScheduler {
+    /// Creates a scheduler with an empty queue. At least one cooking slot is always available.
+    pub fn new(cooking_slots: usize) -> Self {
+        KitchenScheduler {
+            cooking_slots: cooking_slots.max(1),
+            queue: Arc::new(RwLock::new(vec![])),
+        }
+    }
+
+    /// Reloads the queue of open orders from the repository.
+    pub async fn refresh<O: OrderRepository>(&self, repo: &O) -> Result<(), O::ErrT> {
+        let orders = read_open_orders(repo).await?;
+        *self.queue.write().expect("kitchen queue lock poisoned") = orders;
+        Ok(())
+    }
+
+    /// The open orders of the queue as last reloaded.
+    pub fn open_orders(&self) -> Vec<CompleteOrder> {
+        self.queue
+            .read()
+            .expect("kitchen queue lock poisoned")
+            .clone()
+    }
+
+    /// Schedules the current queue as of `now`.
+    pub fn queue(&self, now: DateTime<Utc>) -> KitchenQueue {
+        let queue = self.queue.read().expect("kitchen queue lock poisoned");
+        KitchenQueue {
+            cooking_slots: self.cooking_slots,
+            orders: schedule(&queue, self.cooking_slots, now),
+        }
+    }
+}
+
+/// Computes start and ready times for open orders over `cooking_slots` parallel slots.
+///
+/// Orders already cooking keep their slot from [CompleteOrder::cooking_at]. Waiting orders are then
+/// started first come, first served on whichever slot frees up first, never earlier than `now`.
+/// Orders that aren't ordered or cooking are ignored.
+pub fn schedule(
+    orders: &[CompleteOrder],
+    cooking_slots: usize,
+    now: DateTime<Utc>,
+) -> Vec<ScheduledOrder> {
+    let cooking_slots = cooking_slots.max(1);
+    let mut slots_free_at = vec![now; cooking_slots];
+    let mut scheduled = Vec::with_capacity(orders.len());
+
+    let mut cooking: Vec<&CompleteOrder> = orders
+        .iter()
+        .filter(|order| order.status == OrderStatus::Cooking)
+        .collect();
+    cooking.sort_by_key(|order| order.cooking_at.unwrap_or(order.created_at));
+    for order in cooking {
+        let slot = earliest_free_slot(&slots_free_at);
+        let starts_at = order.cooking_at.unwrap_or(order.created_at);
+        // An overdue order is expected to be ready any moment now.
+        let ready_at = (starts_at + cooking_duration(order)).max(now);
+        slots_free_at[slot] = slots_free_at[slot].max(ready_at);
+        scheduled.push(scheduled_order(order, slot, starts_at, ready_at));
+    }
+
+    let mut waiting: Vec<&CompleteOrder> = orders
+        .iter()
+        .filter(|order| order.status == OrderStatus::Ordered)
+        .collect();
+    waiting.sort_by_key(|order| order.created_at);
+    for order in waiting {
+        let slot = earliest_free_slot(&slots_free_at);
+        let starts_at = slots_free_at[slot].max(now);
+        let ready_at = starts_at + cooking_duration(order);
+        slots_free_at[slot] = ready_at;
+        scheduled.push(scheduled_order(order, slot, starts_at, ready_at));
+    }
+
+    scheduled
+}
+
+fn earliest_free_slot(slots_free_at: &[DateTime<Utc>]) -> usize {
+    slots_free_at
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, free_at)| **free_at)
+        .map(|(slot, _)| slot)
+        .unwrap_or_default()
+}
+
+fn cooking_duration(order: &CompleteOrder) -> Duration {
+    Duration::minutes(order.cooking_time as i64)
+}
+
+fn scheduled_order(
+    order: &CompleteOrder,
+    slot: usize,
+    starts_at: DateTime<Utc>,
+    ready_at: DateTime<Utc>,
+) -> ScheduledOrder {
+    ScheduledOrder {
+        order_id: order.order_id,
+        table_number: order.table_number,
+        menu_item_id: order.menu_item_id,
+        item_name: order.item_name.clone(),
+        quantity: order.quantity,
+        status: order.status,
+        slot,
+        starts_at,
+        ready_at,
+    }
+}
@@ -0,0 +1,187 @@
+use crate::domain::{bump_order, CompleteOrder};
+use crate::error::{AppError, ErrorBody};
+use crate::events::{EventBus, EventFilter, OrderEvent, OrderEventKind};
+use crate::kitchen::KitchenScheduler;
+use crate::presentation::refresh_kitchen;
+use crate::repository::{OrderRepository, RepositoryError};
+use actix_web::rt::task::JoinHandle;
+use actix_web::web;
+use actix_ws::{Message, MessageStream, Session};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// What a kitchen display sends over its WebSocket, as JSON text tagged by `type`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum KitchenCommand {
+    /// Start, or replace, the live feed of orders. Filters left out match everything, like `GET /v1/events`.
+    Subscribe {
+        #[serde(default)]
+        table_number: Option<i32>,
+        #[serde(default)]
+        menu_item_id: Option<i32>,
+        /// Replay the events after this one first, e.g. when the display reconnects.
+        #[serde(default)]
+        last_event_id: Option<u64>,
+    },
+    /// Move an order to its next status: ordered, cooking, ready then served.
+    Bump { order_id: Uuid },
+}
+
+/// What the server sends to a kitchen display, as JSON text tagged by `type`.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum KitchenMessage {
+    /// The display's subscription is active, the events it asked for follow.
+    Subscribed {
+        table_number: Option<i32>,
+        menu_item_id: Option<i32>,
+    },
+    OrderCreated(OrderEvent),
+    OrderCancelled(OrderEvent),
+    OrderStatusChanged(OrderEvent),
+    OrderUpdated(OrderEvent),
+    /// Reply to a [KitchenCommand::Bump] with the order in its new status.
+    Bumped {
+        order: CompleteOrder,
+    },
+    /// A command was refused, with the same body as the REST errors.
+    Error(ErrorBody),
+}
+
+impl From<OrderEvent> for KitchenMessage {
+    fn from(event: OrderEvent) -> Self {
+        match event.kind {
+            OrderEventKind::OrderCreated => KitchenMessage::OrderCreated(event),
+            OrderEventKind::OrderCancelled => KitchenMessage::OrderCancelled(event),
+            OrderEventKind::OrderStatusChanged => KitchenMessage::OrderStatusChanged(event),
+            OrderEventKind::OrderUpdated => KitchenMessage::OrderUpdated(event),
+        }
+    }
+}
+
+impl From<AppError> for KitchenMessage {
+    fn from(error: AppError) -> Self {
+        KitchenMessage::Error(error.body())
+    }
+}
+
+impl KitchenMessage {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("kitchen messages serialize to JSON")
+    }
+}
+
+/// Parses a text message of a display, refusing it with the same error as a malformed REST body.
+pub fn parse_command(text: &str) -> Result<KitchenCommand, AppError> {
+    serde_json::from_str(text).map_err(|error| AppError::BadRequest(error.to_string()))
+}
+
+/// Serves one kitchen display until it disconnects.
+///
+/// Commands are answered in the order they arrive. Once subscribed, events are forwarded by their own
+/// task, so a slow bump doesn't hold back new orders.
+pub async fn run_kitchen_session<R: OrderRepository<ErrT = RepositoryError>>(
+    mut session: Session,
+    mut messages: MessageStream,
+    repo: web::Data<R>,
+    kitchen: web::Data<KitchenScheduler>,
+    events: web::Data<EventBus>,
+) {
+    let mut feed: Option<JoinHandle<()>> = None;
+    while let Some(message) = messages.next().await {
+        let reply = match message {
+            Ok(Message::Text(text)) => match parse_command(&text) {
+                Ok(KitchenCommand::Subscribe {
+                    table_number,
+                    menu_item_id,
+                    last_event_id,
+                }) => {
+                    let filter = EventFilter {
+                        table_number,
+                        menu_item_id,
+                    };
+                    if let Some(previous) = feed.take() {
+                        previous.abort();
+                    }
+                    feed = Some(actix_web::rt::spawn(forward_events(
+                        session.clone(),
+                        events.get_ref().clone(),
+                        filter,
+                        last_event_id,
+                    )));
+                    None
+                }
+                Ok(KitchenCommand::Bump { order_id }) => {
+                    Some(bump(repo.as_ref(), &kitchen, &events, order_id).await)
+                }
+                Err(error) => Some(error.into()),
+            },
+            Ok(Message::Binary(_)) => {
+                Some(AppError::BadRequest("Kitchen commands must be JSON text.".to_string()).into())
+            }
+            Ok(Message::Ping(bytes)) => {
+                if session.pong(&bytes).await.is_err() {
+                    break;
+                }
+                None
+            }
+            Ok(Message::Close(_)) | Err(_) => break,
+            Ok(_) => None,
+        };
+        if let Some(reply) = reply {
+            if session.text(reply.to_json()).await.is_err() {
+                break;
+            }
+        }
+    }
+    if let Some(feed) = feed {
+        feed.abort();
+    }
+    let _ = session.close(None).await;
+}
+
+/// Confirms the subscription then sends every matching event, pinging the display when idle.
+async fn forward_events(
+    mut session: Session,
+    events: EventBus,
+    filter: EventFilter,
+    last_event_id: Option<u64>,
+) {
+    let subscribed = KitchenMessage::Subscribed {
+        table_number: filter.table_number,
+        menu_item_id: filter.menu_item_id,
+    };
+    if session.text(subscribed.to_json()).await.is_err() {
+        return;
+    }
+    let mut stream = Box::pin(events.events(filter, last_event_id));
+    while let Some(event) = stream.next().await {
+        let sent = match event {
+            Some(event) => session.text(KitchenMessage::from(event).to_json()).await,
+            None => session.ping(b"").await,
+        };
+        if sent.is_err() {
+            return;
+        }
+    }
+}
+
+/// Bumps an order like `POST /v1/orders/{order_id}/transitions` would, and tells every display.
+async fn bump<R: OrderRepository<ErrT = RepositoryError>>(
+    repo: &R,
+    kitchen: &KitchenScheduler,
+    events: &EventBus,
+    order_id: Uuid,
+) -> KitchenMessage {
+    match bump_order(repo, order_id).await {
+        Ok(Some(order)) => {
+            refresh_kitchen(kitchen, repo).await;
+            events.publish(OrderEvent::status_changed(&order));
+            KitchenMessage::Bumped { order }
+        }
+        Ok(None) => AppError::NotFound("No order found.".to_string()).into(),
+        Err(error) => AppError::from(error).into(),
+    }
+}
@@ -1,61 +1,289 @@
+mod billing;
+mod change_feed;
+pub mod config;
 mod constants;
-mod domain;
+pub mod domain;
+pub mod error;
+mod events;
+mod kitchen;
+mod kitchen_socket;
 pub mod presentation;
 pub mod repository;
 mod tests;
 
 use actix_web::{dev::Server, middleware::Logger, web, App, Error, HttpServer};
+use change_feed::listen_order_changes;
+use config::Config;
+use error::AppError;
+use events::EventBus;
 use futures::future::try_join_all;
+use kitchen::KitchenScheduler;
 use log::info;
 use presentation::*;
 use rand::Rng;
-use repository::PgSqlOrderRepository;
-use sqlx::{postgres::PgPoolOptions, PgPool};
+use repository::{
+    MenuRepository, OrderRepository, PaymentRepository, PgSqlMenuRepository, PgSqlOrderRepository,
+    PgSqlPaymentRepository, PgSqlTableRepository, RepositoryError, TableRepository,
+};
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::PgPool;
 use std::env;
+use std::str::FromStr;
 
 /// Creates new HTTP server with a PostgreSQL database connection.
+///
+/// Its clients also get the order events of the other servers sharing the database, see [setup_pg_db].
 pub async fn new_http_pg_server(
     socket_addrs: &str,
     repo: PgSqlOrderRepository,
+    menu_repo: PgSqlMenuRepository,
+    table_repo: PgSqlTableRepository,
+    payment_repo: PgSqlPaymentRepository,
+    config: Config,
 ) -> Result<Server, Error> {
+    let kitchen = load_kitchen(&repo, &config).await?;
+    let events = EventBus::new();
+    actix_web::rt::spawn(listen_order_changes(
+        repo.clone(),
+        config.instance_id.clone(),
+        kitchen.clone(),
+        events.clone(),
+    ));
+    serve(
+        socket_addrs,
+        repo,
+        menu_repo,
+        table_repo,
+        payment_repo,
+        config,
+        kitchen,
+        events,
+    )
+    .await
+}
+
+/// Creates new HTTP server backed by any [OrderRepository], [MenuRepository], [TableRepository]
+/// and [PaymentRepository] implementations.
+pub async fn new_http_server<R, M, T, P>(
+    socket_addrs: &str,
+    repo: R,
+    menu_repo: M,
+    table_repo: T,
+    payment_repo: P,
+    config: Config,
+) -> Result<Server, Error>
+where
+    R: OrderRepository<ErrT = RepositoryError> + Clone + Send + 'static,
+    M: MenuRepository<ErrT = RepositoryError> + Clone + Send + 'static,
+    T: TableRepository<ErrT = RepositoryError> + Clone + Send + 'static,
+    P: PaymentRepository<ErrT = RepositoryError> + Clone + Send + 'static,
+{
+    let kitchen = load_kitchen(&repo, &config).await?;
+    serve(
+        socket_addrs,
+        repo,
+        menu_repo,
+        table_repo,
+        payment_repo,
+        config,
+        kitchen,
+        EventBus::new(),
+    )
+    .await
+}
+
+/// The kitchen with the orders already waiting in it, loaded before taking requests.
+async fn load_kitchen<R: OrderRepository<ErrT = RepositoryError>>(
+    repo: &R,
+    config: &Config,
+) -> Result<KitchenScheduler, Error> {
+    let kitchen = KitchenScheduler::new(config.cooking_slots);
+    kitchen.refresh(repo).await.map_err(AppError::from)?;
+    Ok(kitchen)
+}
+
+/// Registers the routes of every API, with the JSON error body of malformed requests.
+///
+/// The repositories, the [KitchenScheduler], the [EventBus] and the [Config] are expected as app data.
+pub(crate) fn routes<R, M, T, P>(cfg: &mut web::ServiceConfig)
+where
+    R: OrderRepository<ErrT = RepositoryError> + Clone + Send + 'static,
+    M: MenuRepository<ErrT = RepositoryError> + Clone + Send + 'static,
+    T: TableRepository<ErrT = RepositoryError> + Clone + Send + 'static,
+    P: PaymentRepository<ErrT = RepositoryError> + Clone + Send + 'static,
+{
+    cfg
+        // Malformed requests get the same JSON error body as every other error
+        .app_data(web::JsonConfig::default().error_handler(bad_request_handler))
+        .app_data(web::PathConfig::default().error_handler(bad_request_handler))
+        .app_data(web::QueryConfig::default().error_handler(bad_request_handler))
+        // Create a new order
+        .route("/v1/orders", web::post().to(create_order::<R, M, T>))
+        // Search orders across every table
+        .route("/v1/orders", web::get().to(get_orders::<R>))
+        // Upload orders taken offline, each one is created or refused on its own
+        .route("/v1/orders/sync", web::post().to(sync_orders::<R, M, T>))
+        // Create several orders for a table in a single transaction
+        .route(
+            "/v1/tables/{table_number}/orders",
+            web::post().to(create_table_orders::<R, M, T>),
+        )
+        // Read all orders from a table
+        .route(
+            "/v1/tables/{table_number}/orders",
+            web::get().to(get_table_orders::<R>),
+        )
+        // Itemized check of the session open at a table
+        .route(
+            "/v1/tables/{table_number}/bill",
+            web::get().to(get_table_bill::<R>),
+        )
+        // Minutes left for the orders of a table still being prepared
+        .route(
+            "/v1/tables/{table_number}/remaining_time",
+            web::get().to(get_table_remaining_time::<R>),
+        )
+        // Read an order from a table and a menu item
+        .route(
+            "/v1/tables/{table_number}/menu_items/{menu_item_id}",
+            web::get().to(get_order_from_menu_item_and_table::<R>),
+        )
+        // Delete an order from a table
+        .route(
+            "/v1/tables/{table_number}/menu_items/{menu_item_id}",
+            web::delete().to(delete_menu_item_from_order::<R>),
+        )
+        // Read an order by id
+        .route("/v1/orders/{order_id}", web::get().to(get_order::<R>))
+        // Move an order to another table or swap its menu item
+        .route(
+            "/v1/orders/{order_id}",
+            web::patch().to(patch_order::<R, M, T>),
+        )
+        .route("/v1/orders/{order_id}", web::delete().to(delete_order::<R>))
+        // Move an order through its lifecycle
+        .route(
+            "/v1/orders/{order_id}/transitions",
+            web::post().to(post_order_transition::<R>),
+        )
+        // Menu management
+        .route("/v1/menu_items", web::get().to(get_menu_items::<M>))
+        .route("/v1/menu_items", web::post().to(post_menu_item::<M>))
+        .route(
+            "/v1/menu_items/{menu_item_id}",
+            web::get().to(get_menu_item::<M>),
+        )
+        .route(
+            "/v1/menu_items/{menu_item_id}",
+            web::patch().to(patch_menu_item::<M>),
+        )
+        .route(
+            "/v1/menu_items/{menu_item_id}",
+            web::delete().to(delete_menu_item::<M>),
+        )
+        // Floor plan
+        .route("/v1/tables", web::get().to(get_tables::<T>))
+        .route("/v1/tables/{table_number}", web::get().to(get_table::<T>))
+        .route("/v1/tables/{table_number}", web::put().to(put_table::<T>))
+        .route(
+            "/v1/tables/{table_number}",
+            web::delete().to(delete_table::<T>),
+        )
+        // Table sessions: seat a party, look at the current one and close it when the guests leave
+        .route(
+            "/v1/tables/{table_number}/sessions",
+            web::get().to(get_table_sessions::<T>),
+        )
+        .route(
+            "/v1/tables/{table_number}/sessions",
+            web::post().to(post_table_session::<T>),
+        )
+        .route(
+            "/v1/tables/{table_number}/sessions/current",
+            web::get().to(get_current_table_session::<T>),
+        )
+        .route(
+            "/v1/tables/{table_number}/sessions/current/close",
+            web::post().to(post_close_table_session::<T>),
+        )
+        // Payments: pay the bill in parts, refund and settle once it's covered
+        .route(
+            "/v1/tables/{table_number}/payments",
+            web::get().to(get_table_payments::<R, T, P>),
+        )
+        .route(
+            "/v1/tables/{table_number}/payments",
+            web::post().to(post_table_payment::<R, T, P>),
+        )
+        .route(
+            "/v1/tables/{table_number}/settle",
+            web::post().to(post_table_settlement::<T, P>),
+        )
+        .route(
+            "/v1/payments/{payment_id}/refunds",
+            web::post().to(post_payment_refund::<P>),
+        )
+        // Audit trail of cancelled orders
+        .route(
+            "/v1/admin/cancellations",
+            web::get().to(get_cancellations::<R>),
+        )
+        // Cooking plan of the kitchen
+        .route("/v1/kitchen/queue", web::get().to(get_kitchen_queue))
+        // Live order events for kitchen displays, as Server-Sent Events
+        .route("/v1/events", web::get().to(get_events))
+        // Two-way channel for kitchen displays: live orders in, bumps out
+        .route("/v1/kitchen/ws", web::get().to(get_kitchen_socket::<R>));
+}
+
+/// Binds the routes of every API, with `events` fanning order events out to the streaming clients.
+// One argument per shared service, like the handlers.
+#[allow(clippy::too_many_arguments)]
+async fn serve<R, M, T, P>(
+    socket_addrs: &str,
+    repo: R,
+    menu_repo: M,
+    table_repo: T,
+    payment_repo: P,
+    config: Config,
+    kitchen: KitchenScheduler,
+    events: EventBus,
+) -> Result<Server, Error>
+where
+    R: OrderRepository<ErrT = RepositoryError> + Clone + Send + 'static,
+    M: MenuRepository<ErrT = RepositoryError> + Clone + Send + 'static,
+    T: TableRepository<ErrT = RepositoryError> + Clone + Send + 'static,
+    P: PaymentRepository<ErrT = RepositoryError> + Clone + Send + 'static,
+{
     let server = HttpServer::new(move || {
         App::new()
             .wrap(Logger::default())
-            // Create a new order
-            .route("/v1/orders", web::post().to(create_order))
-            // Read all orders from a table
-            .route(
-                "/v1/tables/{table_number}/orders",
-                web::get().to(get_table_orders),
-            )
-            // Read an order from a table and a menu item
-            .route(
-                "/v1/tables/{table_number}/menu_items/{menu_item_id}",
-                web::get().to(get_order_from_menu_item_and_table),
-            )
-            // Delete an order from a table
-            .route(
-                "/v1/tables/{table_number}/menu_items/{menu_item_id}",
-                web::delete().to(delete_menu_item_from_order),
-            )
-            .route(
-                "/v1/orders/{order_id}",
-                web::delete().to(delete_order),
-            )
+            .configure(routes::<R, M, T, P>)
             // Add data to your app
             .app_data(web::Data::new(repo.clone()))
+            .app_data(web::Data::new(kitchen.clone()))
+            .app_data(web::Data::new(events.clone()))
+            .app_data(web::Data::new(menu_repo.clone()))
+            .app_data(web::Data::new(table_repo.clone()))
+            .app_data(web::Data::new(payment_repo.clone()))
+            .app_data(web::Data::new(config.clone()))
     })
     .bind(socket_addrs)?
     .run();
     Ok(server)
 }
 
-pub async fn setup_pg_db() -> Result<PgPool, sqlx::Error> {
+/// Connects to the database and brings it up to date.
+///
+/// Connections are named after [Config::instance_id], so the order changes this server makes can be
+/// told apart from those of the other replicas.
+pub async fn setup_pg_db(config: &Config) -> Result<PgPool, sqlx::Error> {
     // Connect to the db
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let options = PgConnectOptions::from_str(&database_url)?.application_name(&config.instance_id);
     let pool = PgPoolOptions::new()
         .acquire_timeout(std::time::Duration::from_secs(30))
-        .connect(&database_url)
+        .connect_with(options)
         .await?;
     info!("Connecting to DB ... OK");
 
@@ -63,6 +291,24 @@ pub async fn setup_pg_db() -> Result<PgPool, sqlx::Error> {
     sqlx::migrate!().run(&pool).await?;
     info!("Running migrations ... OK");
 
+    // Initialize the restaurant's floor plan if first time running this DB.
+    let tables = sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!" FROM tables"#)
+        .fetch_one(&pool)
+        .await?;
+    if tables == 0 {
+        sqlx::query!(
+            "INSERT INTO tables (table_number, seats, section)
+             SELECT table_number, $3, $4 FROM generate_series($1::INT, $2::INT) AS table_number",
+            config.min_table_number,
+            config.max_table_number,
+            constants::DEFAULT_TABLE_SEATS,
+            constants::DEFAULT_TABLE_SECTION,
+        )
+        .execute(&pool)
+        .await?;
+        info!("TABLE tables initialized ... OK");
+    }
+
     // Initialize the restaurant's menu_items TABLE if first time running this DB.
     let rows = sqlx::query!("SELECT * FROM menu_items")
         .fetch_all(&pool)
@@ -70,7 +316,7 @@ pub async fn setup_pg_db() -> Result<PgPool, sqlx::Error> {
     match rows.len() >= 50 {
         true => {
             info!("TABLE menu_items already initialized ... OK");
-            return Ok(pool);
+            Ok(pool)
         }
         false => {
             sqlx::query!("TRUNCATE menu_items CASCADE")
@@ -81,17 +327,24 @@ pub async fn setup_pg_db() -> Result<PgPool, sqlx::Error> {
             for (i, item) in constants::FOOD_ITEMS.iter().enumerate() {
                 futures.push(
                     sqlx::query!(
-                        "INSERT INTO menu_items (id, item_name, cooking_time) VALUES ($1, $2, $3)",
+                        "INSERT INTO menu_items (id, item_name, cooking_time, price) VALUES ($1, $2, $3, $4)",
                         i as i32,
                         item,
                         rng.gen_range(5..15),
+                        rng.gen_range(5..30_i64) * 100,
                     )
                     .execute(&pool),
                 );
             }
             try_join_all(futures).await?;
+            // Seeded ids are explicit, so move the id generator past them for items added later.
+            sqlx::query!(
+                "SELECT setval(pg_get_serial_sequence('menu_items', 'id'), MAX(id)) FROM menu_items"
+            )
+            .fetch_one(&pool)
+            .await?;
             info!("TABLE menu_items initialized ... OK");
-            return Ok(pool);
+            Ok(pool)
         }
     }
 }
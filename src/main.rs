@@ -1,22 +1,25 @@
-use restaurant::repository::PgSqlOrderRepository;
-use restaurant::{setup_pg_db, new_http_pg_server};
-use std::env;
 use log::error;
-
+use restaurant::config::Config;
+use restaurant::repository::{
+    PgSqlMenuRepository, PgSqlOrderRepository, PgSqlPaymentRepository, PgSqlTableRepository,
+};
+use restaurant::{new_http_pg_server, setup_pg_db};
+use std::env;
 
 #[actix_web::main]
 async fn main() -> Result<(), std::io::Error> {
     // Loads the environment variables
     // - Local dev loads from .env
     // - Container loads from .yml file
-    dotenvy::dotenv().ok(); 
+    dotenvy::dotenv().ok();
 
     // Initialize logging
     let env_filter = env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string());
     env_logger::init_from_env(env_logger::Env::default().default_filter_or(env_filter));
 
     // Load the asynchronous pool of SQLx database connections.
-    let pool = match setup_pg_db().await {
+    let config = Config::from_env();
+    let pool = match setup_pg_db(&config).await {
         Ok(val) => val,
         Err(err) => {
             error!("{:?}", err);
@@ -26,13 +29,24 @@ async fn main() -> Result<(), std::io::Error> {
 
     // Run the http server using a pgsql db
     let socket_addrs = env::var("SOCKETADDRS").expect("SOCKETADDRS must be set");
-    let pg_sql_order_repository = PgSqlOrderRepository::new(pool);
-    let server_result = new_http_pg_server(&socket_addrs, pg_sql_order_repository).await;
+    let pg_sql_order_repository = PgSqlOrderRepository::new(pool.clone());
+    let pg_sql_menu_repository = PgSqlMenuRepository::new(pool.clone());
+    let pg_sql_table_repository = PgSqlTableRepository::new(pool.clone());
+    let pg_sql_payment_repository = PgSqlPaymentRepository::new(pool);
+    let server_result = new_http_pg_server(
+        &socket_addrs,
+        pg_sql_order_repository,
+        pg_sql_menu_repository,
+        pg_sql_table_repository,
+        pg_sql_payment_repository,
+        config,
+    )
+    .await;
     match server_result {
         Ok(server) => server.await,
         Err(error) => {
-            error!("{:?}",error);
-            Err(std::io::Error::new(std::io::ErrorKind::Other, error.to_string()))
-        },
+            error!("{:?}", error);
+            Err(std::io::Error::other(error.to_string()))
+        }
     }
-}
\ No newline at end of file
+}
@@ -1,105 +1,1060 @@
+use crate::billing::{build_bill, validate_split};
+use crate::config::Config;
+use crate::constants::DEFAULT_PAGE_LIMIT;
 use crate::domain::{
-    delete_order_by_id, delete_order_item_from_table, read_order_item_from_table,
-    read_orders_by_table, Order,
+    cancel_order, cancel_order_item_from_table, close_table_session, create_menu_item,
+    create_order_once, create_orders, define_table, open_table_session, read_cancellations,
+    read_current_session, read_menu_item, read_menu_items, read_order_by_id,
+    read_order_item_from_table, read_orders_by_session, read_orders_by_table, read_orders_page,
+    read_remaining_time_by_table, read_session_payments, read_table, read_table_sessions,
+    read_tables, record_payment, refund_payment, remove_table, replay_order, retire_menu_item,
+    search_orders, settle_table, sync_order, transition_order, update_menu_item, update_order,
+    validate_amount, validate_cancellation, validate_idempotency_key, validate_menu_item,
+    validate_order, validate_order_listing, validate_order_search, validate_party_size,
+    validate_table, Cancellation, CancellationFilter, CompleteOrder, FieldViolation, MenuItemPatch,
+    NewMenuItem, Order, OrderCursor, OrderPatch, OrderSearch, OrderSort, OrderStatus,
+    RestaurantTable, SyncOutcome, TableItemKey, TableOrdersFilter, TableSession, Tender,
 };
-use crate::repository::PgSqlOrderRepository;
-use actix_web::{web, HttpResponse};
+use crate::error::{AppError, ErrorBody};
+use crate::events::{EventBus, EventFilter, OrderEvent};
+use crate::kitchen::KitchenScheduler;
+use crate::kitchen_socket::run_kitchen_session;
+use crate::repository::{
+    MenuRepository, OrderRepository, PaymentRepository, RepositoryError, TableRepository,
+};
+use actix_web::{web, web::Bytes, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
 use log::error;
 use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
 use uuid::Uuid;
 
 /// The definition of [OrderRequest] which captures incoming JSON data
 #[derive(Serialize, Deserialize)]
 pub struct OrderRequest {
+    /// Id minted by the client, e.g. a device taking orders offline. Generated by the server when omitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Uuid>,
     pub table_number: i32,
     pub menu_item_id: i32,
+    /// Number of plates, defaults to 1 when omitted.
+    #[serde(default = "default_quantity")]
+    pub quantity: i32,
+}
+
+fn default_quantity() -> i32 {
+    1
+}
+
+impl From<&OrderRequest> for Order {
+    fn from(request: &OrderRequest) -> Self {
+        let order =
+            Order::with_quantity(request.table_number, request.menu_item_id, request.quantity);
+        Order {
+            id: request.id.unwrap_or(order.id),
+            ..order
+        }
+    }
+}
+
+/// The definition of [TableOrderItemRequest] which captures one entry of a bulk order for a table.
+#[derive(Serialize, Deserialize)]
+pub struct TableOrderItemRequest {
+    /// Id minted by the client, see [OrderRequest::id].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Uuid>,
+    pub menu_item_id: i32,
+    /// Number of plates, defaults to 1 when omitted.
+    #[serde(default = "default_quantity")]
+    pub quantity: i32,
+}
+
+/// The definition of [UpdateOrderRequest] which captures incoming JSON data for partial [Order] updates.
+#[derive(Serialize, Deserialize, Default)]
+pub struct UpdateOrderRequest {
+    pub table_number: Option<i32>,
+    pub menu_item_id: Option<i32>,
+    pub quantity: Option<i32>,
+}
+
+/// The definition of [TransitionRequest] which captures the [OrderStatus] an [Order] should move to.
+#[derive(Serialize, Deserialize)]
+pub struct TransitionRequest {
+    pub status: OrderStatus,
+    /// Only recorded when cancelling.
+    pub reason: Option<String>,
+    /// Who cancels the order, only recorded when cancelling.
+    pub actor: Option<String>,
+}
+
+/// The definition of [MenuItemRequest] which captures incoming JSON data for new [MenuItem]s.
+#[derive(Serialize, Deserialize)]
+pub struct MenuItemRequest {
+    pub item_name: String,
+    pub cooking_time: i32,
+    pub price: i64,
+}
+
+/// The definition of [UpdateMenuItemRequest] which captures incoming JSON data for partial [MenuItem] updates.
+#[derive(Serialize, Deserialize, Default)]
+pub struct UpdateMenuItemRequest {
+    pub item_name: Option<String>,
+    pub cooking_time: Option<i32>,
+    pub price: Option<i64>,
+}
+
+/// The definition of [TableRequest] which captures incoming JSON data to put a [RestaurantTable] on the floor plan.
+#[derive(Serialize, Deserialize)]
+pub struct TableRequest {
+    pub seats: i32,
+    pub section: String,
+}
+
+/// The definition of [SessionRequest] which captures how many guests are seated at a table.
+#[derive(Serialize, Deserialize)]
+pub struct SessionRequest {
+    pub party_size: i32,
+}
+
+/// Query parameters for listing the [Order]s of a table. Defaults to the [TableSession] open at the table,
+/// oldest first, [DEFAULT_PAGE_LIMIT] per page.
+#[derive(Deserialize)]
+pub struct TableOrdersQuery {
+    pub session_id: Option<Uuid>,
+    pub menu_item_id: Option<i32>,
+    /// Only orders created at or after this time.
+    pub created_from: Option<DateTime<Utc>>,
+    /// Only orders created before this time.
+    pub created_to: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub sort: OrderSort,
+    #[serde(default = "default_page_limit")]
+    pub limit: i64,
+    /// The `next_cursor` of the previous page.
+    pub cursor: Option<String>,
+}
+
+fn default_page_limit() -> i64 {
+    DEFAULT_PAGE_LIMIT
+}
+
+/// Query parameters for searching [Order]s across every table, oldest first, [DEFAULT_PAGE_LIMIT] per page.
+/// See [OrderSearch] for the filters.
+#[derive(Deserialize)]
+pub struct OrderSearchQuery {
+    pub menu_item_id: Option<i32>,
+    pub table_from: Option<i32>,
+    pub table_to: Option<i32>,
+    pub created_from: Option<DateTime<Utc>>,
+    pub created_to: Option<DateTime<Utc>>,
+    pub status: Option<OrderStatus>,
+    pub item_name: Option<String>,
+    #[serde(default)]
+    pub sort: OrderSort,
+    #[serde(default = "default_page_limit")]
+    pub limit: i64,
+    /// The `next_cursor` of the previous page.
+    pub cursor: Option<String>,
+}
+
+/// Query parameters for the bill of a table. Defaults to the [TableSession] open at the table, not split.
+#[derive(Deserialize)]
+pub struct BillQuery {
+    pub session_id: Option<Uuid>,
+    /// Number of guests to split the total across.
+    pub split: Option<i32>,
+}
+
+/// The definition of [PaymentRequest] which captures money handed over for the bill of a table.
+#[derive(Serialize, Deserialize)]
+pub struct PaymentRequest {
+    pub tender: Tender,
+    /// Amount handed over, in minor currency units.
+    pub amount: i64,
+}
+
+/// The definition of [RefundRequest] which captures how much of a [Payment](crate::domain::Payment) to give back.
+#[derive(Serialize, Deserialize)]
+pub struct RefundRequest {
+    pub amount: i64,
+}
+
+/// Query parameters for listing the payments of a table. Defaults to the [TableSession] open at the table.
+#[derive(Deserialize)]
+pub struct PaymentsQuery {
+    pub session_id: Option<Uuid>,
+}
+
+/// Query parameters for cancelling [Order]s: why and by whom, both optional.
+#[derive(Deserialize)]
+pub struct CancelQuery {
+    pub reason: Option<String>,
+    pub actor: Option<String>,
+}
+
+/// Query parameters for listing cancelled [Order]s, see [CancellationFilter].
+#[derive(Deserialize)]
+pub struct CancellationsQuery {
+    pub table_number: Option<i32>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// Query parameters for listing [MenuItem]s.
+#[derive(Deserialize)]
+pub struct MenuQuery {
+    #[serde(default)]
+    pub include_retired: bool,
+}
+
+/// Turns the extractor errors of actix (malformed JSON, path or query) into an [AppError::BadRequest].
+pub fn bad_request_handler<E: std::fmt::Display>(error: E, _req: &HttpRequest) -> actix_web::Error {
+    AppError::BadRequest(error.to_string()).into()
+}
+
+/// Refuses the request with a 400 listing every [FieldViolation], if there are any.
+fn reject_violations(violations: Vec<FieldViolation>) -> Result<(), AppError> {
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(AppError::Validation(violations))
+    }
+}
+
+/// Reloads the kitchen queue after orders changed. The change itself already succeeded, so a failure is only logged.
+pub(crate) async fn refresh_kitchen<R: OrderRepository<ErrT = RepositoryError>>(
+    kitchen: &KitchenScheduler,
+    repo: &R,
+) {
+    if let Err(error) = kitchen.refresh(repo).await {
+        error!("Failed to refresh the kitchen queue: {:?}", error);
+    }
+}
+
+/// Header a client sets to retry a request safely, see [create_order].
+const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// Header set on responses replayed for an `Idempotency-Key` instead of creating anything.
+const IDEMPOTENT_REPLAYED_HEADER: &str = "Idempotent-Replayed";
+
+/// The `Idempotency-Key` header of a request, if it has one.
+fn idempotency_key(req: &HttpRequest) -> Result<Option<String>, AppError> {
+    let Some(value) = req.headers().get(IDEMPOTENCY_KEY_HEADER) else {
+        return Ok(None);
+    };
+    let key = value.to_str().map_err(|_| {
+        AppError::Validation(vec![FieldViolation::new(
+            IDEMPOTENCY_KEY_HEADER,
+            "Idempotency key must be visible ASCII.".to_string(),
+        )])
+    })?;
+    reject_violations(validate_idempotency_key(key))?;
+    Ok(Some(key.to_string()))
 }
 
 /// Post handler for creating an item [Order] with the table number and how long the item will take to cook.
-pub async fn create_order(
-    data: web::Data<PgSqlOrderRepository>,
+///
+/// The order is validated against the floor plan and the menu before it's written.
+///
+/// With an `Idempotency-Key` header, a retry within [Config::idempotency_window] answers with the order created
+/// the first time, flagged by the `Idempotent-Replayed` header, instead of creating another one.
+// One extractor per dependency, like every other handler.
+#[allow(clippy::too_many_arguments)]
+pub async fn create_order<
+    R: OrderRepository<ErrT = RepositoryError>,
+    M: MenuRepository<ErrT = RepositoryError>,
+    T: TableRepository<ErrT = RepositoryError>,
+>(
+    req: HttpRequest,
+    data: web::Data<R>,
+    menu: web::Data<M>,
+    tables: web::Data<T>,
+    config: web::Data<Config>,
+    kitchen: web::Data<KitchenScheduler>,
+    events: web::Data<EventBus>,
     form: web::Json<OrderRequest>,
-) -> HttpResponse {
-    let order = Order::new(form.table_number, form.menu_item_id)
-        .create(data.as_ref())
-        .await;
-    match order {
-        Ok(uuid) => HttpResponse::Ok().json(uuid),
-        Err(error) => {
-            error!("{:?}", error);
-            match error {
-                sqlx::Error::Database(_) => {
-                    HttpResponse::InternalServerError().json("This menu item doesn't exist.")
-                }
-                _ => HttpResponse::InternalServerError().finish(),
+) -> Result<HttpResponse, AppError> {
+    let order = Order::from(&*form);
+    let key = idempotency_key(&req)?;
+    let since = Utc::now() - config.idempotency_window();
+    // Replay before validating, the menu or the floor plan may have changed since the first request.
+    if let Some(key) = &key {
+        if let Some(uuid) = replay_order(data.as_ref(), key, &order, since).await? {
+            return Ok(replayed(uuid));
+        }
+    }
+    let fields = OrderPatch::from(&order);
+    reject_violations(
+        validate_order(
+            menu.as_ref(),
+            tables.as_ref(),
+            &config.table_numbers(),
+            &fields,
+        )
+        .await?,
+    )?;
+    let outcome = match &key {
+        Some(key) => {
+            let (uuid, outcome) = create_order_once(data.as_ref(), key, &order, since).await?;
+            if uuid != order.id {
+                return Ok(replayed(uuid));
             }
+            outcome
         }
+        None => sync_order(data.as_ref(), &order).await?,
+    };
+    if outcome == SyncOutcome::Created {
+        refresh_kitchen(&kitchen, data.as_ref()).await;
+        events.publish(OrderEvent::created(&order));
     }
+    Ok(HttpResponse::Ok().json(order.id))
 }
 
-/// Get handler for querying all [Order] items for a specified table number.
-pub async fn get_table_orders(
-    data: web::Data<PgSqlOrderRepository>,
+/// Answer to a request replayed for its `Idempotency-Key`: the id of the order created the first time.
+fn replayed(uuid: Uuid) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header((IDEMPOTENT_REPLAYED_HEADER, "true"))
+        .json(uuid)
+}
+
+/// Post handler for creating several [Order]s for a table number at once.
+///
+/// The batch is all or nothing: if any menu item is invalid, no order is created.
+// One extractor per dependency, like every other handler.
+#[allow(clippy::too_many_arguments)]
+pub async fn create_table_orders<
+    R: OrderRepository<ErrT = RepositoryError>,
+    M: MenuRepository<ErrT = RepositoryError>,
+    T: TableRepository<ErrT = RepositoryError>,
+>(
+    data: web::Data<R>,
+    menu: web::Data<M>,
+    tables: web::Data<T>,
+    config: web::Data<Config>,
+    kitchen: web::Data<KitchenScheduler>,
+    events: web::Data<EventBus>,
     path: web::Path<i32>,
-) -> HttpResponse {
-    let table_num = path.into_inner();
-    let orders = read_orders_by_table(data.as_ref(), table_num).await;
-    match orders {
-        Ok(items) if items.len() > 0 => HttpResponse::Ok().json(items),
-        Ok(_) => HttpResponse::NotFound().json("No orders found"),
-        Err(error) => {
-            error!("{:?}", error);
-            HttpResponse::InternalServerError().finish()
+    form: web::Json<Vec<TableOrderItemRequest>>,
+) -> Result<HttpResponse, AppError> {
+    let table_number = path.into_inner();
+    if form.is_empty() {
+        return Err(AppError::BadRequest("No menu items to order.".to_string()));
+    }
+    let orders: Vec<Order> = form
+        .iter()
+        .map(|item| {
+            Order::from(&OrderRequest {
+                id: item.id,
+                table_number,
+                menu_item_id: item.menu_item_id,
+                quantity: item.quantity,
+            })
+        })
+        .collect();
+    let table_numbers = config.table_numbers();
+    let table = OrderPatch {
+        table_number: Some(table_number),
+        ..Default::default()
+    };
+    let mut violations =
+        validate_order(menu.as_ref(), tables.as_ref(), &table_numbers, &table).await?;
+    for (index, order) in orders.iter().enumerate() {
+        let item = OrderPatch {
+            table_number: None,
+            ..OrderPatch::from(order)
+        };
+        let item_violations =
+            validate_order(menu.as_ref(), tables.as_ref(), &table_numbers, &item).await?;
+        violations.extend(item_violations.into_iter().map(|v| v.at_index(index)));
+    }
+    reject_violations(violations)?;
+    let outcomes = create_orders(data.as_ref(), &orders).await?;
+    let created: Vec<&Order> = orders
+        .iter()
+        .zip(&outcomes)
+        .filter(|(_, outcome)| **outcome == SyncOutcome::Created)
+        .map(|(order, _)| order)
+        .collect();
+    if !created.is_empty() {
+        refresh_kitchen(&kitchen, data.as_ref()).await;
+    }
+    for order in created {
+        events.publish(OrderEvent::created(order));
+    }
+    let uuids: Vec<Uuid> = orders.iter().map(|order| order.id).collect();
+    Ok(HttpResponse::Ok().json(uuids))
+}
+
+/// Result of one [Order] of a sync, see [sync_orders].
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum OrderSyncResult {
+    /// The order was stored.
+    Created { id: Uuid },
+    /// The same order was already stored under this id.
+    Unchanged { id: Uuid },
+    /// The order was refused, the others of the sync aren't affected.
+    Rejected { id: Uuid, error: ErrorBody },
+}
+
+/// Post handler for devices uploading the [Order]s they took while offline, usually with ids of their own.
+///
+/// Unlike [create_table_orders], each order is validated and stored on its own: the response lists, in the
+/// same order as the request, which ones were created, already stored, or rejected and why.
+pub async fn sync_orders<
+    R: OrderRepository<ErrT = RepositoryError>,
+    M: MenuRepository<ErrT = RepositoryError>,
+    T: TableRepository<ErrT = RepositoryError>,
+>(
+    data: web::Data<R>,
+    menu: web::Data<M>,
+    tables: web::Data<T>,
+    config: web::Data<Config>,
+    kitchen: web::Data<KitchenScheduler>,
+    events: web::Data<EventBus>,
+    form: web::Json<Vec<OrderRequest>>,
+) -> Result<HttpResponse, AppError> {
+    if form.is_empty() {
+        return Err(AppError::BadRequest("No orders to sync.".to_string()));
+    }
+    let table_numbers = config.table_numbers();
+    let mut results = Vec::with_capacity(form.len());
+    for request in form.iter() {
+        let order = Order::from(request);
+        let outcome = async {
+            let fields = OrderPatch::from(&order);
+            reject_violations(
+                validate_order(menu.as_ref(), tables.as_ref(), &table_numbers, &fields).await?,
+            )?;
+            Ok::<_, AppError>(sync_order(data.as_ref(), &order).await?)
         }
+        .await;
+        results.push(match outcome {
+            Ok(SyncOutcome::Created) => {
+                events.publish(OrderEvent::created(&order));
+                OrderSyncResult::Created { id: order.id }
+            }
+            Ok(SyncOutcome::Unchanged) => OrderSyncResult::Unchanged { id: order.id },
+            Err(error) => OrderSyncResult::Rejected {
+                id: order.id,
+                error: error.body(),
+            },
+        });
+    }
+    if results
+        .iter()
+        .any(|result| matches!(result, OrderSyncResult::Created { .. }))
+    {
+        refresh_kitchen(&kitchen, data.as_ref()).await;
     }
+    Ok(HttpResponse::Ok().json(results))
 }
 
-/// Get handler for querying latest [Order] item for a specified menu item [Order::menu_item_id] for a specified table number.
-pub async fn get_order_from_menu_item_and_table(
-    data: web::Data<PgSqlOrderRepository>,
-    path: web::Path<(i32, i32)>,
-) -> HttpResponse {
-    let (table_number, menu_item_id) = path.into_inner();
-    let order = read_order_item_from_table(data.as_ref(), table_number, menu_item_id).await;
-    match order {
-        Ok(item) if item.is_some() => HttpResponse::Ok().json(item),
-        Ok(_) => HttpResponse::NotFound().json("No order found."), // No row found
-        Err(error) => {
-            error!("{:?}", error);
-            HttpResponse::InternalServerError().finish()
+/// Parses the `cursor` of an order listing, adding a [FieldViolation] if it isn't one.
+fn parse_cursor(cursor: Option<&str>, violations: &mut Vec<FieldViolation>) -> Option<OrderCursor> {
+    match cursor.map(str::parse::<OrderCursor>) {
+        Some(Ok(cursor)) => Some(cursor),
+        Some(Err(violation)) => {
+            violations.push(violation);
+            None
         }
+        None => None,
     }
 }
 
-/// Delete handler for removing latest [Order] item for a specified menu item [Order::menu_item_id] for a specified table number.
-pub async fn delete_menu_item_from_order(
-    data: web::Data<PgSqlOrderRepository>,
-    path: web::Path<(i32, i32)>,
-) -> HttpResponse {
-    let (table_number, menu_item_id) = path.into_inner();
-    let delete_result =
-        delete_order_item_from_table(data.as_ref(), menu_item_id, table_number).await;
+/// Get handler for searching [Order]s across every table, e.g. `?status=ready&item_name=ramen&table_from=10&table_to=20`.
+pub async fn get_orders<R: OrderRepository<ErrT = RepositoryError>>(
+    data: web::Data<R>,
+    query: web::Query<OrderSearchQuery>,
+) -> Result<HttpResponse, AppError> {
+    let query = query.into_inner();
+    let mut search = OrderSearch {
+        menu_item_id: query.menu_item_id,
+        table_from: query.table_from,
+        table_to: query.table_to,
+        created_from: query.created_from,
+        created_to: query.created_to,
+        status: query.status,
+        item_name: query.item_name,
+        sort: query.sort,
+        cursor: None,
+    };
+    let mut violations = validate_order_search(&search, query.limit);
+    search.cursor = parse_cursor(query.cursor.as_deref(), &mut violations);
+    reject_violations(violations)?;
+    let page = search_orders(data.as_ref(), &search, query.limit).await?;
+    Ok(HttpResponse::Ok().json(page))
+}
 
-    match delete_result {
-        Ok(rows_deleted) if rows_deleted > 0 => HttpResponse::Ok().json("Order deleted."),
-        Ok(_) => HttpResponse::NotFound().json("No orders found to delete."), // No rows found to delete
-        Err(_) => HttpResponse::InternalServerError().finish(),
+/// Reads the orders of the session open at a table, or of a past session of the table.
+async fn read_table_orders<R: OrderRepository<ErrT = RepositoryError>>(
+    repo: &R,
+    table_number: i32,
+    session_id: Option<Uuid>,
+) -> Result<Vec<CompleteOrder>, RepositoryError> {
+    match session_id {
+        Some(session_id) => Ok(read_orders_by_session(repo, session_id)
+            .await?
+            .into_iter()
+            .filter(|order| order.table_number == table_number)
+            .collect()),
+        None => read_orders_by_table(repo, table_number).await,
     }
 }
 
-/// Delete handler for removing [Order] by id
-pub async fn delete_order(
-    data: web::Data<PgSqlOrderRepository>,
+/// Get handler for a page of the [Order] items of the session open at a table number,
+/// or of a past session with `?session_id=`. See [TableOrdersQuery] for the filters.
+pub async fn get_table_orders<R: OrderRepository<ErrT = RepositoryError>>(
+    data: web::Data<R>,
+    path: web::Path<i32>,
+    query: web::Query<TableOrdersQuery>,
+) -> Result<HttpResponse, AppError> {
+    let query = query.into_inner();
+    let mut violations = validate_order_listing(query.limit, query.created_from, query.created_to);
+    let cursor = parse_cursor(query.cursor.as_deref(), &mut violations);
+    reject_violations(violations)?;
+    let filter = TableOrdersFilter {
+        session_id: query.session_id,
+        menu_item_id: query.menu_item_id,
+        created_from: query.created_from,
+        created_to: query.created_to,
+        sort: query.sort,
+        cursor,
+    };
+    let page = read_orders_page(data.as_ref(), path.into_inner(), &filter, query.limit).await?;
+    Ok(HttpResponse::Ok().json(page))
+}
+
+/// Get handler for the itemized bill of a table session, optionally split with `?split=` guests.
+pub async fn get_table_bill<R: OrderRepository<ErrT = RepositoryError>>(
+    data: web::Data<R>,
+    config: web::Data<Config>,
+    path: web::Path<i32>,
+    query: web::Query<BillQuery>,
+) -> Result<HttpResponse, AppError> {
+    if let Some(guests) = query.split {
+        reject_violations(validate_split(guests))?;
+    }
+    let table_number = path.into_inner();
+    let orders = read_table_orders(data.as_ref(), table_number, query.session_id).await?;
+    if orders.is_empty() {
+        return Err(AppError::NotFound("No orders to bill.".to_string()));
+    }
+    let bill = build_bill(table_number, &orders, config.billing_rates(), query.split);
+    Ok(HttpResponse::Ok().json(bill))
+}
+
+/// Get handler for how many minutes are left for each [Order] of a table still being prepared.
+pub async fn get_table_remaining_time<R: OrderRepository<ErrT = RepositoryError>>(
+    data: web::Data<R>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, AppError> {
+    let table_num = path.into_inner();
+    let remaining = read_remaining_time_by_table(data.as_ref(), table_num, Utc::now()).await?;
+    Ok(HttpResponse::Ok().json(remaining))
+}
+
+/// Get handler for querying an [Order] by id.
+pub async fn get_order<R: OrderRepository<ErrT = RepositoryError>>(
+    data: web::Data<R>,
+    path: web::Path<Uuid>,
+) -> Result<HttpResponse, AppError> {
+    let order_id = path.into_inner();
+    read_order_by_id(data.as_ref(), order_id)
+        .await?
+        .map(|order| HttpResponse::Ok().json(order))
+        .ok_or_else(|| AppError::NotFound("No order found.".to_string()))
+}
+
+/// Get handler for querying latest [Order] item for a specified menu item [Order::menu_item_id] for a specified table number.
+pub async fn get_order_from_menu_item_and_table<R: OrderRepository<ErrT = RepositoryError>>(
+    data: web::Data<R>,
+    path: web::Path<TableItemKey>,
+) -> Result<HttpResponse, AppError> {
+    read_order_item_from_table(data.as_ref(), path.into_inner())
+        .await?
+        .map(|order| HttpResponse::Ok().json(order))
+        .ok_or_else(|| AppError::NotFound("No order found.".to_string()))
+}
+
+/// Delete handler for cancelling one plate of the latest [Order] item for a specified menu item [Order::menu_item_id]
+/// for a specified table number, with an optional `?reason=` and `?actor=`.
+pub async fn delete_menu_item_from_order<R: OrderRepository<ErrT = RepositoryError>>(
+    data: web::Data<R>,
+    kitchen: web::Data<KitchenScheduler>,
+    events: web::Data<EventBus>,
+    path: web::Path<TableItemKey>,
+    query: web::Query<CancelQuery>,
+) -> Result<HttpResponse, AppError> {
+    let query = query.into_inner();
+    let cancellation = Cancellation {
+        reason: query.reason,
+        actor: query.actor,
+    };
+    reject_violations(validate_cancellation(&cancellation))?;
+    let plate =
+        cancel_order_item_from_table(data.as_ref(), path.into_inner(), &cancellation).await?;
+    refresh_kitchen(&kitchen, data.as_ref()).await;
+    plate
+        .map(|plate| {
+            events.publish(OrderEvent::cancelled(&plate));
+            HttpResponse::Ok().json(plate)
+        })
+        .ok_or_else(|| AppError::NotFound("No orders found to cancel.".to_string()))
+}
+
+/// Patch handler for moving an [Order] to another table or swapping its menu item.
+#[allow(clippy::too_many_arguments)]
+pub async fn patch_order<
+    R: OrderRepository<ErrT = RepositoryError>,
+    M: MenuRepository<ErrT = RepositoryError>,
+    T: TableRepository<ErrT = RepositoryError>,
+>(
+    data: web::Data<R>,
+    menu: web::Data<M>,
+    tables: web::Data<T>,
+    config: web::Data<Config>,
+    kitchen: web::Data<KitchenScheduler>,
+    events: web::Data<EventBus>,
     path: web::Path<Uuid>,
-) -> HttpResponse {
+    form: web::Json<UpdateOrderRequest>,
+) -> Result<HttpResponse, AppError> {
     let order_id = path.into_inner();
-    let delete_result = delete_order_by_id(data.as_ref(), order_id).await;
-    match delete_result {
-        Ok(rows_deleted) if rows_deleted == 1 => HttpResponse::Ok().json("Order deleted."),
-        Ok(rows_deleted) if rows_deleted >= 2 => {
-            HttpResponse::InternalServerError().json("Multiple orders deleted, but shouldn't had been possible... Why multiple orders had same id?")
+    let patch = OrderPatch {
+        table_number: form.table_number,
+        menu_item_id: form.menu_item_id,
+        quantity: form.quantity,
+    };
+    reject_violations(
+        validate_order(
+            menu.as_ref(),
+            tables.as_ref(),
+            &config.table_numbers(),
+            &patch,
+        )
+        .await?,
+    )?;
+    let updated = update_order(data.as_ref(), order_id, &patch).await?;
+    refresh_kitchen(&kitchen, data.as_ref()).await;
+    updated
+        .map(|order| {
+            events.publish(OrderEvent::updated(&order));
+            HttpResponse::Ok().json(order)
+        })
+        .ok_or_else(|| AppError::NotFound("No order found.".to_string()))
+}
+
+/// Post handler for moving an [Order] through its lifecycle, e.g. from `ordered` to `cooking`.
+///
+/// Illegal transitions, like serving a cancelled order, are refused with a 409.
+pub async fn post_order_transition<R: OrderRepository<ErrT = RepositoryError>>(
+    data: web::Data<R>,
+    kitchen: web::Data<KitchenScheduler>,
+    events: web::Data<EventBus>,
+    path: web::Path<Uuid>,
+    form: web::Json<TransitionRequest>,
+) -> Result<HttpResponse, AppError> {
+    let order_id = path.into_inner();
+    let form = form.into_inner();
+    let transitioned = match form.status {
+        OrderStatus::Cancelled => {
+            let cancellation = Cancellation {
+                reason: form.reason,
+                actor: form.actor,
+            };
+            reject_violations(validate_cancellation(&cancellation))?;
+            let cancelled = cancel_order(data.as_ref(), order_id, &cancellation).await?;
+            if let Some(order) = &cancelled {
+                events.publish(OrderEvent::cancelled(order));
+            }
+            cancelled
         }
-        Ok(_) => HttpResponse::NotFound().json("No orders found to delete."), // No rows found to delete
-        Err(_) => HttpResponse::InternalServerError().finish(),
+        status => {
+            let transitioned = transition_order(data.as_ref(), order_id, status).await?;
+            if let Some(order) = &transitioned {
+                events.publish(OrderEvent::status_changed(order));
+            }
+            transitioned
+        }
+    };
+    refresh_kitchen(&kitchen, data.as_ref()).await;
+    transitioned
+        .map(|order| HttpResponse::Ok().json(order))
+        .ok_or_else(|| AppError::NotFound("No order found.".to_string()))
+}
+
+/// Delete handler for cancelling every plate of an [Order] by id, with an optional `?reason=` and `?actor=`.
+///
+/// The order is kept for the audit trail, see [get_cancellations].
+pub async fn delete_order<R: OrderRepository<ErrT = RepositoryError>>(
+    data: web::Data<R>,
+    kitchen: web::Data<KitchenScheduler>,
+    events: web::Data<EventBus>,
+    path: web::Path<Uuid>,
+    query: web::Query<CancelQuery>,
+) -> Result<HttpResponse, AppError> {
+    let query = query.into_inner();
+    let cancellation = Cancellation {
+        reason: query.reason,
+        actor: query.actor,
+    };
+    reject_violations(validate_cancellation(&cancellation))?;
+    let cancelled = cancel_order(data.as_ref(), path.into_inner(), &cancellation).await?;
+    refresh_kitchen(&kitchen, data.as_ref()).await;
+    cancelled
+        .map(|order| {
+            events.publish(OrderEvent::cancelled(&order));
+            HttpResponse::Ok().json(order)
+        })
+        .ok_or_else(|| AppError::NotFound("No orders found to cancel.".to_string()))
+}
+
+/// Get handler for the cancelled [Order]s with their reasons and who cancelled them, the latest first.
+pub async fn get_cancellations<R: OrderRepository<ErrT = RepositoryError>>(
+    data: web::Data<R>,
+    query: web::Query<CancellationsQuery>,
+) -> Result<HttpResponse, AppError> {
+    let query = query.into_inner();
+    let filter = CancellationFilter {
+        table_number: query.table_number,
+        since: query.since,
+        until: query.until,
+    };
+    let cancellations = read_cancellations(data.as_ref(), &filter).await?;
+    Ok(HttpResponse::Ok().json(cancellations))
+}
+
+/// Header an SSE client sends when reconnecting, with the id of the last event it got.
+const LAST_EVENT_ID_HEADER: &str = "Last-Event-ID";
+
+/// The definition of [EventsQuery] which captures the filters of the event stream and where to resume it.
+#[derive(Serialize, Deserialize, Default)]
+pub struct EventsQuery {
+    pub table_number: Option<i32>,
+    pub menu_item_id: Option<i32>,
+    /// Resume after this event, for clients that can't set the `Last-Event-ID` header.
+    pub last_event_id: Option<u64>,
+}
+
+/// Get handler streaming [OrderEvent]s as Server-Sent Events, so kitchen displays don't have to poll.
+///
+/// Events can be filtered by `?table_number=` and `?menu_item_id=`. A client resuming with the
+/// `Last-Event-ID` header, or `?last_event_id=`, first gets the events it missed.
+pub async fn get_events(
+    req: HttpRequest,
+    events: web::Data<EventBus>,
+    query: web::Query<EventsQuery>,
+) -> Result<HttpResponse, AppError> {
+    let last_event_id = match req.headers().get(LAST_EVENT_ID_HEADER) {
+        Some(value) => Some(
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.trim().parse::<u64>().ok())
+                .ok_or_else(|| {
+                    AppError::Validation(vec![FieldViolation::new(
+                        LAST_EVENT_ID_HEADER,
+                        "Last event id must be a whole number.".to_string(),
+                    )])
+                })?,
+        ),
+        None => query.last_event_id,
+    };
+    let filter = EventFilter {
+        table_number: query.table_number,
+        menu_item_id: query.menu_item_id,
+    };
+    let stream = events.events(filter, last_event_id).map(|event| {
+        let message = match event {
+            Some(event) => event.to_sse(),
+            None => ": keep-alive\n\n".to_string(),
+        };
+        Ok::<_, Infallible>(Bytes::from(message))
+    });
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(stream))
+}
+
+/// Get handler upgrading to the kitchen display WebSocket, see `KitchenCommand` for its protocol.
+///
+/// Displays subscribe to live orders and chefs bump them to their next status from the screen.
+pub async fn get_kitchen_socket<R: OrderRepository<ErrT = RepositoryError> + 'static>(
+    req: HttpRequest,
+    body: web::Payload,
+    data: web::Data<R>,
+    kitchen: web::Data<KitchenScheduler>,
+    events: web::Data<EventBus>,
+) -> Result<HttpResponse, AppError> {
+    let (response, session, messages) = actix_ws::handle(&req, body)
+        .map_err(|error| AppError::BadRequest(format!("WebSocket handshake failed: {error}")))?;
+    actix_web::rt::spawn(run_kitchen_session(
+        session, messages, data, kitchen, events,
+    ));
+    Ok(response)
+}
+
+/// Get handler for the kitchen's cooking plan: when every open [Order] starts and is ready, given the cooking slots.
+pub async fn get_kitchen_queue(kitchen: web::Data<KitchenScheduler>) -> HttpResponse {
+    HttpResponse::Ok().json(kitchen.queue(Utc::now()))
+}
+
+/// Get handler for listing the menu. Retired [MenuItem]s are hidden unless `?include_retired=true`.
+pub async fn get_menu_items<M: MenuRepository<ErrT = RepositoryError>>(
+    data: web::Data<M>,
+    query: web::Query<MenuQuery>,
+) -> Result<HttpResponse, AppError> {
+    let items = read_menu_items(data.as_ref(), query.include_retired).await?;
+    Ok(HttpResponse::Ok().json(items))
+}
+
+/// Get handler for querying a single [MenuItem] by id.
+pub async fn get_menu_item<M: MenuRepository<ErrT = RepositoryError>>(
+    data: web::Data<M>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, AppError> {
+    read_menu_item(data.as_ref(), path.into_inner())
+        .await?
+        .map(|item| HttpResponse::Ok().json(item))
+        .ok_or_else(|| AppError::NotFound("No menu item found.".to_string()))
+}
+
+/// Post handler for adding a [MenuItem] to the menu.
+pub async fn post_menu_item<M: MenuRepository<ErrT = RepositoryError>>(
+    data: web::Data<M>,
+    form: web::Json<MenuItemRequest>,
+) -> Result<HttpResponse, AppError> {
+    let form = form.into_inner();
+    let item = NewMenuItem {
+        item_name: form.item_name,
+        cooking_time: form.cooking_time,
+        price: form.price,
+    };
+    reject_violations(validate_menu_item(&MenuItemPatch::from(&item)))?;
+    let item = create_menu_item(data.as_ref(), &item).await?;
+    Ok(HttpResponse::Created().json(item))
+}
+
+/// Patch handler for renaming, repricing or changing the cooking time of a [MenuItem].
+pub async fn patch_menu_item<M: MenuRepository<ErrT = RepositoryError>>(
+    data: web::Data<M>,
+    path: web::Path<i32>,
+    form: web::Json<UpdateMenuItemRequest>,
+) -> Result<HttpResponse, AppError> {
+    let form = form.into_inner();
+    let patch = MenuItemPatch {
+        item_name: form.item_name,
+        cooking_time: form.cooking_time,
+        price: form.price,
+    };
+    reject_violations(validate_menu_item(&patch))?;
+    update_menu_item(data.as_ref(), path.into_inner(), &patch)
+        .await?
+        .map(|item| HttpResponse::Ok().json(item))
+        .ok_or_else(|| AppError::NotFound("No menu item found.".to_string()))
+}
+
+/// Delete handler for retiring a [MenuItem]. The item is kept for past [Order]s but can't be ordered anymore.
+pub async fn delete_menu_item<M: MenuRepository<ErrT = RepositoryError>>(
+    data: web::Data<M>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, AppError> {
+    retire_menu_item(data.as_ref(), path.into_inner())
+        .await?
+        .map(|item| HttpResponse::Ok().json(item))
+        .ok_or_else(|| AppError::NotFound("No menu item found.".to_string()))
+}
+
+/// Get handler for the floor plan, every [RestaurantTable] ordered by table number.
+pub async fn get_tables<T: TableRepository<ErrT = RepositoryError>>(
+    data: web::Data<T>,
+) -> Result<HttpResponse, AppError> {
+    let tables = read_tables(data.as_ref()).await?;
+    Ok(HttpResponse::Ok().json(tables))
+}
+
+/// Get handler for querying a single [RestaurantTable] by its table number.
+pub async fn get_table<T: TableRepository<ErrT = RepositoryError>>(
+    data: web::Data<T>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, AppError> {
+    read_table(data.as_ref(), path.into_inner())
+        .await?
+        .map(|table| HttpResponse::Ok().json(table))
+        .ok_or_else(|| AppError::NotFound("No table found.".to_string()))
+}
+
+/// Put handler for adding a [RestaurantTable] to the floor plan, or changing its seats and section.
+pub async fn put_table<T: TableRepository<ErrT = RepositoryError>>(
+    data: web::Data<T>,
+    config: web::Data<Config>,
+    path: web::Path<i32>,
+    form: web::Json<TableRequest>,
+) -> Result<HttpResponse, AppError> {
+    let form = form.into_inner();
+    let table = RestaurantTable {
+        table_number: path.into_inner(),
+        seats: form.seats,
+        section: form.section,
+    };
+    reject_violations(validate_table(&config.table_numbers(), &table))?;
+    let table = define_table(data.as_ref(), &table).await?;
+    Ok(HttpResponse::Ok().json(table))
+}
+
+/// Delete handler for removing a [RestaurantTable] from the floor plan. Tables with [Order]s are refused with a 409.
+pub async fn delete_table<T: TableRepository<ErrT = RepositoryError>>(
+    data: web::Data<T>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, AppError> {
+    match remove_table(data.as_ref(), path.into_inner()).await? {
+        0 => Err(AppError::NotFound("No table found to delete.".to_string())),
+        _ => Ok(HttpResponse::Ok().json("Table deleted.")),
     }
 }
+
+/// Post handler for seating a party at a table: opens a [TableSession], or sets the party size of the session already open.
+pub async fn post_table_session<T: TableRepository<ErrT = RepositoryError>>(
+    data: web::Data<T>,
+    path: web::Path<i32>,
+    form: web::Json<SessionRequest>,
+) -> Result<HttpResponse, AppError> {
+    reject_violations(validate_party_size(form.party_size))?;
+    let session = open_table_session(
+        data.as_ref(),
+        path.into_inner(),
+        form.party_size,
+        Utc::now(),
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(session))
+}
+
+/// Get handler for every [TableSession] of a table, the latest first.
+pub async fn get_table_sessions<T: TableRepository<ErrT = RepositoryError>>(
+    data: web::Data<T>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, AppError> {
+    let sessions: Vec<TableSession> = read_table_sessions(data.as_ref(), path.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(sessions))
+}
+
+/// Get handler for the [TableSession] open at a table.
+pub async fn get_current_table_session<T: TableRepository<ErrT = RepositoryError>>(
+    data: web::Data<T>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, AppError> {
+    read_current_session(data.as_ref(), path.into_inner())
+        .await?
+        .map(|session| HttpResponse::Ok().json(session))
+        .ok_or_else(|| AppError::NotFound("No open session.".to_string()))
+}
+
+/// Post handler for closing the [TableSession] open at a table when the guests leave, refused while its bill isn't paid.
+pub async fn post_close_table_session<T: TableRepository<ErrT = RepositoryError>>(
+    tables: web::Data<T>,
+    config: web::Data<Config>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, AppError> {
+    close_table_session(
+        tables.as_ref(),
+        path.into_inner(),
+        config.billing_rates(),
+        Utc::now(),
+    )
+    .await?
+    .map(|session| HttpResponse::Ok().json(session))
+    .ok_or_else(|| AppError::NotFound("No open session to close.".to_string()))
+}
+
+/// Post handler for recording a payment, possibly partial, against the bill of the session open at a table.
+pub async fn post_table_payment<R, T, P>(
+    data: web::Data<R>,
+    tables: web::Data<T>,
+    payments: web::Data<P>,
+    config: web::Data<Config>,
+    path: web::Path<i32>,
+    form: web::Json<PaymentRequest>,
+) -> Result<HttpResponse, AppError>
+where
+    R: OrderRepository<ErrT = RepositoryError>,
+    T: TableRepository<ErrT = RepositoryError>,
+    P: PaymentRepository<ErrT = RepositoryError>,
+{
+    reject_violations(validate_amount(form.amount))?;
+    let payment = record_payment(
+        data.as_ref(),
+        tables.as_ref(),
+        payments.as_ref(),
+        path.into_inner(),
+        form.tender,
+        form.amount,
+        config.billing_rates(),
+    )
+    .await?;
+    Ok(HttpResponse::Created().json(payment))
+}
+
+/// Get handler for the payments of the session open at a table, or of a past session with `?session_id=`,
+/// with what's left to pay.
+pub async fn get_table_payments<R, T, P>(
+    data: web::Data<R>,
+    tables: web::Data<T>,
+    payments: web::Data<P>,
+    config: web::Data<Config>,
+    path: web::Path<i32>,
+    query: web::Query<PaymentsQuery>,
+) -> Result<HttpResponse, AppError>
+where
+    R: OrderRepository<ErrT = RepositoryError>,
+    T: TableRepository<ErrT = RepositoryError>,
+    P: PaymentRepository<ErrT = RepositoryError>,
+{
+    read_session_payments(
+        data.as_ref(),
+        tables.as_ref(),
+        payments.as_ref(),
+        path.into_inner(),
+        query.session_id,
+        config.billing_rates(),
+    )
+    .await?
+    .map(|session_payments| HttpResponse::Ok().json(session_payments))
+    .ok_or_else(|| AppError::NotFound("No session found.".to_string()))
+}
+
+/// Post handler for giving back part or all of a payment.
+pub async fn post_payment_refund<P: PaymentRepository<ErrT = RepositoryError>>(
+    payments: web::Data<P>,
+    path: web::Path<Uuid>,
+    form: web::Json<RefundRequest>,
+) -> Result<HttpResponse, AppError> {
+    reject_violations(validate_amount(form.amount))?;
+    refund_payment(payments.as_ref(), path.into_inner(), form.amount)
+        .await?
+        .map(|refund| HttpResponse::Created().json(refund))
+        .ok_or_else(|| AppError::NotFound("No payment found to refund.".to_string()))
+}
+
+/// Post handler for settling the bill of the session open at a table, refused until payments cover the total.
+pub async fn post_table_settlement<T, P>(
+    tables: web::Data<T>,
+    payments: web::Data<P>,
+    config: web::Data<Config>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, AppError>
+where
+    T: TableRepository<ErrT = RepositoryError>,
+    P: PaymentRepository<ErrT = RepositoryError>,
+{
+    let session = settle_table(
+        tables.as_ref(),
+        payments.as_ref(),
+        path.into_inner(),
+        config.billing_rates(),
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(session))
+}
@@ -1,40 +1,392 @@
-use crate::domain::{CompleteOrder, Order};
+use crate::billing::{balance, build_bill, check_covered, BillingRates};
+use crate::constants::{
+    DEFAULT_MAX_TABLE_NUMBER, DEFAULT_MIN_TABLE_NUMBER, DEFAULT_TABLE_SEATS, DEFAULT_TABLE_SECTION,
+    FOOD_ITEMS,
+};
+use crate::domain::{
+    Cancellation, CancellationFilter, CompleteOrder, IdempotencyKeyReused, IdempotencyRecord,
+    IllegalTransition, MenuItem, MenuItemPatch, NewMenuItem, Order, OrderPatch, OrderSearch,
+    OrderSort, OrderStatus, Payment, PaymentError, PaymentKind, RestaurantTable, SyncOutcome,
+    TableItemKey, TableOrdersFilter, TableSession, Tender,
+};
 use async_trait::async_trait;
-use sqlx::PgPool;
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use sqlx::{PgConnection, PgPool};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
 use uuid::Uuid;
 
+/// Name of the foreign key from `orders` to the floor plan, see the `tables` migration.
+const ORDERS_TABLE_NUMBER_FKEY: &str = "orders_table_number_fkey";
+
+/// Name of the foreign key from `table_sessions` to the floor plan, see the `table_sessions` migration.
+const TABLE_SESSIONS_TABLE_NUMBER_FKEY: &str = "table_sessions_table_number_fkey";
+
+/// Common error type for [OrderRepository], [MenuRepository], [TableRepository] and [PaymentRepository] implementations, so every storage backend maps to HTTP the same way.
+#[derive(Debug)]
+pub enum RepositoryError {
+    /// The [Order::menu_item_id] doesn't reference a registered [MenuItem::id], or the item was retired.
+    MenuItemNotFound(i32),
+    /// The [Order::table_number] doesn't reference a [RestaurantTable] of the floor plan.
+    TableNotFound(i32),
+    /// The [RestaurantTable] still has [Order]s, so it can't be removed from the floor plan.
+    TableInUse(i32),
+    /// The [Order::quantity] isn't a positive number of plates.
+    InvalidQuantity(i32),
+    /// A different [Order] is already stored under this [Order::id].
+    OrderIdConflict(Uuid),
+    /// The [Order] can't move between these [OrderStatus]es.
+    IllegalTransition(IllegalTransition),
+    /// The `Idempotency-Key` was already used to create a different [Order].
+    IdempotencyKeyReused(IdempotencyKeyReused),
+    /// The [Payment] or settlement is refused because of the bill of the [TableSession].
+    Payment(PaymentError),
+    /// The [TableSession] at this table is closed, so its bill can't change anymore.
+    SessionClosed(i32),
+    /// Any other error from the PostgreSQL driver.
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for RepositoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepositoryError::MenuItemNotFound(id) => write!(f, "Menu item {} doesn't exist.", id),
+            RepositoryError::TableNotFound(number) => {
+                write!(f, "Table {} isn't on the floor plan.", number)
+            }
+            RepositoryError::TableInUse(number) => {
+                write!(f, "Table {} still has orders.", number)
+            }
+            RepositoryError::InvalidQuantity(quantity) => {
+                write!(f, "Quantity must be at least 1, got {}.", quantity)
+            }
+            RepositoryError::OrderIdConflict(id) => {
+                write!(f, "A different order is already stored as {}.", id)
+            }
+            RepositoryError::IllegalTransition(IllegalTransition { from, to }) => {
+                write!(f, "Order can't go from {} to {}.", from, to)
+            }
+            RepositoryError::IdempotencyKeyReused(IdempotencyKeyReused { key }) => {
+                write!(f, "Idempotency key '{}' was used for another order.", key)
+            }
+            RepositoryError::Payment(error) => write!(f, "{}", error),
+            RepositoryError::SessionClosed(number) => {
+                write!(f, "The session at table {} is closed.", number)
+            }
+            RepositoryError::Database(error) => write!(f, "Database error: {}", error),
+        }
+    }
+}
+
+impl From<sqlx::Error> for RepositoryError {
+    fn from(error: sqlx::Error) -> Self {
+        RepositoryError::Database(error)
+    }
+}
+
+impl From<IllegalTransition> for RepositoryError {
+    fn from(transition: IllegalTransition) -> Self {
+        RepositoryError::IllegalTransition(transition)
+    }
+}
+
+impl From<IdempotencyKeyReused> for RepositoryError {
+    fn from(error: IdempotencyKeyReused) -> Self {
+        RepositoryError::IdempotencyKeyReused(error)
+    }
+}
+
+impl From<PaymentError> for RepositoryError {
+    fn from(error: PaymentError) -> Self {
+        RepositoryError::Payment(error)
+    }
+}
+
+impl RepositoryError {
+    /// Maps constraint violations when writing to `orders`: the foreign keys on `table_number` and `menu_item_id`
+    /// to [RepositoryError::TableNotFound] and [RepositoryError::MenuItemNotFound], and the check on `quantity`
+    /// to [RepositoryError::InvalidQuantity].
+    fn from_order_write(
+        error: sqlx::Error,
+        table_number: i32,
+        menu_item_id: i32,
+        quantity: i32,
+    ) -> Self {
+        match error.as_database_error() {
+            Some(db_error)
+                if db_error.is_foreign_key_violation()
+                    && db_error.constraint() == Some(ORDERS_TABLE_NUMBER_FKEY) =>
+            {
+                RepositoryError::TableNotFound(table_number)
+            }
+            Some(db_error) if db_error.is_foreign_key_violation() => {
+                RepositoryError::MenuItemNotFound(menu_item_id)
+            }
+            Some(db_error) if db_error.is_check_violation() => {
+                RepositoryError::InvalidQuantity(quantity)
+            }
+            _ => RepositoryError::Database(error),
+        }
+    }
+
+    /// Maps the foreign key on `table_number` when writing to `table_sessions` to [RepositoryError::TableNotFound].
+    fn from_session_write(error: sqlx::Error, table_number: i32) -> Self {
+        match error.as_database_error() {
+            Some(db_error)
+                if db_error.is_foreign_key_violation()
+                    && db_error.constraint() == Some(TABLE_SESSIONS_TABLE_NUMBER_FKEY) =>
+            {
+                RepositoryError::TableNotFound(table_number)
+            }
+            _ => RepositoryError::Database(error),
+        }
+    }
+}
+
+impl std::error::Error for RepositoryError {}
+
 #[async_trait]
 pub trait OrderRepository {
     type ErrT;
 
     /// CREATE - Store the item [Order] with the table number and how long the item will take to cook.
+    ///
+    /// The order joins the [TableSession] open at its table, which is opened if needed.
+    /// Storing the same order twice under its [Order::id] is a no-op, a different order under it is
+    /// [RepositoryError::OrderIdConflict]. Orders are compared as they were first stored.
     async fn create(&self, order: &Order) -> Result<Uuid, Self::ErrT>;
 
-    /// READ - Show all [Order] items for a specified table number
+    /// CREATE - Store several [Order]s atomically: if any of them is rejected, none is stored.
+    ///
+    /// Returns the [SyncOutcome] of each order, in the same order: like [Self::sync], orders already
+    /// stored under their id, or earlier in the batch, are [SyncOutcome::Unchanged].
+    async fn create_many(&self, orders: &[Order]) -> Result<Vec<SyncOutcome>, Self::ErrT>;
+
+    /// CREATE - Store an [Order] like [Self::create], telling whether it was already stored.
+    async fn sync(&self, order: &Order) -> Result<SyncOutcome, Self::ErrT>;
+
+    /// CREATE - Store an [Order] along with the [IdempotencyRecord] of its key, unless the key was used since `since`.
+    ///
+    /// Returns the record holding the key: the given one if the order was stored, the earlier one otherwise.
+    /// Like [Self::sync], the outcome tells whether the order was already stored under its id, it's
+    /// [SyncOutcome::Unchanged] when the key was held by an earlier order.
+    async fn create_once(
+        &self,
+        order: &Order,
+        record: &IdempotencyRecord,
+        since: DateTime<Utc>,
+    ) -> Result<(IdempotencyRecord, SyncOutcome), Self::ErrT>;
+
+    /// READ - Query the [IdempotencyRecord] of a key, if it was used since `since`.
+    async fn read_idempotency_key(
+        &self,
+        key: &str,
+        since: DateTime<Utc>,
+    ) -> Result<Option<IdempotencyRecord>, Self::ErrT>;
+
+    /// READ - Show all [Order] items of the [TableSession] open at a specified table number.
     async fn read_orders_by_table(
         &self,
         table_number: i32,
     ) -> Result<Vec<CompleteOrder>, Self::ErrT>;
 
-    /// READ - Query latest [Order] item for a specified menu item [Order::menu_item_id] for a specified table number.
-    async fn read_order_item_from_table(
+    /// READ - Show at most `limit` [Order] items of a table passing the [TableOrdersFilter], in its [OrderSort].
+    async fn read_orders_page(
         &self,
-        menu_item_id: i32,
         table_number: i32,
+        filter: &TableOrdersFilter,
+        limit: i64,
+    ) -> Result<Vec<CompleteOrder>, Self::ErrT>;
+
+    /// READ - Find at most `limit` [Order] items across every table passing the [OrderSearch], in its [OrderSort].
+    async fn search(
+        &self,
+        search: &OrderSearch,
+        limit: i64,
+    ) -> Result<Vec<CompleteOrder>, Self::ErrT>;
+
+    /// READ - Show all [Order] items of a [TableSession], open or closed, oldest first.
+    async fn read_orders_by_session(
+        &self,
+        session_id: Uuid,
+    ) -> Result<Vec<CompleteOrder>, Self::ErrT>;
+
+    /// READ - Show all [Order]s still waiting for or being cooked, oldest first.
+    async fn read_open_orders(&self) -> Result<Vec<CompleteOrder>, Self::ErrT>;
+
+    /// READ - Query an [Order] by [Order::id].
+    async fn read_order_by_id(&self, order_id: Uuid) -> Result<Option<CompleteOrder>, Self::ErrT>;
+
+    /// READ - Query latest [Order] item for a menu item in the session open at a table, see [TableItemKey].
+    ///
+    /// The latest is the last [Order::created_at], ties broken by the highest [Order::id],
+    /// the same order [Self::cancel_plate] takes a plate off. Orders of closed sessions are
+    /// left out, their bills are final.
+    async fn read_order_item_from_table(
+        &self,
+        key: TableItemKey,
+    ) -> Result<Option<CompleteOrder>, Self::ErrT>;
+
+    /// UPDATE - Apply an [OrderPatch] to an [Order] still [OrderStatus::Ordered], keeping its [Order::created_at].
+    ///
+    /// Returns `None` if the order doesn't exist or isn't ordered anymore. Fails with
+    /// [RepositoryError::SessionClosed] if the session of the order, or the one it moves to, is closed.
+    async fn update_order(
+        &self,
+        order_id: Uuid,
+        patch: &OrderPatch,
+    ) -> Result<Option<CompleteOrder>, Self::ErrT>;
+
+    /// UPDATE - Move an [Order] from the `from` status to the `to` status and record the transition time.
+    ///
+    /// Returns `None` if the order doesn't exist or isn't in the `from` status anymore.
+    /// Legality of the transition is checked by the domain, see [OrderStatus::can_transition_to].
+    async fn update_status(
+        &self,
+        order_id: Uuid,
+        from: OrderStatus,
+        to: OrderStatus,
+        at: DateTime<Utc>,
+    ) -> Result<Option<CompleteOrder>, Self::ErrT>;
+
+    /// UPDATE - Cancel every plate of an [Order] in the `from` status, recording the [Cancellation].
+    ///
+    /// The order is kept but left out of the listings, see [Self::read_cancellations].
+    /// Returns `None` if the order doesn't exist or isn't in the `from` status anymore.
+    /// Fails with [RepositoryError::SessionClosed] if the session of the order is closed.
+    async fn cancel_order(
+        &self,
+        order_id: Uuid,
+        from: OrderStatus,
+        cancellation: &Cancellation,
+        at: DateTime<Utc>,
     ) -> Result<Option<CompleteOrder>, Self::ErrT>;
 
-    /// UPDATE - Not implemented. For this simple API, updates are done by removing and creating new [Order]s.
-    async fn update_order(&self) -> Result<(), Self::ErrT>;
+    /// UPDATE - Cancel one plate of an [Order] in the `from` status, recording the [Cancellation].
+    ///
+    /// The last plate cancels the order itself. Otherwise [Order::quantity] is decremented and the plate
+    /// is split off into a cancelled [Order] of its own, so every cancelled plate keeps its reason.
+    /// Returns the cancelled order, or `None` if the order doesn't exist or isn't in the `from` status anymore.
+    /// Fails with [RepositoryError::SessionClosed] if the session of the order is closed.
+    async fn cancel_plate(
+        &self,
+        order_id: Uuid,
+        from: OrderStatus,
+        cancellation: &Cancellation,
+        at: DateTime<Utc>,
+    ) -> Result<Option<CompleteOrder>, Self::ErrT>;
+
+    /// READ - Show cancelled [Order]s passing the [CancellationFilter], the latest cancelled first.
+    async fn read_cancellations(
+        &self,
+        filter: &CancellationFilter,
+    ) -> Result<Vec<CompleteOrder>, Self::ErrT>;
+}
+
+#[async_trait]
+pub trait MenuRepository {
+    type ErrT;
+
+    /// CREATE - Add a [MenuItem] to the menu with a generated id.
+    async fn create_menu_item(&self, item: &NewMenuItem) -> Result<MenuItem, Self::ErrT>;
+
+    /// READ - Show all [MenuItem]s ordered by id, optionally including retired ones.
+    async fn read_menu_items(&self, include_retired: bool) -> Result<Vec<MenuItem>, Self::ErrT>;
+
+    /// READ - Query a [MenuItem] by id, retired or not.
+    async fn read_menu_item(&self, menu_item_id: i32) -> Result<Option<MenuItem>, Self::ErrT>;
 
-    /// DELETE - Remove latest [Order] item for a specified menu item [Order::menu_item_id] for a specified table number.
-    async fn delete_order_item_from_table(
+    /// UPDATE - Apply a [MenuItemPatch] to a [MenuItem]. Returns `None` if the item doesn't exist.
+    async fn update_menu_item(
         &self,
         menu_item_id: i32,
+        patch: &MenuItemPatch,
+    ) -> Result<Option<MenuItem>, Self::ErrT>;
+
+    /// DELETE - Retire a [MenuItem] so it can't be ordered anymore. Returns `None` if the item doesn't exist.
+    ///
+    /// Items are never removed, since past [Order]s still reference them.
+    async fn retire_menu_item(&self, menu_item_id: i32) -> Result<Option<MenuItem>, Self::ErrT>;
+}
+
+#[async_trait]
+pub trait TableRepository {
+    type ErrT;
+
+    /// READ - Show the floor plan, every [RestaurantTable] ordered by table number.
+    async fn read_tables(&self) -> Result<Vec<RestaurantTable>, Self::ErrT>;
+
+    /// READ - Query a [RestaurantTable] by its table number.
+    async fn read_table(&self, table_number: i32) -> Result<Option<RestaurantTable>, Self::ErrT>;
+
+    /// UPSERT - Add a [RestaurantTable] to the floor plan, or change its seats and section if it's already there.
+    async fn upsert_table(&self, table: &RestaurantTable) -> Result<RestaurantTable, Self::ErrT>;
+
+    /// DELETE - Remove a [RestaurantTable] from the floor plan.
+    ///
+    /// Fails with [RepositoryError::TableInUse] if [Order]s or [TableSession]s still reference it.
+    async fn delete_table(&self, table_number: i32) -> Result<u64, Self::ErrT>;
+
+    /// CREATE - Open a [TableSession] for a party at a table, or set the party size of the session already open.
+    async fn open_session(
+        &self,
+        table_number: i32,
+        party_size: i32,
+        at: DateTime<Utc>,
+    ) -> Result<TableSession, Self::ErrT>;
+
+    /// READ - Query the [TableSession] open at a table.
+    async fn read_current_session(
+        &self,
         table_number: i32,
-    ) -> Result<u64, Self::ErrT>;
+    ) -> Result<Option<TableSession>, Self::ErrT>;
+
+    /// READ - Show every [TableSession] of a table, the latest first.
+    async fn read_sessions(&self, table_number: i32) -> Result<Vec<TableSession>, Self::ErrT>;
+
+    /// UPDATE - Close a [TableSession] once its bill, built with `rates`, is paid.
+    ///
+    /// Returns `None` if the session isn't open anymore. Refused with [PaymentError::NotCovered]
+    /// while something is due, checked while no payment or order can be added to the session.
+    async fn close_session(
+        &self,
+        session_id: Uuid,
+        rates: BillingRates,
+        at: DateTime<Utc>,
+    ) -> Result<Option<TableSession>, Self::ErrT>;
+}
+
+#[async_trait]
+pub trait PaymentRepository {
+    type ErrT;
+
+    /// CREATE - Record a [Payment] or refund against the bill of a [TableSession], as long as the session
+    /// still has the `recorded` payments and refunds it was checked against.
+    /// Returns `None` if others were recorded in the meantime, and fails if the session is closed.
+    async fn create_payment(
+        &self,
+        payment: &Payment,
+        recorded: usize,
+    ) -> Result<Option<Payment>, Self::ErrT>;
+
+    /// READ - Show every [Payment] and refund of a [TableSession], oldest first.
+    async fn read_payments(&self, session_id: Uuid) -> Result<Vec<Payment>, Self::ErrT>;
 
-    /// DELETE - Remove by [Order::id]
-    async fn delete_order_by_id(&self, order_id: Uuid) -> Result<u64, Self::ErrT>;
+    /// READ - Query a [Payment] or refund by its id.
+    async fn read_payment(&self, payment_id: Uuid) -> Result<Option<Payment>, Self::ErrT>;
+
+    /// UPDATE - Mark the bill of a [TableSession], built with `rates`, as settled and close the session.
+    ///
+    /// Returns `None` if the session isn't open anymore. Refused with [PaymentError::NotCovered]
+    /// while something is due, checked while no payment or order can be added to the session.
+    async fn settle_session(
+        &self,
+        session_id: Uuid,
+        rates: BillingRates,
+        at: DateTime<Utc>,
+    ) -> Result<Option<TableSession>, Self::ErrT>;
 }
 
 #[derive(Clone)]
@@ -46,23 +398,96 @@ impl PgSqlOrderRepository {
     pub fn new(pool: PgPool) -> Self {
         PgSqlOrderRepository { pool }
     }
+
+    /// The connections the repository writes with, also used to listen to the order changes they notify.
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
 }
 
 #[async_trait]
 impl OrderRepository for PgSqlOrderRepository {
-    type ErrT = sqlx::Error;
+    type ErrT = RepositoryError;
 
     async fn create(&self, order: &Order) -> Result<Uuid, Self::ErrT> {
-        sqlx::query!(
-            "INSERT INTO orders (id, table_number, menu_item_id, created_at) VALUES ($1, $2, $3, $4)",
-            order.id,
-            order.table_number,
-            order.menu_item_id,
-            order.created_at,
+        self.sync(order).await?;
+        Ok(order.id)
+    }
+
+    async fn create_many(&self, orders: &[Order]) -> Result<Vec<SyncOutcome>, Self::ErrT> {
+        let mut tx = self.pool.begin().await?;
+        let mut outcomes = Vec::with_capacity(orders.len());
+        for order in orders {
+            // Returning early drops the transaction, which rolls back every insert so far.
+            outcomes.push(insert_order(&mut tx, order).await?);
+        }
+        tx.commit().await?;
+        Ok(outcomes)
+    }
+
+    async fn sync(&self, order: &Order) -> Result<SyncOutcome, Self::ErrT> {
+        let mut tx = self.pool.begin().await?;
+        let outcome = insert_order(&mut tx, order).await?;
+        tx.commit().await?;
+        Ok(outcome)
+    }
+
+    async fn create_once(
+        &self,
+        order: &Order,
+        record: &IdempotencyRecord,
+        since: DateTime<Utc>,
+    ) -> Result<(IdempotencyRecord, SyncOutcome), Self::ErrT> {
+        let mut tx = self.pool.begin().await?;
+        let outcome = insert_order(&mut tx, order).await?;
+        // A concurrent request with the same key waits here until the first one commits, then finds its key.
+        let stored = sqlx::query_as!(
+            IdempotencyRecord,
+            "INSERT INTO idempotency_keys (key, fingerprint, order_id, created_at) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (key) DO UPDATE
+             SET fingerprint = EXCLUDED.fingerprint, order_id = EXCLUDED.order_id, created_at = EXCLUDED.created_at
+             WHERE idempotency_keys.created_at < $5
+             RETURNING key, fingerprint, order_id, created_at",
+            record.key,
+            record.fingerprint,
+            record.order_id,
+            record.created_at,
+            since,
         )
-        .execute(&self.pool)
+        .fetch_optional(&mut *tx)
         .await?;
-        Ok(order.id)
+        match stored {
+            Some(stored) => {
+                tx.commit().await?;
+                Ok((stored, outcome))
+            }
+            None => {
+                // The key is still in use: drop the order and answer with the one created first.
+                tx.rollback().await?;
+                let earlier = self.read_idempotency_key(&record.key, since).await?;
+                Ok((
+                    earlier.ok_or(sqlx::Error::RowNotFound)?,
+                    SyncOutcome::Unchanged,
+                ))
+            }
+        }
+    }
+
+    async fn read_idempotency_key(
+        &self,
+        key: &str,
+        since: DateTime<Utc>,
+    ) -> Result<Option<IdempotencyRecord>, Self::ErrT> {
+        sqlx::query_as!(
+            IdempotencyRecord,
+            "SELECT key, fingerprint, order_id, created_at FROM idempotency_keys
+             WHERE key = $1 AND created_at >= $2",
+            key,
+            since,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(RepositoryError::from)
     }
 
     async fn read_orders_by_table(
@@ -71,64 +496,1776 @@ impl OrderRepository for PgSqlOrderRepository {
     ) -> Result<Vec<CompleteOrder>, Self::ErrT> {
         sqlx::query_as!(
             CompleteOrder,
-            r#"SELECT orders.id as "order_id", table_number, menu_item_id, created_at, item_name, cooking_time
+            r#"SELECT orders.id as "order_id", table_number, session_id, menu_item_id, created_at, quantity,
+                status as "status: OrderStatus", cooking_at, ready_at, served_at, cancelled_at, cancel_reason, cancelled_by,
+                item_name, cooking_time, price, NULL::TIMESTAMPTZ as "estimated_ready_at"
+            FROM orders
+            INNER JOIN menu_items ON orders.menu_item_id = menu_items.id
+            WHERE orders.session_id = (
+              SELECT id FROM table_sessions WHERE table_number = $1 AND closed_at IS NULL
+            )
+              AND orders.status <> 'cancelled'
+            ORDER BY created_at, orders.id
+            "#,
+            table_number
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(RepositoryError::from)
+    }
+
+    async fn read_orders_page(
+        &self,
+        table_number: i32,
+        filter: &TableOrdersFilter,
+        limit: i64,
+    ) -> Result<Vec<CompleteOrder>, Self::ErrT> {
+        let by_cooking_time = filter.sort == OrderSort::CookingTime;
+        let cursor = filter.cursor.as_ref();
+        // The sort key is the same for every row when sorting by creation, so the keyset only compares the tie-breakers.
+        sqlx::query_as!(
+            CompleteOrder,
+            r#"SELECT orders.id as "order_id", table_number, session_id, menu_item_id, created_at, quantity,
+                status as "status: OrderStatus", cooking_at, ready_at, served_at, cancelled_at, cancel_reason, cancelled_by,
+                item_name, cooking_time, price, NULL::TIMESTAMPTZ as "estimated_ready_at"
             FROM orders
             INNER JOIN menu_items ON orders.menu_item_id = menu_items.id
             WHERE orders.table_number = $1
-            "#, table_number
+              AND orders.status <> 'cancelled'
+              AND orders.session_id = COALESCE($2, (
+                SELECT id FROM table_sessions WHERE table_number = $1 AND closed_at IS NULL
+              ))
+              AND ($3::INT IS NULL OR orders.menu_item_id = $3)
+              AND ($4::TIMESTAMPTZ IS NULL OR orders.created_at >= $4)
+              AND ($5::TIMESTAMPTZ IS NULL OR orders.created_at < $5)
+              AND ($7::UUID IS NULL OR
+                (CASE WHEN $6 THEN cooking_time ELSE 0 END, orders.created_at, orders.id)
+                  > (CASE WHEN $6 THEN $8::INT ELSE 0 END, $9::TIMESTAMPTZ, $7::UUID))
+            ORDER BY CASE WHEN $6 THEN cooking_time ELSE 0 END, orders.created_at, orders.id
+            LIMIT $10
+            "#,
+            table_number,
+            filter.session_id,
+            filter.menu_item_id,
+            filter.created_from,
+            filter.created_to,
+            by_cooking_time,
+            cursor.map(|cursor| cursor.order_id),
+            cursor.map(|cursor| cursor.cooking_time),
+            cursor.map(|cursor| cursor.created_at),
+            limit,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(RepositoryError::from)
+    }
+
+    async fn search(
+        &self,
+        search: &OrderSearch,
+        limit: i64,
+    ) -> Result<Vec<CompleteOrder>, Self::ErrT> {
+        let by_cooking_time = search.sort == OrderSort::CookingTime;
+        let cursor = search.cursor.as_ref();
+        // Wildcards typed by the user are matched literally.
+        let item_name = search.item_name.as_ref().map(|name| {
+            name.replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        });
+        sqlx::query_as!(
+            CompleteOrder,
+            r#"SELECT orders.id as "order_id", table_number, session_id, menu_item_id, created_at, quantity,
+                status as "status: OrderStatus", cooking_at, ready_at, served_at, cancelled_at, cancel_reason, cancelled_by,
+                item_name, cooking_time, price, NULL::TIMESTAMPTZ as "estimated_ready_at"
+            FROM orders
+            INNER JOIN menu_items ON orders.menu_item_id = menu_items.id
+            WHERE ($1::INT IS NULL OR orders.menu_item_id = $1)
+              AND ($2::INT IS NULL OR orders.table_number >= $2)
+              AND ($3::INT IS NULL OR orders.table_number <= $3)
+              AND ($4::TIMESTAMPTZ IS NULL OR orders.created_at >= $4)
+              AND ($5::TIMESTAMPTZ IS NULL OR orders.created_at < $5)
+              AND (orders.status = $6 OR ($6::order_status IS NULL AND orders.status <> 'cancelled'))
+              AND ($7::TEXT IS NULL OR item_name ILIKE '%' || $7 || '%')
+              AND ($9::UUID IS NULL OR
+                (CASE WHEN $8 THEN cooking_time ELSE 0 END, orders.created_at, orders.id)
+                  > (CASE WHEN $8 THEN $10::INT ELSE 0 END, $11::TIMESTAMPTZ, $9::UUID))
+            ORDER BY CASE WHEN $8 THEN cooking_time ELSE 0 END, orders.created_at, orders.id
+            LIMIT $12
+            "#,
+            search.menu_item_id,
+            search.table_from,
+            search.table_to,
+            search.created_from,
+            search.created_to,
+            search.status as Option<OrderStatus>,
+            item_name,
+            by_cooking_time,
+            cursor.map(|cursor| cursor.order_id),
+            cursor.map(|cursor| cursor.cooking_time),
+            cursor.map(|cursor| cursor.created_at),
+            limit,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(RepositoryError::from)
+    }
+
+    async fn read_orders_by_session(
+        &self,
+        session_id: Uuid,
+    ) -> Result<Vec<CompleteOrder>, Self::ErrT> {
+        session_orders(&mut *self.pool.acquire().await?, session_id).await
+    }
+
+    async fn read_open_orders(&self) -> Result<Vec<CompleteOrder>, Self::ErrT> {
+        sqlx::query_as!(
+            CompleteOrder,
+            r#"SELECT orders.id as "order_id", table_number, session_id, menu_item_id, created_at, quantity,
+                status as "status: OrderStatus", cooking_at, ready_at, served_at, cancelled_at, cancel_reason, cancelled_by,
+                item_name, cooking_time, price, NULL::TIMESTAMPTZ as "estimated_ready_at"
+            FROM orders
+            INNER JOIN menu_items ON orders.menu_item_id = menu_items.id
+            WHERE orders.status IN ('ordered', 'cooking')
+            ORDER BY created_at, orders.id
+            "#
         )
         .fetch_all(&self.pool)
         .await
+        .map_err(RepositoryError::from)
+    }
+
+    async fn read_order_by_id(&self, order_id: Uuid) -> Result<Option<CompleteOrder>, Self::ErrT> {
+        sqlx::query_as!(
+            CompleteOrder,
+            r#"SELECT orders.id as "order_id", table_number, session_id, menu_item_id, created_at, quantity,
+                status as "status: OrderStatus", cooking_at, ready_at, served_at, cancelled_at, cancel_reason, cancelled_by,
+                item_name, cooking_time, price, NULL::TIMESTAMPTZ as "estimated_ready_at"
+            FROM orders
+            INNER JOIN menu_items ON orders.menu_item_id = menu_items.id
+            WHERE orders.id = $1
+            "#,
+            order_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(RepositoryError::from)
     }
 
     async fn read_order_item_from_table(
         &self,
-        menu_item_id: i32,
-        table_number: i32,
+        key: TableItemKey,
     ) -> Result<Option<CompleteOrder>, Self::ErrT> {
         sqlx::query_as!(
             CompleteOrder,
-            r#"SELECT orders.id as "order_id", table_number, menu_item_id, created_at, item_name, cooking_time
+            r#"SELECT orders.id as "order_id", table_number, session_id, menu_item_id, created_at, quantity,
+                status as "status: OrderStatus", cooking_at, ready_at, served_at, cancelled_at, cancel_reason, cancelled_by,
+                item_name, cooking_time, price, NULL::TIMESTAMPTZ as "estimated_ready_at"
             FROM orders
             INNER JOIN menu_items ON orders.menu_item_id = menu_items.id
-            WHERE orders.menu_item_id = $1 AND orders.table_number = $2
-            "#, 
-            menu_item_id,
-            table_number
+            WHERE orders.session_id = (
+              SELECT id FROM table_sessions WHERE table_number = $1 AND closed_at IS NULL
+            )
+              AND orders.menu_item_id = $2
+              AND orders.status <> 'cancelled'
+            ORDER BY orders.created_at DESC, orders.id DESC
+            LIMIT 1
+            "#,
+            key.table_number,
+            key.menu_item_id,
         )
         .fetch_optional(&self.pool)
         .await
+        .map_err(RepositoryError::from)
     }
 
-    async fn update_order(&self) -> Result<(), Self::ErrT> {
-        Ok(())
+    async fn update_order(
+        &self,
+        order_id: Uuid,
+        patch: &OrderPatch,
+    ) -> Result<Option<CompleteOrder>, Self::ErrT> {
+        let mut tx = self.pool.begin().await?;
+        if let Some(menu_item_id) = patch.menu_item_id {
+            let orderable = sqlx::query_scalar!(
+                r#"SELECT EXISTS (SELECT 1 FROM menu_items WHERE id = $1 AND retired_at IS NULL) as "exists!""#,
+                menu_item_id
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            if !orderable {
+                return Err(RepositoryError::MenuItemNotFound(menu_item_id));
+            }
+        }
+        let Some(current) = sqlx::query!(
+            "SELECT table_number, session_id FROM orders WHERE id = $1 AND status = 'ordered' FOR UPDATE",
+            order_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            return Ok(None);
+        };
+        // The bill of a closed session is final, the order can't leave it.
+        if let Some(session_id) = current.session_id {
+            lock_open_session(&mut tx, session_id, current.table_number).await?;
+        }
+        // A moved order joins the session of its new table.
+        let session_id = match patch.table_number {
+            Some(table_number) => {
+                let session_id = current_session_id(&mut tx, table_number, Utc::now()).await?;
+                lock_open_session(&mut tx, session_id, table_number).await?;
+                Some(session_id)
+            }
+            None => None,
+        };
+        let order = sqlx::query_as!(
+            CompleteOrder,
+            r#"WITH updated AS (
+              UPDATE orders
+              SET table_number = COALESCE($2, table_number),
+                  menu_item_id = COALESCE($3, menu_item_id),
+                  quantity = COALESCE($4, quantity),
+                  session_id = COALESCE($5, session_id)
+              WHERE id = $1 AND status = 'ordered'
+              RETURNING *
+            )
+            SELECT updated.id as "order_id", table_number, session_id, menu_item_id, created_at, quantity,
+                status as "status: OrderStatus", cooking_at, ready_at, served_at, cancelled_at, cancel_reason, cancelled_by,
+                item_name, cooking_time, price, NULL::TIMESTAMPTZ as "estimated_ready_at"
+            FROM updated
+            INNER JOIN menu_items ON updated.menu_item_id = menu_items.id
+            "#,
+            order_id,
+            patch.table_number,
+            patch.menu_item_id,
+            patch.quantity,
+            session_id,
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|error| {
+            RepositoryError::from_order_write(
+                error,
+                patch.table_number.unwrap_or_default(),
+                patch.menu_item_id.unwrap_or_default(),
+                patch.quantity.unwrap_or_default(),
+            )
+        })?;
+        // Don't leave a session opened for an order that doesn't exist.
+        if order.is_some() {
+            tx.commit().await?;
+        }
+        Ok(order)
     }
 
-    async fn delete_order_item_from_table(
+    async fn update_status(
         &self,
-        menu_item_id: i32,
-        table_number: i32,
-    ) -> Result<u64, Self::ErrT> {
-        let rows_deleted = sqlx::query!(
-            "DELETE FROM orders WHERE id IN (
-              SELECT id FROM orders
-              WHERE table_number = $1 AND menu_item_id = $2
-              ORDER BY created_at DESC LIMIT 1
-             )",
-            table_number,
-            menu_item_id,
+        order_id: Uuid,
+        from: OrderStatus,
+        to: OrderStatus,
+        at: DateTime<Utc>,
+    ) -> Result<Option<CompleteOrder>, Self::ErrT> {
+        sqlx::query_as!(
+            CompleteOrder,
+            r#"WITH updated AS (
+              UPDATE orders
+              SET status = $3,
+                  cooking_at = CASE WHEN $3 = 'cooking'::order_status THEN $4 ELSE cooking_at END,
+                  ready_at = CASE WHEN $3 = 'ready'::order_status THEN $4 ELSE ready_at END,
+                  served_at = CASE WHEN $3 = 'served'::order_status THEN $4 ELSE served_at END,
+                  cancelled_at = CASE WHEN $3 = 'cancelled'::order_status THEN $4 ELSE cancelled_at END
+              WHERE id = $1 AND status = $2
+              RETURNING *
+            )
+            SELECT updated.id as "order_id", table_number, session_id, menu_item_id, created_at, quantity,
+                status as "status: OrderStatus", cooking_at, ready_at, served_at, cancelled_at, cancel_reason, cancelled_by,
+                item_name, cooking_time, price, NULL::TIMESTAMPTZ as "estimated_ready_at"
+            FROM updated
+            INNER JOIN menu_items ON updated.menu_item_id = menu_items.id
+            "#,
+            order_id,
+            from as OrderStatus,
+            to as OrderStatus,
+            at,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(RepositoryError::from)
+    }
+
+    async fn cancel_order(
+        &self,
+        order_id: Uuid,
+        from: OrderStatus,
+        cancellation: &Cancellation,
+        at: DateTime<Utc>,
+    ) -> Result<Option<CompleteOrder>, Self::ErrT> {
+        let mut tx = self.pool.begin().await?;
+        if !lock_order_session(&mut tx, order_id, from).await? {
+            return Ok(None);
+        }
+        let order = sqlx::query_as!(
+            CompleteOrder,
+            r#"WITH cancelled AS (
+              UPDATE orders
+              SET status = 'cancelled', cancelled_at = $3, cancel_reason = $4, cancelled_by = $5
+              WHERE id = $1 AND status = $2
+              RETURNING *
+            )
+            SELECT cancelled.id as "order_id", table_number, session_id, menu_item_id, created_at, quantity,
+                status as "status: OrderStatus", cooking_at, ready_at, served_at, cancelled_at, cancel_reason, cancelled_by,
+                item_name, cooking_time, price, NULL::TIMESTAMPTZ as "estimated_ready_at"
+            FROM cancelled
+            INNER JOIN menu_items ON cancelled.menu_item_id = menu_items.id
+            "#,
+            order_id,
+            from as OrderStatus,
+            at,
+            cancellation.reason,
+            cancellation.actor,
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(order)
+    }
+
+    async fn cancel_plate(
+        &self,
+        order_id: Uuid,
+        from: OrderStatus,
+        cancellation: &Cancellation,
+        at: DateTime<Utc>,
+    ) -> Result<Option<CompleteOrder>, Self::ErrT> {
+        let mut tx = self.pool.begin().await?;
+        if !lock_order_session(&mut tx, order_id, from).await? {
+            return Ok(None);
+        }
+        // Either split one plate off into a cancelled order, or cancel the order when it's the last plate.
+        let plate = sqlx::query_as!(
+            CompleteOrder,
+            r#"WITH target AS (
+              SELECT * FROM orders WHERE id = $1 AND status = $2 FOR UPDATE
+            ), decremented AS (
+              UPDATE orders SET quantity = orders.quantity - 1
+              FROM target WHERE orders.id = target.id AND target.quantity > 1
+            ), split AS (
+              INSERT INTO orders (id, table_number, session_id, menu_item_id, created_at, quantity, status,
+                                  cooking_at, ready_at, served_at, cancelled_at, cancel_reason, cancelled_by)
+              SELECT $6, table_number, session_id, menu_item_id, created_at, 1, 'cancelled',
+                     cooking_at, ready_at, served_at, $3, $4, $5
+              FROM target WHERE target.quantity > 1
+              RETURNING *
+            ), cancelled AS (
+              UPDATE orders
+              SET status = 'cancelled', cancelled_at = $3, cancel_reason = $4, cancelled_by = $5
+              FROM target WHERE orders.id = target.id AND target.quantity = 1
+              RETURNING orders.*
+            ), plate AS (
+              SELECT * FROM split UNION ALL SELECT * FROM cancelled
+            )
+            SELECT plate.id as "order_id!", table_number as "table_number!", session_id,
+                menu_item_id as "menu_item_id!", created_at as "created_at!", quantity as "quantity!",
+                status as "status!: OrderStatus", cooking_at, ready_at, served_at, cancelled_at, cancel_reason, cancelled_by,
+                item_name, cooking_time, price, NULL::TIMESTAMPTZ as "estimated_ready_at"
+            FROM plate
+            INNER JOIN menu_items ON plate.menu_item_id = menu_items.id
+            "#,
+            order_id,
+            from as OrderStatus,
+            at,
+            cancellation.reason,
+            cancellation.actor,
+            Uuid::new_v4(),
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(plate)
+    }
+
+    async fn read_cancellations(
+        &self,
+        filter: &CancellationFilter,
+    ) -> Result<Vec<CompleteOrder>, Self::ErrT> {
+        sqlx::query_as!(
+            CompleteOrder,
+            r#"SELECT orders.id as "order_id", table_number, session_id, menu_item_id, created_at, quantity,
+                status as "status: OrderStatus", cooking_at, ready_at, served_at, cancelled_at, cancel_reason, cancelled_by,
+                item_name, cooking_time, price, NULL::TIMESTAMPTZ as "estimated_ready_at"
+            FROM orders
+            INNER JOIN menu_items ON orders.menu_item_id = menu_items.id
+            WHERE orders.status = 'cancelled'
+              AND ($1::INT IS NULL OR orders.table_number = $1)
+              AND ($2::TIMESTAMPTZ IS NULL OR orders.cancelled_at >= $2)
+              AND ($3::TIMESTAMPTZ IS NULL OR orders.cancelled_at < $3)
+            ORDER BY orders.cancelled_at DESC, orders.id DESC
+            "#,
+            filter.table_number,
+            filter.since,
+            filter.until,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(RepositoryError::from)
+    }
+}
+
+/// Inserts an [Order] as long as its menu item exists and isn't retired, attaching it to the session open at its table.
+async fn insert_order(
+    conn: &mut PgConnection,
+    order: &Order,
+) -> Result<SyncOutcome, RepositoryError> {
+    if let Some(outcome) = stored_outcome(conn, order).await? {
+        return Ok(outcome);
+    }
+    let session_id = current_session_id(conn, order.table_number, order.created_at).await?;
+    let rows_inserted = sqlx::query!(
+        "INSERT INTO orders (id, table_number, menu_item_id, created_at, quantity, status, session_id, fingerprint)
+         SELECT $1, $2, id, $4, $5, $6, $7, $8 FROM menu_items WHERE id = $3 AND retired_at IS NULL
+         ON CONFLICT (id) DO NOTHING",
+        order.id,
+        order.table_number,
+        order.menu_item_id,
+        order.created_at,
+        order.quantity,
+        order.status as OrderStatus,
+        session_id,
+        order.fingerprint(),
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|error| {
+        RepositoryError::from_order_write(
+            error,
+            order.table_number,
+            order.menu_item_id,
+            order.quantity,
         )
-        .execute(&self.pool)
+    })?
+    .rows_affected();
+    if rows_inserted > 0 {
+        return Ok(SyncOutcome::Created);
+    }
+    // Either the menu item can't be ordered, or a concurrent request stored the id in the meantime.
+    stored_outcome(conn, order)
         .await?
-        .rows_affected();
-        Ok(rows_deleted)
+        .ok_or(RepositoryError::MenuItemNotFound(order.menu_item_id))
+}
+
+/// [SyncOutcome::Unchanged] if the [Order] is already stored under its id, `None` if nothing is.
+async fn stored_outcome(
+    conn: &mut PgConnection,
+    order: &Order,
+) -> Result<Option<SyncOutcome>, RepositoryError> {
+    let stored = sqlx::query!("SELECT fingerprint FROM orders WHERE id = $1", order.id)
+        .fetch_optional(&mut *conn)
+        .await?;
+    stored
+        .map(|stored| same_order(order, stored.fingerprint.as_deref()))
+        .transpose()
+}
+
+/// Compares an [Order] with the one first stored under its id: the same table, menu item and quantity make it a no-op.
+///
+/// Plates split off by a cancellation have no fingerprint, no client sent them.
+fn same_order(order: &Order, fingerprint: Option<&str>) -> Result<SyncOutcome, RepositoryError> {
+    if fingerprint == Some(order.fingerprint().as_str()) {
+        Ok(SyncOutcome::Unchanged)
+    } else {
+        Err(RepositoryError::OrderIdConflict(order.id))
     }
+}
 
-    async fn delete_order_by_id(&self, order_id: Uuid) -> Result<u64, Self::ErrT> {
-        let rows_deleted = sqlx::query!("DELETE FROM orders WHERE id = $1", order_id,)
-            .execute(&self.pool)
-            .await?
-            .rows_affected();
-        Ok(rows_deleted)
+/// Id of the [TableSession] open at a table, opening one without a party size if there's none.
+///
+/// The session is kept from being closed until the transaction ends, so its bill can't be settled
+/// without the orders attached to it.
+async fn current_session_id(
+    conn: &mut PgConnection,
+    table_number: i32,
+    at: DateTime<Utc>,
+) -> Result<Uuid, RepositoryError> {
+    loop {
+        sqlx::query!(
+            "INSERT INTO table_sessions (id, table_number, opened_at) VALUES ($1, $2, $3)
+             ON CONFLICT (table_number) WHERE closed_at IS NULL DO NOTHING",
+            Uuid::new_v4(),
+            table_number,
+            at,
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|error| RepositoryError::from_session_write(error, table_number))?;
+        // A session closed before it could be locked leaves the table free for the next one.
+        let session_id = sqlx::query_scalar!(
+            "SELECT id FROM table_sessions WHERE table_number = $1 AND closed_at IS NULL FOR SHARE",
+            table_number,
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+        if let Some(session_id) = session_id {
+            return Ok(session_id);
+        }
+    }
+}
+
+/// Keeps a [TableSession] from being closed until the transaction ends, failing if it already is.
+async fn lock_open_session(
+    conn: &mut PgConnection,
+    session_id: Uuid,
+    table_number: i32,
+) -> Result<(), RepositoryError> {
+    let closed_at = sqlx::query_scalar!(
+        "SELECT closed_at FROM table_sessions WHERE id = $1 FOR SHARE",
+        session_id,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    match closed_at {
+        Some(_) => Err(RepositoryError::SessionClosed(table_number)),
+        None => Ok(()),
+    }
+}
+
+/// Locks an open [TableSession] until the transaction ends, so no payment or order can be added to it,
+/// and checks its bill, built with `rates`, is paid.
+///
+/// Returns `false` if the session isn't open anymore, fails with [PaymentError::NotCovered] while something is due.
+async fn lock_covered_session(
+    conn: &mut PgConnection,
+    session_id: Uuid,
+    rates: BillingRates,
+) -> Result<bool, RepositoryError> {
+    let session = sqlx::query!(
+        "SELECT table_number FROM table_sessions WHERE id = $1 AND closed_at IS NULL FOR UPDATE",
+        session_id,
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    let Some(session) = session else {
+        return Ok(false);
+    };
+    let orders = session_orders(conn, session_id).await?;
+    let payments = session_payments(conn, session_id).await?;
+    let bill = build_bill(session.table_number, &orders, rates, None);
+    check_covered(balance(&bill, &payments))?;
+    Ok(true)
+}
+
+/// The [Order]s of a [TableSession] that aren't cancelled, oldest first.
+async fn session_orders(
+    conn: &mut PgConnection,
+    session_id: Uuid,
+) -> Result<Vec<CompleteOrder>, RepositoryError> {
+    let orders = sqlx::query_as!(
+        CompleteOrder,
+        r#"SELECT orders.id as "order_id", table_number, session_id, menu_item_id, created_at, quantity,
+            status as "status: OrderStatus", cooking_at, ready_at, served_at, cancelled_at, cancel_reason, cancelled_by,
+            item_name, cooking_time, price, NULL::TIMESTAMPTZ as "estimated_ready_at"
+        FROM orders
+        INNER JOIN menu_items ON orders.menu_item_id = menu_items.id
+        WHERE orders.session_id = $1 AND orders.status <> 'cancelled'
+        ORDER BY created_at, orders.id
+        "#,
+        session_id
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(orders)
+}
+
+/// Every [Payment] and refund of a [TableSession], oldest first.
+async fn session_payments(
+    conn: &mut PgConnection,
+    session_id: Uuid,
+) -> Result<Vec<Payment>, RepositoryError> {
+    let payments = sqlx::query_as!(
+        Payment,
+        r#"SELECT id, session_id, kind as "kind: PaymentKind", tender as "tender: Tender",
+                  amount, tendered, change, refund_of, created_at
+           FROM payments
+           WHERE session_id = $1
+           ORDER BY created_at"#,
+        session_id,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(payments)
+}
+
+/// Locks an [Order] in the `from` status and keeps its [TableSession] from being closed until the transaction ends.
+///
+/// Returns `false` if there's no such order, fails with [RepositoryError::SessionClosed] if its session is closed.
+async fn lock_order_session(
+    conn: &mut PgConnection,
+    order_id: Uuid,
+    from: OrderStatus,
+) -> Result<bool, RepositoryError> {
+    let Some(order) = sqlx::query!(
+        "SELECT table_number, session_id FROM orders WHERE id = $1 AND status = $2 FOR UPDATE",
+        order_id,
+        from as OrderStatus,
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    else {
+        return Ok(false);
+    };
+    if let Some(session_id) = order.session_id {
+        lock_open_session(conn, session_id, order.table_number).await?;
+    }
+    Ok(true)
+}
+
+#[derive(Clone)]
+pub struct PgSqlMenuRepository {
+    pool: PgPool,
+}
+
+impl PgSqlMenuRepository {
+    pub fn new(pool: PgPool) -> Self {
+        PgSqlMenuRepository { pool }
+    }
+}
+
+#[async_trait]
+impl MenuRepository for PgSqlMenuRepository {
+    type ErrT = RepositoryError;
+
+    async fn create_menu_item(&self, item: &NewMenuItem) -> Result<MenuItem, Self::ErrT> {
+        let item = sqlx::query_as!(
+            MenuItem,
+            "INSERT INTO menu_items (item_name, cooking_time, price) VALUES ($1, $2, $3)
+             RETURNING id, item_name, cooking_time, price, retired_at",
+            item.item_name,
+            item.cooking_time,
+            item.price,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(item)
+    }
+
+    async fn read_menu_items(&self, include_retired: bool) -> Result<Vec<MenuItem>, Self::ErrT> {
+        let items = sqlx::query_as!(
+            MenuItem,
+            "SELECT id, item_name, cooking_time, price, retired_at FROM menu_items
+             WHERE $1 OR retired_at IS NULL
+             ORDER BY id",
+            include_retired,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(items)
+    }
+
+    async fn read_menu_item(&self, menu_item_id: i32) -> Result<Option<MenuItem>, Self::ErrT> {
+        let item = sqlx::query_as!(
+            MenuItem,
+            "SELECT id, item_name, cooking_time, price, retired_at FROM menu_items WHERE id = $1",
+            menu_item_id,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(item)
+    }
+
+    async fn update_menu_item(
+        &self,
+        menu_item_id: i32,
+        patch: &MenuItemPatch,
+    ) -> Result<Option<MenuItem>, Self::ErrT> {
+        let item = sqlx::query_as!(
+            MenuItem,
+            "UPDATE menu_items
+             SET item_name = COALESCE($2, item_name),
+                 cooking_time = COALESCE($3, cooking_time),
+                 price = COALESCE($4, price)
+             WHERE id = $1
+             RETURNING id, item_name, cooking_time, price, retired_at",
+            menu_item_id,
+            patch.item_name,
+            patch.cooking_time,
+            patch.price,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(item)
+    }
+
+    async fn retire_menu_item(&self, menu_item_id: i32) -> Result<Option<MenuItem>, Self::ErrT> {
+        let item = sqlx::query_as!(
+            MenuItem,
+            "UPDATE menu_items SET retired_at = COALESCE(retired_at, $2)
+             WHERE id = $1
+             RETURNING id, item_name, cooking_time, price, retired_at",
+            menu_item_id,
+            Utc::now(),
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(item)
+    }
+}
+
+#[derive(Clone)]
+pub struct PgSqlTableRepository {
+    pool: PgPool,
+}
+
+impl PgSqlTableRepository {
+    pub fn new(pool: PgPool) -> Self {
+        PgSqlTableRepository { pool }
+    }
+}
+
+#[async_trait]
+impl TableRepository for PgSqlTableRepository {
+    type ErrT = RepositoryError;
+
+    async fn read_tables(&self) -> Result<Vec<RestaurantTable>, Self::ErrT> {
+        let tables = sqlx::query_as!(
+            RestaurantTable,
+            "SELECT table_number, seats, section FROM tables ORDER BY table_number"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(tables)
+    }
+
+    async fn read_table(&self, table_number: i32) -> Result<Option<RestaurantTable>, Self::ErrT> {
+        let table = sqlx::query_as!(
+            RestaurantTable,
+            "SELECT table_number, seats, section FROM tables WHERE table_number = $1",
+            table_number,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(table)
+    }
+
+    async fn upsert_table(&self, table: &RestaurantTable) -> Result<RestaurantTable, Self::ErrT> {
+        let table = sqlx::query_as!(
+            RestaurantTable,
+            "INSERT INTO tables (table_number, seats, section) VALUES ($1, $2, $3)
+             ON CONFLICT (table_number) DO UPDATE SET seats = EXCLUDED.seats, section = EXCLUDED.section
+             RETURNING table_number, seats, section",
+            table.table_number,
+            table.seats,
+            table.section,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(table)
+    }
+
+    async fn delete_table(&self, table_number: i32) -> Result<u64, Self::ErrT> {
+        let rows_deleted = sqlx::query!("DELETE FROM tables WHERE table_number = $1", table_number)
+            .execute(&self.pool)
+            .await
+            .map_err(|error| match error.as_database_error() {
+                Some(db_error) if db_error.is_foreign_key_violation() => {
+                    RepositoryError::TableInUse(table_number)
+                }
+                _ => RepositoryError::Database(error),
+            })?
+            .rows_affected();
+        Ok(rows_deleted)
+    }
+
+    async fn open_session(
+        &self,
+        table_number: i32,
+        party_size: i32,
+        at: DateTime<Utc>,
+    ) -> Result<TableSession, Self::ErrT> {
+        sqlx::query_as!(
+            TableSession,
+            "INSERT INTO table_sessions (id, table_number, party_size, opened_at) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (table_number) WHERE closed_at IS NULL DO UPDATE SET party_size = EXCLUDED.party_size
+             RETURNING id, table_number, party_size, opened_at, closed_at, settled_at",
+            Uuid::new_v4(),
+            table_number,
+            party_size,
+            at,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|error| RepositoryError::from_session_write(error, table_number))
+    }
+
+    async fn read_current_session(
+        &self,
+        table_number: i32,
+    ) -> Result<Option<TableSession>, Self::ErrT> {
+        let session = sqlx::query_as!(
+            TableSession,
+            "SELECT id, table_number, party_size, opened_at, closed_at, settled_at FROM table_sessions
+             WHERE table_number = $1 AND closed_at IS NULL",
+            table_number,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(session)
+    }
+
+    async fn read_sessions(&self, table_number: i32) -> Result<Vec<TableSession>, Self::ErrT> {
+        let sessions = sqlx::query_as!(
+            TableSession,
+            "SELECT id, table_number, party_size, opened_at, closed_at, settled_at FROM table_sessions
+             WHERE table_number = $1
+             ORDER BY opened_at DESC",
+            table_number,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(sessions)
+    }
+
+    async fn close_session(
+        &self,
+        session_id: Uuid,
+        rates: BillingRates,
+        at: DateTime<Utc>,
+    ) -> Result<Option<TableSession>, Self::ErrT> {
+        let mut tx = self.pool.begin().await?;
+        if !lock_covered_session(&mut tx, session_id, rates).await? {
+            return Ok(None);
+        }
+        let session = sqlx::query_as!(
+            TableSession,
+            "UPDATE table_sessions SET closed_at = $2
+             WHERE id = $1
+             RETURNING id, table_number, party_size, opened_at, closed_at, settled_at",
+            session_id,
+            at,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(Some(session))
+    }
+}
+
+#[derive(Clone)]
+pub struct PgSqlPaymentRepository {
+    pool: PgPool,
+}
+
+impl PgSqlPaymentRepository {
+    pub fn new(pool: PgPool) -> Self {
+        PgSqlPaymentRepository { pool }
+    }
+}
+
+#[async_trait]
+impl PaymentRepository for PgSqlPaymentRepository {
+    type ErrT = RepositoryError;
+
+    async fn create_payment(
+        &self,
+        payment: &Payment,
+        recorded: usize,
+    ) -> Result<Option<Payment>, Self::ErrT> {
+        let mut tx = self.pool.begin().await?;
+        // Payments of a session are recorded one at a time, each against the ones before it.
+        let session = sqlx::query!(
+            "SELECT table_number, closed_at FROM table_sessions WHERE id = $1 FOR UPDATE",
+            payment.session_id,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if session.closed_at.is_some() {
+            return Err(RepositoryError::SessionClosed(session.table_number));
+        }
+        let count = sqlx::query_scalar!(
+            r#"SELECT count(*) as "count!" FROM payments WHERE session_id = $1"#,
+            payment.session_id,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if count != recorded as i64 {
+            return Ok(None);
+        }
+        let payment = sqlx::query_as!(
+            Payment,
+            r#"INSERT INTO payments (id, session_id, kind, tender, amount, tendered, change, refund_of, created_at)
+               VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+               RETURNING id, session_id, kind as "kind: PaymentKind", tender as "tender: Tender",
+                         amount, tendered, change, refund_of, created_at"#,
+            payment.id,
+            payment.session_id,
+            payment.kind as PaymentKind,
+            payment.tender as Tender,
+            payment.amount,
+            payment.tendered,
+            payment.change,
+            payment.refund_of,
+            payment.created_at,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(Some(payment))
+    }
+
+    async fn read_payments(&self, session_id: Uuid) -> Result<Vec<Payment>, Self::ErrT> {
+        session_payments(&mut *self.pool.acquire().await?, session_id).await
+    }
+
+    async fn read_payment(&self, payment_id: Uuid) -> Result<Option<Payment>, Self::ErrT> {
+        let payment = sqlx::query_as!(
+            Payment,
+            r#"SELECT id, session_id, kind as "kind: PaymentKind", tender as "tender: Tender",
+                      amount, tendered, change, refund_of, created_at
+               FROM payments
+               WHERE id = $1"#,
+            payment_id,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(payment)
+    }
+
+    async fn settle_session(
+        &self,
+        session_id: Uuid,
+        rates: BillingRates,
+        at: DateTime<Utc>,
+    ) -> Result<Option<TableSession>, Self::ErrT> {
+        let mut tx = self.pool.begin().await?;
+        if !lock_covered_session(&mut tx, session_id, rates).await? {
+            return Ok(None);
+        }
+        let session = sqlx::query_as!(
+            TableSession,
+            "UPDATE table_sessions SET closed_at = $2, settled_at = $2
+             WHERE id = $1
+             RETURNING id, table_number, party_size, opened_at, closed_at, settled_at",
+            session_id,
+            at,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(Some(session))
+    }
+}
+
+/// Thread-safe [OrderRepository], [MenuRepository], [TableRepository] and [PaymentRepository] kept in memory, for tests and local runs without PostgreSQL.
+///
+/// Clones share the same storage, just like clones of a [PgPool] share the same database.
+#[derive(Clone)]
+pub struct InMemoryOrderRepository {
+    state: Arc<Mutex<InMemoryState>>,
+}
+
+#[derive(Default)]
+struct InMemoryState {
+    orders: Vec<Order>,
+    menu_items: HashMap<i32, MenuItem>,
+    tables: BTreeMap<i32, RestaurantTable>,
+    sessions: Vec<TableSession>,
+    payments: Vec<Payment>,
+    idempotency_keys: HashMap<String, IdempotencyRecord>,
+    /// [Order::fingerprint] of the orders as first stored, like the 'fingerprint' column.
+    fingerprints: HashMap<Uuid, String>,
+}
+
+impl InMemoryOrderRepository {
+    /// Creates an empty repository with the menu loaded from [FOOD_ITEMS],
+    /// the same way `setup_pg_db` initializes the 'menu_items' table.
+    pub fn new() -> Self {
+        let mut rng = rand::thread_rng();
+        let menu_items = FOOD_ITEMS
+            .iter()
+            .enumerate()
+            .map(|(i, item)| MenuItem {
+                id: i as i32,
+                item_name: item.to_string(),
+                cooking_time: rng.gen_range(5..15),
+                price: rng.gen_range(5..30) * 100,
+                retired_at: None,
+            })
+            .collect();
+        Self::with_menu(menu_items)
+    }
+
+    /// Creates an empty repository with a custom menu and the default floor plan,
+    /// the same way `setup_pg_db` initializes the 'tables' table.
+    pub fn with_menu(menu_items: Vec<MenuItem>) -> Self {
+        let tables = (DEFAULT_MIN_TABLE_NUMBER..=DEFAULT_MAX_TABLE_NUMBER)
+            .map(|table_number| {
+                let table = RestaurantTable {
+                    table_number,
+                    seats: DEFAULT_TABLE_SEATS,
+                    section: DEFAULT_TABLE_SECTION.to_string(),
+                };
+                (table_number, table)
+            })
+            .collect();
+        let state = InMemoryState {
+            orders: vec![],
+            menu_items: menu_items.into_iter().map(|item| (item.id, item)).collect(),
+            tables,
+            sessions: vec![],
+            payments: vec![],
+            idempotency_keys: HashMap::new(),
+            fingerprints: HashMap::new(),
+        };
+        InMemoryOrderRepository {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, InMemoryState> {
+        self.state
+            .lock()
+            .expect("in-memory repository lock poisoned")
+    }
+}
+
+impl Default for InMemoryOrderRepository {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InMemoryState {
+    /// Joins an [Order] with its [MenuItem], like the INNER JOIN of [PgSqlOrderRepository].
+    fn complete(&self, order: &Order) -> Option<CompleteOrder> {
+        self.menu_items
+            .get(&order.menu_item_id)
+            .map(|item| CompleteOrder {
+                order_id: order.id,
+                table_number: order.table_number,
+                session_id: order.session_id,
+                menu_item_id: order.menu_item_id,
+                created_at: order.created_at,
+                quantity: order.quantity,
+                status: order.status,
+                cooking_at: order.cooking_at,
+                ready_at: order.ready_at,
+                served_at: order.served_at,
+                cancelled_at: order.cancelled_at,
+                cancel_reason: order.cancel_reason.clone(),
+                cancelled_by: order.cancelled_by.clone(),
+                item_name: item.item_name.clone(),
+                cooking_time: item.cooking_time,
+                price: item.price,
+                estimated_ready_at: None,
+            })
+    }
+
+    /// Whether new [Order]s can reference this menu item.
+    fn is_orderable(&self, menu_item_id: i32) -> bool {
+        self.menu_items
+            .get(&menu_item_id)
+            .is_some_and(|item| item.retired_at.is_none())
+    }
+
+    /// Checks the table and menu item of a new [Order], like the foreign keys of the 'orders' table.
+    fn check_order(&self, order: &Order) -> Result<(), RepositoryError> {
+        if !self.tables.contains_key(&order.table_number) {
+            return Err(RepositoryError::TableNotFound(order.table_number));
+        }
+        if !self.is_orderable(order.menu_item_id) {
+            return Err(RepositoryError::MenuItemNotFound(order.menu_item_id));
+        }
+        if order.quantity < 1 {
+            return Err(RepositoryError::InvalidQuantity(order.quantity));
+        }
+        Ok(())
+    }
+
+    /// [SyncOutcome::Unchanged] if the [Order] is already stored under its id, `None` if nothing is.
+    fn stored_outcome(&self, order: &Order) -> Result<Option<SyncOutcome>, RepositoryError> {
+        self.orders
+            .iter()
+            .find(|stored| stored.id == order.id)
+            .map(|stored| same_order(order, self.fingerprints.get(&stored.id).map(String::as_str)))
+            .transpose()
+    }
+
+    /// The [IdempotencyRecord] of a key used since `since`, older ones can be replaced.
+    fn idempotency_record(&self, key: &str, since: DateTime<Utc>) -> Option<&IdempotencyRecord> {
+        self.idempotency_keys
+            .get(key)
+            .filter(|record| record.created_at >= since)
+    }
+
+    /// The [TableSession] open at a table, like the partial unique index on 'table_sessions'.
+    fn current_session(&mut self, table_number: i32) -> Option<&mut TableSession> {
+        self.sessions
+            .iter_mut()
+            .find(|session| session.table_number == table_number && session.closed_at.is_none())
+    }
+
+    /// Id of the [TableSession] open at a table, opening one without a party size if there's none.
+    fn current_session_id(&mut self, table_number: i32, at: DateTime<Utc>) -> Uuid {
+        if let Some(session) = self.current_session(table_number) {
+            return session.id;
+        }
+        let session = TableSession {
+            id: Uuid::new_v4(),
+            table_number,
+            party_size: None,
+            opened_at: at,
+            closed_at: None,
+            settled_at: None,
+        };
+        self.sessions.push(session.clone());
+        session.id
+    }
+
+    /// Stores a checked [Order], attached to the session open at its table.
+    fn insert(&mut self, order: &Order) {
+        let session_id = self.current_session_id(order.table_number, order.created_at);
+        self.fingerprints.insert(order.id, order.fingerprint());
+        self.orders.push(Order {
+            session_id: Some(session_id),
+            ..order.clone()
+        });
+    }
+
+    /// The open [TableSession] with the id, like [PgSqlPaymentRepository] checks it before closing it.
+    ///
+    /// Fails with [PaymentError::NotCovered] while its bill, built with `rates`, isn't paid.
+    fn covered_session(
+        &mut self,
+        session_id: Uuid,
+        rates: BillingRates,
+    ) -> Result<Option<&mut TableSession>, RepositoryError> {
+        let Some(table_number) = self
+            .sessions
+            .iter()
+            .find(|session| session.id == session_id && session.closed_at.is_none())
+            .map(|session| session.table_number)
+        else {
+            return Ok(None);
+        };
+        let orders: Vec<CompleteOrder> = self
+            .orders
+            .iter()
+            .filter(|order| {
+                order.session_id == Some(session_id) && order.status != OrderStatus::Cancelled
+            })
+            .filter_map(|order| self.complete(order))
+            .collect();
+        let payments: Vec<Payment> = self
+            .payments
+            .iter()
+            .filter(|payment| payment.session_id == session_id)
+            .cloned()
+            .collect();
+        check_covered(balance(
+            &build_bill(table_number, &orders, rates, None),
+            &payments,
+        ))?;
+        Ok(self
+            .sessions
+            .iter_mut()
+            .find(|session| session.id == session_id))
+    }
+
+    /// Fails with [RepositoryError::SessionClosed] if the session of an [Order] is closed, its bill is final.
+    fn check_open_session(&self, order: &Order) -> Result<(), RepositoryError> {
+        let closed = self
+            .sessions
+            .iter()
+            .any(|session| Some(session.id) == order.session_id && session.closed_at.is_some());
+        if closed {
+            return Err(RepositoryError::SessionClosed(order.table_number));
+        }
+        Ok(())
+    }
+
+    /// Index of the latest created [Order] for a menu item in the session open at a table,
+    /// ties broken by id like [PgSqlOrderRepository].
+    fn latest_index(&self, key: TableItemKey) -> Option<usize> {
+        let session_id = self
+            .sessions
+            .iter()
+            .find(|session| {
+                session.table_number == key.table_number && session.closed_at.is_none()
+            })?
+            .id;
+        self.orders
+            .iter()
+            .enumerate()
+            .filter(|(_, order)| {
+                order.session_id == Some(session_id)
+                    && order.menu_item_id == key.menu_item_id
+                    && order.status != OrderStatus::Cancelled
+            })
+            .max_by_key(|(_, order)| (order.created_at, order.id))
+            .map(|(i, _)| i)
+    }
+}
+
+#[async_trait]
+impl OrderRepository for InMemoryOrderRepository {
+    type ErrT = RepositoryError;
+
+    async fn create(&self, order: &Order) -> Result<Uuid, Self::ErrT> {
+        self.sync(order).await?;
+        Ok(order.id)
+    }
+
+    async fn create_many(&self, orders: &[Order]) -> Result<Vec<SyncOutcome>, Self::ErrT> {
+        let mut state = self.lock();
+        // Check the whole batch before storing anything, like a rolled back transaction.
+        for (index, order) in orders.iter().enumerate() {
+            let earlier = orders[..index]
+                .iter()
+                .find(|earlier| earlier.id == order.id);
+            match earlier {
+                Some(earlier) => {
+                    same_order(order, Some(earlier.fingerprint().as_str()))?;
+                }
+                None if state.stored_outcome(order)?.is_none() => state.check_order(order)?,
+                None => {}
+            }
+        }
+        let mut outcomes = Vec::with_capacity(orders.len());
+        for order in orders {
+            outcomes.push(match state.stored_outcome(order)? {
+                Some(outcome) => outcome,
+                None => {
+                    state.insert(order);
+                    SyncOutcome::Created
+                }
+            });
+        }
+        Ok(outcomes)
+    }
+
+    async fn sync(&self, order: &Order) -> Result<SyncOutcome, Self::ErrT> {
+        let mut state = self.lock();
+        if let Some(outcome) = state.stored_outcome(order)? {
+            return Ok(outcome);
+        }
+        state.check_order(order)?;
+        state.insert(order);
+        Ok(SyncOutcome::Created)
+    }
+
+    async fn create_once(
+        &self,
+        order: &Order,
+        record: &IdempotencyRecord,
+        since: DateTime<Utc>,
+    ) -> Result<(IdempotencyRecord, SyncOutcome), Self::ErrT> {
+        let mut state = self.lock();
+        if let Some(earlier) = state.idempotency_record(&record.key, since) {
+            return Ok((earlier.clone(), SyncOutcome::Unchanged));
+        }
+        let outcome = match state.stored_outcome(order)? {
+            Some(outcome) => outcome,
+            None => {
+                state.check_order(order)?;
+                state.insert(order);
+                SyncOutcome::Created
+            }
+        };
+        state
+            .idempotency_keys
+            .insert(record.key.clone(), record.clone());
+        Ok((record.clone(), outcome))
+    }
+
+    async fn read_idempotency_key(
+        &self,
+        key: &str,
+        since: DateTime<Utc>,
+    ) -> Result<Option<IdempotencyRecord>, Self::ErrT> {
+        Ok(self.lock().idempotency_record(key, since).cloned())
+    }
+
+    async fn read_orders_by_table(
+        &self,
+        table_number: i32,
+    ) -> Result<Vec<CompleteOrder>, Self::ErrT> {
+        let mut state = self.lock();
+        let Some(session_id) = state
+            .current_session(table_number)
+            .map(|session| session.id)
+        else {
+            return Ok(vec![]);
+        };
+        let mut orders: Vec<CompleteOrder> = state
+            .orders
+            .iter()
+            .filter(|order| {
+                order.session_id == Some(session_id) && order.status != OrderStatus::Cancelled
+            })
+            .filter_map(|order| state.complete(order))
+            .collect();
+        orders.sort_by_key(|order| (order.created_at, order.order_id));
+        Ok(orders)
+    }
+
+    async fn read_orders_page(
+        &self,
+        table_number: i32,
+        filter: &TableOrdersFilter,
+        limit: i64,
+    ) -> Result<Vec<CompleteOrder>, Self::ErrT> {
+        let mut state = self.lock();
+        let session_id = match filter.session_id {
+            Some(session_id) => session_id,
+            None => match state.current_session(table_number) {
+                Some(session) => session.id,
+                None => return Ok(vec![]),
+            },
+        };
+        let mut orders: Vec<CompleteOrder> = state
+            .orders
+            .iter()
+            .filter(|order| {
+                order.table_number == table_number
+                    && order.session_id == Some(session_id)
+                    && order.status != OrderStatus::Cancelled
+            })
+            .filter_map(|order| state.complete(order))
+            .filter(|order| filter.matches(order))
+            .collect();
+        orders.sort_by_key(|order| {
+            filter
+                .sort
+                .key(order.cooking_time, order.created_at, order.order_id)
+        });
+        orders.truncate(limit as usize);
+        Ok(orders)
+    }
+
+    async fn search(
+        &self,
+        search: &OrderSearch,
+        limit: i64,
+    ) -> Result<Vec<CompleteOrder>, Self::ErrT> {
+        let state = self.lock();
+        let mut orders: Vec<CompleteOrder> = state
+            .orders
+            .iter()
+            .filter_map(|order| state.complete(order))
+            .filter(|order| search.matches(order))
+            .collect();
+        orders.sort_by_key(|order| {
+            search
+                .sort
+                .key(order.cooking_time, order.created_at, order.order_id)
+        });
+        orders.truncate(limit as usize);
+        Ok(orders)
+    }
+
+    async fn read_orders_by_session(
+        &self,
+        session_id: Uuid,
+    ) -> Result<Vec<CompleteOrder>, Self::ErrT> {
+        let state = self.lock();
+        let mut orders: Vec<CompleteOrder> = state
+            .orders
+            .iter()
+            .filter(|order| {
+                order.session_id == Some(session_id) && order.status != OrderStatus::Cancelled
+            })
+            .filter_map(|order| state.complete(order))
+            .collect();
+        orders.sort_by_key(|order| (order.created_at, order.order_id));
+        Ok(orders)
+    }
+
+    async fn read_open_orders(&self) -> Result<Vec<CompleteOrder>, Self::ErrT> {
+        let state = self.lock();
+        let mut orders: Vec<CompleteOrder> = state
+            .orders
+            .iter()
+            .filter(|order| matches!(order.status, OrderStatus::Ordered | OrderStatus::Cooking))
+            .filter_map(|order| state.complete(order))
+            .collect();
+        orders.sort_by_key(|order| (order.created_at, order.order_id));
+        Ok(orders)
+    }
+
+    async fn read_order_by_id(&self, order_id: Uuid) -> Result<Option<CompleteOrder>, Self::ErrT> {
+        let state = self.lock();
+        Ok(state
+            .orders
+            .iter()
+            .find(|order| order.id == order_id)
+            .and_then(|order| state.complete(order)))
+    }
+
+    async fn read_order_item_from_table(
+        &self,
+        key: TableItemKey,
+    ) -> Result<Option<CompleteOrder>, Self::ErrT> {
+        let state = self.lock();
+        Ok(state
+            .latest_index(key)
+            .and_then(|i| state.complete(&state.orders[i])))
+    }
+
+    async fn update_order(
+        &self,
+        order_id: Uuid,
+        patch: &OrderPatch,
+    ) -> Result<Option<CompleteOrder>, Self::ErrT> {
+        let mut state = self.lock();
+        if let Some(table_number) = patch.table_number {
+            if !state.tables.contains_key(&table_number) {
+                return Err(RepositoryError::TableNotFound(table_number));
+            }
+        }
+        if let Some(menu_item_id) = patch.menu_item_id {
+            if !state.is_orderable(menu_item_id) {
+                return Err(RepositoryError::MenuItemNotFound(menu_item_id));
+            }
+        }
+        if let Some(quantity) = patch.quantity.filter(|quantity| *quantity < 1) {
+            return Err(RepositoryError::InvalidQuantity(quantity));
+        }
+        let Some(i) = state
+            .orders
+            .iter()
+            .position(|order| order.id == order_id && order.status == OrderStatus::Ordered)
+        else {
+            return Ok(None);
+        };
+        // The bill of a closed session is final, the order can't leave it.
+        state.check_open_session(&state.orders[i])?;
+        // A moved order joins the session of its new table.
+        if let Some(table_number) = patch.table_number {
+            let session_id = state.current_session_id(table_number, Utc::now());
+            state.orders[i].table_number = table_number;
+            state.orders[i].session_id = Some(session_id);
+        }
+        let order = &mut state.orders[i];
+        if let Some(menu_item_id) = patch.menu_item_id {
+            order.menu_item_id = menu_item_id;
+        }
+        if let Some(quantity) = patch.quantity {
+            order.quantity = quantity;
+        }
+        let order = order.clone();
+        Ok(state.complete(&order))
+    }
+
+    async fn update_status(
+        &self,
+        order_id: Uuid,
+        from: OrderStatus,
+        to: OrderStatus,
+        at: DateTime<Utc>,
+    ) -> Result<Option<CompleteOrder>, Self::ErrT> {
+        let mut state = self.lock();
+        let Some(order) = state
+            .orders
+            .iter_mut()
+            .find(|order| order.id == order_id && order.status == from)
+        else {
+            return Ok(None);
+        };
+        order.status = to;
+        match to {
+            OrderStatus::Ordered => (),
+            OrderStatus::Cooking => order.cooking_at = Some(at),
+            OrderStatus::Ready => order.ready_at = Some(at),
+            OrderStatus::Served => order.served_at = Some(at),
+            OrderStatus::Cancelled => order.cancelled_at = Some(at),
+        }
+        let order = order.clone();
+        Ok(state.complete(&order))
+    }
+
+    async fn cancel_order(
+        &self,
+        order_id: Uuid,
+        from: OrderStatus,
+        cancellation: &Cancellation,
+        at: DateTime<Utc>,
+    ) -> Result<Option<CompleteOrder>, Self::ErrT> {
+        let mut state = self.lock();
+        let Some(i) = state
+            .orders
+            .iter()
+            .position(|order| order.id == order_id && order.status == from)
+        else {
+            return Ok(None);
+        };
+        state.check_open_session(&state.orders[i])?;
+        let order = &mut state.orders[i];
+        order.cancel(cancellation, at);
+        let order = order.clone();
+        Ok(state.complete(&order))
+    }
+
+    async fn cancel_plate(
+        &self,
+        order_id: Uuid,
+        from: OrderStatus,
+        cancellation: &Cancellation,
+        at: DateTime<Utc>,
+    ) -> Result<Option<CompleteOrder>, Self::ErrT> {
+        let mut state = self.lock();
+        let Some(i) = state
+            .orders
+            .iter()
+            .position(|order| order.id == order_id && order.status == from)
+        else {
+            return Ok(None);
+        };
+        state.check_open_session(&state.orders[i])?;
+        let order = &mut state.orders[i];
+        let plate = if order.quantity > 1 {
+            order.quantity -= 1;
+            let mut plate = Order {
+                id: Uuid::new_v4(),
+                quantity: 1,
+                ..order.clone()
+            };
+            plate.cancel(cancellation, at);
+            state.orders.push(plate.clone());
+            plate
+        } else {
+            order.cancel(cancellation, at);
+            order.clone()
+        };
+        Ok(state.complete(&plate))
+    }
+
+    async fn read_cancellations(
+        &self,
+        filter: &CancellationFilter,
+    ) -> Result<Vec<CompleteOrder>, Self::ErrT> {
+        let state = self.lock();
+        let mut orders: Vec<CompleteOrder> = state
+            .orders
+            .iter()
+            .filter(|order| order.status == OrderStatus::Cancelled)
+            .filter_map(|order| state.complete(order))
+            .filter(|order| filter.matches(order))
+            .collect();
+        orders.sort_by_key(|order| std::cmp::Reverse((order.cancelled_at, order.order_id)));
+        Ok(orders)
+    }
+}
+
+#[async_trait]
+impl MenuRepository for InMemoryOrderRepository {
+    type ErrT = RepositoryError;
+
+    async fn create_menu_item(&self, item: &NewMenuItem) -> Result<MenuItem, Self::ErrT> {
+        let mut state = self.lock();
+        let id = state.menu_items.keys().max().map_or(0, |id| id + 1);
+        let item = MenuItem {
+            id,
+            item_name: item.item_name.clone(),
+            cooking_time: item.cooking_time,
+            price: item.price,
+            retired_at: None,
+        };
+        state.menu_items.insert(id, item.clone());
+        Ok(item)
+    }
+
+    async fn read_menu_items(&self, include_retired: bool) -> Result<Vec<MenuItem>, Self::ErrT> {
+        let state = self.lock();
+        let mut items: Vec<MenuItem> = state
+            .menu_items
+            .values()
+            .filter(|item| include_retired || item.retired_at.is_none())
+            .cloned()
+            .collect();
+        items.sort_by_key(|item| item.id);
+        Ok(items)
+    }
+
+    async fn read_menu_item(&self, menu_item_id: i32) -> Result<Option<MenuItem>, Self::ErrT> {
+        Ok(self.lock().menu_items.get(&menu_item_id).cloned())
+    }
+
+    async fn update_menu_item(
+        &self,
+        menu_item_id: i32,
+        patch: &MenuItemPatch,
+    ) -> Result<Option<MenuItem>, Self::ErrT> {
+        let mut state = self.lock();
+        let Some(item) = state.menu_items.get_mut(&menu_item_id) else {
+            return Ok(None);
+        };
+        if let Some(item_name) = &patch.item_name {
+            item.item_name = item_name.clone();
+        }
+        if let Some(cooking_time) = patch.cooking_time {
+            item.cooking_time = cooking_time;
+        }
+        if let Some(price) = patch.price {
+            item.price = price;
+        }
+        Ok(Some(item.clone()))
+    }
+
+    async fn retire_menu_item(&self, menu_item_id: i32) -> Result<Option<MenuItem>, Self::ErrT> {
+        let mut state = self.lock();
+        let Some(item) = state.menu_items.get_mut(&menu_item_id) else {
+            return Ok(None);
+        };
+        item.retired_at.get_or_insert_with(Utc::now);
+        Ok(Some(item.clone()))
+    }
+}
+
+#[async_trait]
+impl TableRepository for InMemoryOrderRepository {
+    type ErrT = RepositoryError;
+
+    async fn read_tables(&self) -> Result<Vec<RestaurantTable>, Self::ErrT> {
+        Ok(self.lock().tables.values().cloned().collect())
+    }
+
+    async fn read_table(&self, table_number: i32) -> Result<Option<RestaurantTable>, Self::ErrT> {
+        Ok(self.lock().tables.get(&table_number).cloned())
+    }
+
+    async fn upsert_table(&self, table: &RestaurantTable) -> Result<RestaurantTable, Self::ErrT> {
+        let mut state = self.lock();
+        state.tables.insert(table.table_number, table.clone());
+        Ok(table.clone())
+    }
+
+    async fn delete_table(&self, table_number: i32) -> Result<u64, Self::ErrT> {
+        let mut state = self.lock();
+        let in_use = state
+            .orders
+            .iter()
+            .any(|order| order.table_number == table_number)
+            || state
+                .sessions
+                .iter()
+                .any(|session| session.table_number == table_number);
+        if in_use {
+            return Err(RepositoryError::TableInUse(table_number));
+        }
+        Ok(state.tables.remove(&table_number).map_or(0, |_| 1))
+    }
+
+    async fn open_session(
+        &self,
+        table_number: i32,
+        party_size: i32,
+        at: DateTime<Utc>,
+    ) -> Result<TableSession, Self::ErrT> {
+        let mut state = self.lock();
+        if !state.tables.contains_key(&table_number) {
+            return Err(RepositoryError::TableNotFound(table_number));
+        }
+        state.current_session_id(table_number, at);
+        let session = state
+            .current_session(table_number)
+            .expect("a session was just opened");
+        session.party_size = Some(party_size);
+        Ok(session.clone())
+    }
+
+    async fn read_current_session(
+        &self,
+        table_number: i32,
+    ) -> Result<Option<TableSession>, Self::ErrT> {
+        Ok(self.lock().current_session(table_number).cloned())
+    }
+
+    async fn read_sessions(&self, table_number: i32) -> Result<Vec<TableSession>, Self::ErrT> {
+        let state = self.lock();
+        let mut sessions: Vec<TableSession> = state
+            .sessions
+            .iter()
+            .filter(|session| session.table_number == table_number)
+            .cloned()
+            .collect();
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.opened_at));
+        Ok(sessions)
+    }
+
+    async fn close_session(
+        &self,
+        session_id: Uuid,
+        rates: BillingRates,
+        at: DateTime<Utc>,
+    ) -> Result<Option<TableSession>, Self::ErrT> {
+        let mut state = self.lock();
+        Ok(state.covered_session(session_id, rates)?.map(|session| {
+            session.closed_at = Some(at);
+            session.clone()
+        }))
+    }
+}
+
+#[async_trait]
+impl PaymentRepository for InMemoryOrderRepository {
+    type ErrT = RepositoryError;
+
+    async fn create_payment(
+        &self,
+        payment: &Payment,
+        recorded: usize,
+    ) -> Result<Option<Payment>, Self::ErrT> {
+        let mut state = self.lock();
+        let session = state
+            .sessions
+            .iter()
+            .find(|session| session.id == payment.session_id)
+            .ok_or(sqlx::Error::RowNotFound)?;
+        if session.closed_at.is_some() {
+            return Err(RepositoryError::SessionClosed(session.table_number));
+        }
+        let count = state
+            .payments
+            .iter()
+            .filter(|other| other.session_id == payment.session_id)
+            .count();
+        if count != recorded {
+            return Ok(None);
+        }
+        state.payments.push(payment.clone());
+        Ok(Some(payment.clone()))
+    }
+
+    async fn read_payments(&self, session_id: Uuid) -> Result<Vec<Payment>, Self::ErrT> {
+        let state = self.lock();
+        let mut payments: Vec<Payment> = state
+            .payments
+            .iter()
+            .filter(|payment| payment.session_id == session_id)
+            .cloned()
+            .collect();
+        payments.sort_by_key(|payment| payment.created_at);
+        Ok(payments)
+    }
+
+    async fn read_payment(&self, payment_id: Uuid) -> Result<Option<Payment>, Self::ErrT> {
+        let state = self.lock();
+        Ok(state
+            .payments
+            .iter()
+            .find(|payment| payment.id == payment_id)
+            .cloned())
+    }
+
+    async fn settle_session(
+        &self,
+        session_id: Uuid,
+        rates: BillingRates,
+        at: DateTime<Utc>,
+    ) -> Result<Option<TableSession>, Self::ErrT> {
+        let mut state = self.lock();
+        Ok(state.covered_session(session_id, rates)?.map(|session| {
+            session.closed_at = Some(at);
+            session.settled_at = Some(at);
+            session.clone()
+        }))
     }
 }
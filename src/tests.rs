@@ -1,84 +1,981 @@
 #[cfg(test)]
 mod pg_sql_tests {
-    use crate::constants::FOOD_ITEMS;
+    use crate::billing::BillingRates;
+    use crate::change_feed::order_event;
+    use crate::constants::{
+        DEFAULT_MAX_TABLE_NUMBER, DEFAULT_MIN_TABLE_NUMBER, DEFAULT_TABLE_SEATS,
+        DEFAULT_TABLE_SECTION, FOOD_ITEMS, ORDER_CHANGES_CHANNEL,
+    };
     use crate::domain::*;
-    use crate::repository::PgSqlOrderRepository;
+    use crate::events::OrderEventKind;
+    use crate::repository::{
+        OrderRepository, PgSqlMenuRepository, PgSqlOrderRepository, PgSqlPaymentRepository,
+        PgSqlTableRepository, RepositoryError,
+    };
+    use chrono::Utc;
     use futures::future::try_join_all;
+    use futures::lock::{Mutex, MutexGuard};
     use rand::Rng;
-    use sqlx::postgres::PgPoolOptions;
+    use sqlx::postgres::{PgListener, PgPoolOptions};
     use sqlx::PgPool;
     use std::env;
     use std::error::Error as stdErr;
+    use std::sync::OnceLock;
+    use uuid::Uuid;
 
-    /// Test database CRUD functions:
-    ///
-    /// CREATE: order.
-    ///
-    /// READ: query all orders for a specified table number.
-    ///
-    /// READ: query latest order for a specified menu item and for a specified table number.
-    ///
-    /// DELETE: latest order for a specified item and for a specified table number.
+    const TABLES_QTY: i32 = 100;
+    const TOTAL_REQS_PER_CLIENT: i32 = 50;
+
+    /// Creates orders at every table, reads them back, then cancels them plate by plate.
     #[actix_web::test]
     async fn test_create_read_delete_orders() -> Result<(), Box<dyn stdErr>> {
-        println!("------test_create_read_delete_orders------");
-        const TABLES_QTY: i32 = 100;
-        const TOTAL_REQS_PER_CLIENT: i32 = 50;
-
-        let pool = setup_pg_test_db().await?;
-        let pg_sql_order_repository = PgSqlOrderRepository::new(pool);
-        let mut rng = rand::thread_rng();
-
+        let (_db, pool) = setup_pg_test_db().await?;
+        let pg_sql_order_repository = PgSqlOrderRepository::new(pool.clone());
         let orders = read_orders_by_table(&pg_sql_order_repository, 1).await?;
         assert_eq!(0, orders.len());
 
-        print!("Testing CREATE and READ ... ");
-        //CREATE and READ
+        let mut rng = rand::thread_rng();
         for table_num in 1..=TABLES_QTY {
             for _ in 1..=TOTAL_REQS_PER_CLIENT {
                 let order = Order::new(table_num, rng.gen_range(1..50));
                 let uuid = order.create(&pg_sql_order_repository).await?;
                 assert_eq!(4, uuid.get_version_num());
+                let created = read_order_by_id(&pg_sql_order_repository, uuid).await?;
+                assert_eq!(order.menu_item_id, created.unwrap().menu_item_id);
             }
             let orders = read_orders_by_table(&pg_sql_order_repository, table_num).await?;
             assert_eq!(TOTAL_REQS_PER_CLIENT, orders.len() as i32);
             for order in orders {
                 let order = read_order_item_from_table(
                     &pg_sql_order_repository,
-                    order.menu_item_id,
-                    table_num,
+                    TableItemKey::from(&order),
                 )
                 .await?;
                 assert!(order.is_some());
             }
         }
-        println!("OK");
 
-        print!("Testing DELETE ... ");
-        //DELETE
         for table_num in 1..=TABLES_QTY {
             let orders: Vec<CompleteOrder> =
                 read_orders_by_table(&pg_sql_order_repository, table_num).await?;
             assert_eq!(TOTAL_REQS_PER_CLIENT, orders.len() as i32);
             for order in orders {
-                let rows_affected = delete_order_item_from_table(
+                let cancelled = cancel_order_item_from_table(
                     &pg_sql_order_repository,
-                    order.menu_item_id,
-                    order.table_number,
+                    TableItemKey::from(&order),
+                    &Cancellation::default(),
                 )
                 .await?;
-                assert_eq!(1, rows_affected);
+                assert!(cancelled.is_some());
             }
         }
-        println!("OK");
+        Ok(())
+    }
+
+    /// Patched orders keep their creation time, only while ordered and in an open session.
+    #[actix_web::test]
+    async fn test_update_order() -> Result<(), Box<dyn stdErr>> {
+        let (_db, pool) = setup_pg_test_db().await?;
+        let pg_sql_order_repository = PgSqlOrderRepository::new(pool.clone());
+        let pg_sql_table_repository = PgSqlTableRepository::new(pool.clone());
+        let rates = BillingRates {
+            service_charge_bps: 0,
+            tax_rate_bps: 0,
+        };
+        for _ in 0..3 {
+            Order::new(1, rand::thread_rng().gen_range(1..50))
+                .create(&pg_sql_order_repository)
+                .await?;
+        }
+        let order = read_orders_by_table(&pg_sql_order_repository, 1).await?[0].clone();
+        let patch = OrderPatch {
+            menu_item_id: Some((order.menu_item_id + 1) % 50),
+            ..Default::default()
+        };
+        let updated = update_order(&pg_sql_order_repository, order.order_id, &patch)
+            .await?
+            .unwrap();
+        assert_eq!(patch.menu_item_id, Some(updated.menu_item_id));
+        assert_eq!(order.created_at, updated.created_at);
+        let patch = OrderPatch {
+            menu_item_id: Some(999),
+            ..Default::default()
+        };
+        assert!(
+            update_order(&pg_sql_order_repository, order.order_id, &patch)
+                .await
+                .is_err()
+        );
+        let table_num = TABLES_QTY + 9;
+        let move_to_2 = OrderPatch {
+            table_number: Some(2),
+            ..Default::default()
+        };
+        let served = Order::new(table_num, 3);
+        served.create(&pg_sql_order_repository).await?;
+        for status in [
+            OrderStatus::Cooking,
+            OrderStatus::Ready,
+            OrderStatus::Served,
+        ] {
+            transition_order(&pg_sql_order_repository, served.id, status).await?;
+        }
+        let result = update_order(&pg_sql_order_repository, served.id, &move_to_2).await;
+        assert!(matches!(
+            result,
+            Err(RepositoryError::IllegalTransition(IllegalTransition {
+                from: OrderStatus::Served,
+                to: OrderStatus::Ordered
+            }))
+        ));
+        let closed = Order::new(table_num, 4);
+        closed.create(&pg_sql_order_repository).await?;
+        close_table_session(&pg_sql_table_repository, table_num, rates, Utc::now()).await?;
+        let result = update_order(&pg_sql_order_repository, closed.id, &move_to_2).await;
+        assert!(matches!(
+            result,
+            Err(RepositoryError::SessionClosed(n)) if n == table_num
+        ));
+        Ok(())
+    }
+
+    /// Pages of a table's orders cover every order once, in the order of the sort.
+    #[actix_web::test]
+    async fn test_order_pages() -> Result<(), Box<dyn stdErr>> {
+        let (_db, pool) = setup_pg_test_db().await?;
+        let pg_sql_order_repository = PgSqlOrderRepository::new(pool.clone());
+        for _ in 0..20 {
+            Order::new(2, rand::thread_rng().gen_range(1..50))
+                .create(&pg_sql_order_repository)
+                .await?;
+        }
+        let orders = read_orders_by_table(&pg_sql_order_repository, 2).await?;
+        for sort in [OrderSort::CreatedAt, OrderSort::CookingTime] {
+            let mut filter = TableOrdersFilter {
+                sort,
+                ..Default::default()
+            };
+            let mut keys = vec![];
+            loop {
+                let page = read_orders_page(&pg_sql_order_repository, 2, &filter, 7).await?;
+                keys.extend(
+                    page.orders
+                        .iter()
+                        .map(|o| sort.key(o.cooking_time, o.created_at, o.order_id)),
+                );
+                let Some(cursor) = page.next_cursor else {
+                    break;
+                };
+                filter.cursor = Some(cursor.parse().unwrap());
+            }
+            assert_eq!(orders.len(), keys.len());
+            assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+        let filter = TableOrdersFilter {
+            menu_item_id: Some(orders[0].menu_item_id),
+            created_to: Some(orders[0].created_at + chrono::Duration::microseconds(1)),
+            ..Default::default()
+        };
+        let page = read_orders_page(&pg_sql_order_repository, 2, &filter, 10).await?;
+        assert_eq!(orders[0].order_id, page.orders[0].order_id);
+        assert!(!page.has_more);
+        Ok(())
+    }
+
+    /// Searching matches part of the item name, ignoring case, within a table range.
+    #[actix_web::test]
+    async fn test_order_search() -> Result<(), Box<dyn stdErr>> {
+        let (_db, pool) = setup_pg_test_db().await?;
+        let pg_sql_order_repository = PgSqlOrderRepository::new(pool.clone());
+        for _ in 0..20 {
+            Order::new(2, rand::thread_rng().gen_range(1..50))
+                .create(&pg_sql_order_repository)
+                .await?;
+        }
+        let orders = read_orders_by_table(&pg_sql_order_repository, 2).await?;
+        let order = &orders[0];
+        let search = OrderSearch {
+            item_name: Some(order.item_name.to_uppercase()),
+            table_from: Some(2),
+            table_to: Some(2),
+            status: Some(OrderStatus::Ordered),
+            ..Default::default()
+        };
+        let page = search_orders(&pg_sql_order_repository, &search, 200).await?;
+        assert!(page.orders.iter().any(|o| o.order_id == order.order_id));
+        assert!(page.orders.iter().all(|o| o.table_number == 2
+            && o.item_name
+                .to_lowercase()
+                .contains(&order.item_name.to_lowercase())));
+        let search = OrderSearch {
+            item_name: Some("%".to_string()),
+            ..Default::default()
+        };
+        assert!(search_orders(&pg_sql_order_repository, &search, 10)
+            .await?
+            .orders
+            .is_empty());
+        let search = OrderSearch {
+            table_from: Some(1),
+            table_to: Some(TABLES_QTY),
+            ..Default::default()
+        };
+        let page = search_orders(&pg_sql_order_repository, &search, 10).await?;
+        assert_eq!(10, page.orders.len());
+        assert!(page.has_more);
+        Ok(())
+    }
+
+    /// Cancelling from a table takes one plate off the latest order.
+    #[actix_web::test]
+    async fn test_order_quantity() -> Result<(), Box<dyn stdErr>> {
+        let (_db, pool) = setup_pg_test_db().await?;
+        let pg_sql_order_repository = PgSqlOrderRepository::new(pool.clone());
+        let table_num = TABLES_QTY + 1;
+        Order::with_quantity(table_num, 29, 2)
+            .create(&pg_sql_order_repository)
+            .await?;
+        assert!(cancel_order_item_from_table(
+            &pg_sql_order_repository,
+            TableItemKey {
+                table_number: table_num,
+                menu_item_id: 29
+            },
+            &Cancellation::default(),
+        )
+        .await?
+        .is_some());
+        let orders = read_orders_by_table(&pg_sql_order_repository, table_num).await?;
+        assert_eq!(1, orders.len());
+        assert_eq!(1, orders[0].quantity);
+        assert!(cancel_order_item_from_table(
+            &pg_sql_order_repository,
+            TableItemKey {
+                table_number: table_num,
+                menu_item_id: 29
+            },
+            &Cancellation::default(),
+        )
+        .await?
+        .is_some());
+        assert_eq!(
+            0,
+            read_orders_by_table(&pg_sql_order_repository, table_num)
+                .await?
+                .len()
+        );
+        assert!(Order::with_quantity(table_num, 29, 0)
+            .create(&pg_sql_order_repository)
+            .await
+            .is_err());
+        let first = Order::new(table_num, 29);
+        let twins = [
+            Order {
+                created_at: first.created_at,
+                ..Order::new(table_num, 29)
+            },
+            first,
+        ];
+        create_orders(&pg_sql_order_repository, &twins).await?;
+        let key = TableItemKey::from(&twins[0]);
+        let latest = read_order_item_from_table(&pg_sql_order_repository, key)
+            .await?
+            .unwrap();
+        assert_eq!(twins.iter().map(|o| o.id).max(), Some(latest.order_id));
+        cancel_order_item_from_table(&pg_sql_order_repository, key, &Cancellation::default())
+            .await?;
+        let cancelled = read_order_by_id(&pg_sql_order_repository, latest.order_id)
+            .await?
+            .unwrap();
+        assert_eq!(OrderStatus::Cancelled, cancelled.status);
+
+        // Once the party left, their orders are no longer the latest of the table.
+        let pg_sql_table_repository = PgSqlTableRepository::new(pool.clone());
+        let rates = BillingRates {
+            service_charge_bps: 0,
+            tax_rate_bps: 0,
+        };
+        close_table_session(&pg_sql_table_repository, table_num, rates, Utc::now()).await?;
+        assert!(read_order_item_from_table(&pg_sql_order_repository, key)
+            .await?
+            .is_none());
+        let next_party = Order::new(table_num, 29);
+        next_party.create(&pg_sql_order_repository).await?;
+        let latest = read_order_item_from_table(&pg_sql_order_repository, key)
+            .await?
+            .unwrap();
+        assert_eq!(next_party.id, latest.order_id);
+        Ok(())
+    }
+
+    /// A batch of orders is stored all or nothing.
+    #[actix_web::test]
+    async fn test_create_many_orders() -> Result<(), Box<dyn stdErr>> {
+        let (_db, pool) = setup_pg_test_db().await?;
+        let pg_sql_order_repository = PgSqlOrderRepository::new(pool.clone());
+        let table_num = TABLES_QTY + 2;
+        let orders = vec![Order::new(table_num, 1), Order::new(table_num, 999)];
+        assert!(create_orders(&pg_sql_order_repository, &orders)
+            .await
+            .is_err());
+        assert_eq!(
+            0,
+            read_orders_by_table(&pg_sql_order_repository, table_num)
+                .await?
+                .len()
+        );
+        let orders = vec![
+            Order::new(table_num, 1),
+            Order::with_quantity(table_num, 2, 2),
+        ];
+        assert_eq!(
+            vec![SyncOutcome::Created; 2],
+            create_orders(&pg_sql_order_repository, &orders).await?
+        );
+        assert_eq!(
+            2,
+            read_orders_by_table(&pg_sql_order_repository, table_num)
+                .await?
+                .len()
+        );
+        Ok(())
+    }
+
+    /// Orders move through the kitchen one status at a time.
+    #[actix_web::test]
+    async fn test_order_status() -> Result<(), Box<dyn stdErr>> {
+        let (_db, pool) = setup_pg_test_db().await?;
+        let pg_sql_order_repository = PgSqlOrderRepository::new(pool.clone());
+        for _ in 0..3 {
+            Order::new(1, rand::thread_rng().gen_range(1..50))
+                .create(&pg_sql_order_repository)
+                .await?;
+        }
+        let order = read_orders_by_table(&pg_sql_order_repository, 1).await?[0].clone();
+        let cooking = transition_order(
+            &pg_sql_order_repository,
+            order.order_id,
+            OrderStatus::Cooking,
+        )
+        .await?
+        .unwrap();
+        assert_eq!(OrderStatus::Cooking, cooking.status);
+        assert!(cooking.cooking_at.is_some());
+        let open_orders = read_open_orders(&pg_sql_order_repository).await?;
+        assert!(open_orders
+            .iter()
+            .any(|open| open.order_id == order.order_id && open.status == OrderStatus::Cooking));
+        assert!(transition_order(
+            &pg_sql_order_repository,
+            order.order_id,
+            OrderStatus::Served
+        )
+        .await
+        .is_err());
+        let cancelled = transition_order(
+            &pg_sql_order_repository,
+            order.order_id,
+            OrderStatus::Cancelled,
+        )
+        .await?
+        .unwrap();
+        assert!(cancelled.cancelled_at.is_some());
+        // Cancelled orders are no longer listed at their table.
+        assert_eq!(
+            2,
+            read_orders_by_table(&pg_sql_order_repository, 1)
+                .await?
+                .len()
+        );
+        Ok(())
+    }
+
+    /// Menu items can be added, repriced and retired.
+    #[actix_web::test]
+    async fn test_menu_management() -> Result<(), Box<dyn stdErr>> {
+        let (_db, pool) = setup_pg_test_db().await?;
+        let pg_sql_order_repository = PgSqlOrderRepository::new(pool.clone());
+        let pg_sql_menu_repository = PgSqlMenuRepository::new(pool.clone());
+        let item = NewMenuItem {
+            item_name: "Katsu Curry".to_string(),
+            cooking_time: 12,
+            price: 1200,
+        };
+        let item = create_menu_item(&pg_sql_menu_repository, &item).await?;
+        assert_eq!(FOOD_ITEMS.len() as i32, item.id);
+        let patch = MenuItemPatch {
+            price: Some(1350),
+            ..Default::default()
+        };
+        let repriced = update_menu_item(&pg_sql_menu_repository, item.id, &patch)
+            .await?
+            .unwrap();
+        assert_eq!(1350, repriced.price);
+        assert_eq!(item.item_name, repriced.item_name);
+        let retired = retire_menu_item(&pg_sql_menu_repository, item.id)
+            .await?
+            .unwrap();
+        assert!(retired.retired_at.is_some());
+        let menu = read_menu_items(&pg_sql_menu_repository, false).await?;
+        assert_eq!(FOOD_ITEMS.len(), menu.len());
+        assert!(Order::new(1, item.id)
+            .create(&pg_sql_order_repository)
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    /// Orders join the session open at their table, closing it clears the table.
+    #[actix_web::test]
+    async fn test_table_sessions() -> Result<(), Box<dyn stdErr>> {
+        let (_db, pool) = setup_pg_test_db().await?;
+        let pg_sql_order_repository = PgSqlOrderRepository::new(pool.clone());
+        let pg_sql_table_repository = PgSqlTableRepository::new(pool.clone());
+        let rates = BillingRates {
+            service_charge_bps: 0,
+            tax_rate_bps: 0,
+        };
+        let table_num = TABLES_QTY + 3;
+        let session =
+            open_table_session(&pg_sql_table_repository, table_num, 4, Utc::now()).await?;
+        assert_eq!(Some(4), session.party_size);
+        let order = Order::new(table_num, 1);
+        order.create(&pg_sql_order_repository).await?;
+        let orders = read_orders_by_table(&pg_sql_order_repository, table_num).await?;
+        assert_eq!(Some(session.id), orders[0].session_id);
+        let closed = close_table_session(&pg_sql_table_repository, table_num, rates, Utc::now())
+            .await?
+            .unwrap();
+        assert!(closed.closed_at.is_some());
+        assert!(read_orders_by_table(&pg_sql_order_repository, table_num)
+            .await?
+            .is_empty());
+        assert_eq!(
+            1,
+            read_orders_by_session(&pg_sql_order_repository, session.id)
+                .await?
+                .len()
+        );
+        // The next order opens a new session, its party size is unknown.
+        Order::new(table_num, 2)
+            .create(&pg_sql_order_repository)
+            .await?;
+        let current = read_current_session(&pg_sql_table_repository, table_num)
+            .await?
+            .unwrap();
+        assert_ne!(session.id, current.id);
+        assert_eq!(None, current.party_size);
+        let sessions = read_table_sessions(&pg_sql_table_repository, table_num).await?;
+        assert_eq!(
+            vec![current.id, session.id],
+            sessions.iter().map(|s| s.id).collect::<Vec<_>>()
+        );
+        let result = open_table_session(&pg_sql_table_repository, -1, 2, Utc::now()).await;
+        assert!(matches!(result, Err(RepositoryError::TableNotFound(-1))));
+        Ok(())
+    }
+
+    /// Bills are paid in parts, refunded and settled once covered, even under concurrent requests.
+    #[actix_web::test]
+    async fn test_payments() -> Result<(), Box<dyn stdErr>> {
+        let (_db, pool) = setup_pg_test_db().await?;
+        let pg_sql_order_repository = PgSqlOrderRepository::new(pool.clone());
+        let pg_sql_menu_repository = PgSqlMenuRepository::new(pool.clone());
+        let pg_sql_table_repository = PgSqlTableRepository::new(pool.clone());
+        let pg_sql_payment_repository = PgSqlPaymentRepository::new(pool.clone());
+        let rates = BillingRates {
+            service_charge_bps: 0,
+            tax_rate_bps: 0,
+        };
+        let table_num = TABLES_QTY + 4;
+        let item = NewMenuItem {
+            item_name: "Tonkatsu".to_string(),
+            cooking_time: 10,
+            price: 1000,
+        };
+        let item = create_menu_item(&pg_sql_menu_repository, &item).await?;
+        let order = Order::with_quantity(table_num, item.id, 2);
+        order.create(&pg_sql_order_repository).await?;
+        let total = 2000;
+        let card = record_payment(
+            &pg_sql_order_repository,
+            &pg_sql_table_repository,
+            &pg_sql_payment_repository,
+            table_num,
+            Tender::Card,
+            total - 100,
+            rates,
+        )
+        .await?;
+        let result = settle_table(
+            &pg_sql_table_repository,
+            &pg_sql_payment_repository,
+            table_num,
+            rates,
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(RepositoryError::Payment(PaymentError::NotCovered { .. }))
+        ));
+        // Nor can the session be closed without settling.
+        let result =
+            close_table_session(&pg_sql_table_repository, table_num, rates, Utc::now()).await;
+        assert!(matches!(
+            result,
+            Err(RepositoryError::Payment(PaymentError::NotCovered {
+                total: 2000,
+                paid: 1900
+            }))
+        ));
+        let cash = record_payment(
+            &pg_sql_order_repository,
+            &pg_sql_table_repository,
+            &pg_sql_payment_repository,
+            table_num,
+            Tender::Cash,
+            500,
+            rates,
+        )
+        .await?;
+        assert_eq!((100, 400), (cash.amount, cash.change));
+        let refund = refund_payment(&pg_sql_payment_repository, card.id, 50)
+            .await?
+            .unwrap();
+        assert_eq!(Some(card.id), refund.refund_of);
+        let payments = read_session_payments(
+            &pg_sql_order_repository,
+            &pg_sql_table_repository,
+            &pg_sql_payment_repository,
+            table_num,
+            None,
+            rates,
+        )
+        .await?
+        .unwrap();
+        assert_eq!(3, payments.payments.len());
+        assert_eq!(50, payments.balance.due);
+        // Concurrent refunds can't give back more than the payment together.
+        let (first, second) = futures::join!(
+            refund_payment(&pg_sql_payment_repository, cash.id, 60),
+            refund_payment(&pg_sql_payment_repository, cash.id, 60),
+        );
+        assert_eq!(1, [&first, &second].iter().filter(|r| r.is_ok()).count());
+        assert!([first, second].into_iter().any(|r| matches!(
+            r,
+            Err(RepositoryError::Payment(
+                PaymentError::RefundExceedsPayment { refundable: 40, .. }
+            ))
+        )));
+        // Nor can concurrent card payments go over what's due.
+        let pay_by_card = || {
+            record_payment(
+                &pg_sql_order_repository,
+                &pg_sql_table_repository,
+                &pg_sql_payment_repository,
+                table_num,
+                Tender::Card,
+                110,
+                rates,
+            )
+        };
+        let (first, second) = futures::join!(pay_by_card(), pay_by_card());
+        assert_eq!(1, [&first, &second].iter().filter(|r| r.is_ok()).count());
+        assert!([first, second]
+            .into_iter()
+            .any(|r| matches!(r, Err(RepositoryError::Payment(PaymentError::NothingDue)))));
+        // An order racing the settlement either joins the bill and refuses it, or waits for the next party.
+        let extra = Order::new(table_num, item.id);
+        let settle = || {
+            settle_table(
+                &pg_sql_table_repository,
+                &pg_sql_payment_repository,
+                table_num,
+                rates,
+            )
+        };
+        let (settled, created) = futures::join!(settle(), extra.create(&pg_sql_order_repository));
+        created?;
+        let extra = read_order_by_id(&pg_sql_order_repository, extra.id)
+            .await?
+            .unwrap();
+        let settled = match settled {
+            Ok(settled) => {
+                assert_ne!(Some(settled.id), extra.session_id);
+                settled
+            }
+            Err(RepositoryError::Payment(PaymentError::NotCovered { .. })) => {
+                record_payment(
+                    &pg_sql_order_repository,
+                    &pg_sql_table_repository,
+                    &pg_sql_payment_repository,
+                    table_num,
+                    Tender::Card,
+                    1000,
+                    rates,
+                )
+                .await?;
+                settle().await?
+            }
+            Err(error) => return Err(error.into()),
+        };
+        assert!(settled.settled_at.is_some());
+        assert_eq!(settled.settled_at, settled.closed_at);
+        let result = refund_payment(&pg_sql_payment_repository, card.id, 10).await;
+        assert!(matches!(result, Err(RepositoryError::SessionClosed(n)) if n == table_num));
+        // Nor can the settled orders be cancelled.
+        let result =
+            cancel_order(&pg_sql_order_repository, order.id, &Cancellation::default()).await;
+        assert!(matches!(result, Err(RepositoryError::SessionClosed(n)) if n == table_num));
+        let result = pg_sql_order_repository
+            .cancel_plate(
+                order.id,
+                OrderStatus::Ordered,
+                &Cancellation::default(),
+                Utc::now(),
+            )
+            .await;
+        assert!(matches!(result, Err(RepositoryError::SessionClosed(n)) if n == table_num));
+        let order = read_order_by_id(&pg_sql_order_repository, order.id)
+            .await?
+            .unwrap();
+        assert_eq!((OrderStatus::Ordered, 2), (order.status, order.quantity));
+        Ok(())
+    }
+
+    /// Tables can be defined and removed, only while unused.
+    #[actix_web::test]
+    async fn test_floor_plan() -> Result<(), Box<dyn stdErr>> {
+        let (_db, pool) = setup_pg_test_db().await?;
+        let pg_sql_order_repository = PgSqlOrderRepository::new(pool.clone());
+        let pg_sql_table_repository = PgSqlTableRepository::new(pool.clone());
+        Order::new(1, 1).create(&pg_sql_order_repository).await?;
+        let terrace = RestaurantTable {
+            table_number: DEFAULT_MAX_TABLE_NUMBER + 1,
+            seats: 2,
+            section: "terrace".to_string(),
+        };
+        assert_eq!(
+            terrace,
+            define_table(&pg_sql_table_repository, &terrace).await?
+        );
+        let resized = RestaurantTable {
+            seats: 6,
+            ..terrace.clone()
+        };
+        define_table(&pg_sql_table_repository, &resized).await?;
+        let table = read_table(&pg_sql_table_repository, terrace.table_number)
+            .await?
+            .unwrap();
+        assert_eq!(6, table.seats);
+        let result = Order::new(terrace.table_number + 1, 1)
+            .create(&pg_sql_order_repository)
+            .await;
+        assert!(matches!(result, Err(RepositoryError::TableNotFound(_))));
+        let result = remove_table(&pg_sql_table_repository, 1).await;
+        assert!(matches!(result, Err(RepositoryError::TableInUse(1))));
+        assert_eq!(
+            1,
+            remove_table(&pg_sql_table_repository, terrace.table_number).await?
+        );
+        Ok(())
+    }
 
+    /// Retries with the same idempotency key create a single order.
+    #[actix_web::test]
+    async fn test_idempotency() -> Result<(), Box<dyn stdErr>> {
+        let (_db, pool) = setup_pg_test_db().await?;
+        let pg_sql_order_repository = PgSqlOrderRepository::new(pool.clone());
+        let table_num = TABLES_QTY + 6;
+        let key = Uuid::new_v4().to_string();
+        let since = Utc::now() - chrono::Duration::hours(1);
+        // Concurrent retries of the same request create a single order.
+        let retries: Vec<Order> = (0..4).map(|_| Order::new(table_num, 2)).collect();
+        let created = try_join_all(
+            retries
+                .iter()
+                .map(|order| create_order_once(&pg_sql_order_repository, &key, order, since)),
+        )
+        .await?;
+        let uuids: Vec<Uuid> = created.iter().map(|(uuid, _)| *uuid).collect();
+        assert!(uuids.iter().all(|uuid| *uuid == uuids[0]));
+        assert_eq!(
+            1,
+            created
+                .iter()
+                .filter(|(_, outcome)| *outcome == SyncOutcome::Created)
+                .count()
+        );
+        assert!(retries.iter().any(|order| order.id == uuids[0]));
+        assert_eq!(
+            1,
+            read_orders_by_table(&pg_sql_order_repository, table_num)
+                .await?
+                .len()
+        );
+        assert_eq!(
+            Some(uuids[0]),
+            replay_order(
+                &pg_sql_order_repository,
+                &key,
+                &Order::new(table_num, 2),
+                since
+            )
+            .await?
+        );
+        let result = create_order_once(
+            &pg_sql_order_repository,
+            &key,
+            &Order::new(table_num, 3),
+            since,
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(RepositoryError::IdempotencyKeyReused(_))
+        ));
+        let later = Utc::now() + chrono::Duration::seconds(1);
+        let fresh = Order::new(table_num, 3);
+        assert_eq!(
+            (fresh.id, SyncOutcome::Created),
+            create_order_once(&pg_sql_order_repository, &key, &fresh, later).await?
+        );
+        // An order already synced under its id isn't created again with a new key.
+        let synced = Order::new(table_num, 3);
+        sync_order(&pg_sql_order_repository, &synced).await?;
+        assert_eq!(
+            (synced.id, SyncOutcome::Unchanged),
+            create_order_once(&pg_sql_order_repository, "synced", &synced, since).await?
+        );
+        Ok(())
+    }
+
+    /// Ids minted by devices can be sent again: the same order is a no-op, a different one a conflict.
+    #[actix_web::test]
+    async fn test_client_order_ids() -> Result<(), Box<dyn stdErr>> {
+        let (_db, pool) = setup_pg_test_db().await?;
+        let pg_sql_order_repository = PgSqlOrderRepository::new(pool.clone());
+        let table_num = TABLES_QTY + 7;
+        let id = Uuid::new_v4();
+        let order = Order {
+            id,
+            ..Order::with_quantity(table_num, 4, 2)
+        };
+        let outcomes =
+            try_join_all((0..4).map(|_| sync_order(&pg_sql_order_repository, &order))).await?;
+        assert_eq!(
+            1,
+            outcomes
+                .iter()
+                .filter(|outcome| **outcome == SyncOutcome::Created)
+                .count()
+        );
+        assert_eq!(id, order.create(&pg_sql_order_repository).await?);
+        assert_eq!(
+            1,
+            read_orders_by_table(&pg_sql_order_repository, table_num)
+                .await?
+                .len()
+        );
+        let different = Order {
+            id,
+            ..Order::new(table_num, 5)
+        };
+        let result = sync_order(&pg_sql_order_repository, &different).await;
+        assert!(matches!(result, Err(RepositoryError::OrderIdConflict(_))));
+        // Cancelling a plate doesn't make the order sent first a conflict.
+        let key = TableItemKey::from(&order);
+        cancel_order_item_from_table(&pg_sql_order_repository, key, &Cancellation::default())
+            .await?
+            .unwrap();
+        assert_eq!(
+            SyncOutcome::Unchanged,
+            sync_order(&pg_sql_order_repository, &order).await?
+        );
+        let batch = [Order::new(table_num, 6), different];
+        assert!(create_orders(&pg_sql_order_repository, &batch)
+            .await
+            .is_err());
+        assert_eq!(
+            1,
+            read_orders_by_table(&pg_sql_order_repository, table_num)
+                .await?
+                .len()
+        );
+        // Orders already stored, or earlier in the batch, aren't created again.
+        let fresh = Order::new(table_num, 6);
+        let batch = [order.clone(), fresh.clone(), fresh];
+        assert_eq!(
+            vec![
+                SyncOutcome::Unchanged,
+                SyncOutcome::Created,
+                SyncOutcome::Unchanged
+            ],
+            create_orders(&pg_sql_order_repository, &batch).await?
+        );
+        Ok(())
+    }
+
+    /// Cancelled orders keep why and by whom, and leave the listings.
+    #[actix_web::test]
+    async fn test_cancellations() -> Result<(), Box<dyn stdErr>> {
+        let (_db, pool) = setup_pg_test_db().await?;
+        let pg_sql_order_repository = PgSqlOrderRepository::new(pool.clone());
+        let table_num = TABLES_QTY + 5;
+        let plates = Order::with_quantity(table_num, 3, 2);
+        plates.create(&pg_sql_order_repository).await?;
+        let cancellation = Cancellation {
+            reason: Some("Customer left".to_string()),
+            actor: Some("manager".to_string()),
+        };
+        let plate = cancel_order_item_from_table(
+            &pg_sql_order_repository,
+            TableItemKey::from(&plates),
+            &cancellation,
+        )
+        .await?
+        .unwrap();
+        assert_ne!(plates.id, plate.order_id);
+        assert_eq!((1, OrderStatus::Cancelled), (plate.quantity, plate.status));
+        assert_eq!(cancellation.reason, plate.cancel_reason);
+        assert_eq!(cancellation.actor, plate.cancelled_by);
+        let orders = read_orders_by_table(&pg_sql_order_repository, table_num).await?;
+        assert_eq!(1, orders.len());
+        assert_eq!((plates.id, 1), (orders[0].order_id, orders[0].quantity));
+        let whole = cancel_order(&pg_sql_order_repository, plates.id, &cancellation)
+            .await?
+            .unwrap();
+        assert_eq!(plates.id, whole.order_id);
+        let result = cancel_order(&pg_sql_order_repository, plates.id, &cancellation).await;
+        assert!(matches!(result, Err(RepositoryError::IllegalTransition(_))));
+        assert!(read_orders_by_table(&pg_sql_order_repository, table_num)
+            .await?
+            .is_empty());
+        let filter = CancellationFilter {
+            table_number: Some(table_num),
+            ..Default::default()
+        };
+        let cancelled = read_cancellations(&pg_sql_order_repository, &filter).await?;
+        let ids: Vec<Uuid> = cancelled.iter().map(|o| o.order_id).collect();
+        assert_eq!(vec![whole.order_id, plate.order_id], ids);
+        let filter = CancellationFilter {
+            since: whole.cancelled_at,
+            ..filter
+        };
+        assert_eq!(
+            1,
+            read_cancellations(&pg_sql_order_repository, &filter)
+                .await?
+                .len()
+        );
+        let search = OrderSearch {
+            table_from: Some(table_num),
+            table_to: Some(table_num),
+            ..Default::default()
+        };
+        assert!(search_orders(&pg_sql_order_repository, &search, 10)
+            .await?
+            .orders
+            .is_empty());
+        Ok(())
+    }
+
+    /// Order changes are notified to the other servers sharing the database.
+    #[actix_web::test]
+    async fn test_change_feed() -> Result<(), Box<dyn stdErr>> {
+        let (_db, pool) = setup_pg_test_db().await?;
+        let pg_sql_order_repository = PgSqlOrderRepository::new(pool.clone());
+        let table_num = TABLES_QTY + 8;
+        let mut listener = PgListener::connect_with(pg_sql_order_repository.pool()).await?;
+        listener.listen(ORDER_CHANGES_CHANNEL).await?;
+        let this_server: String = sqlx::query_scalar("SELECT current_setting('application_name')")
+            .fetch_one(pg_sql_order_repository.pool())
+            .await?;
+        let plates = Order::with_quantity(table_num, 3, 2);
+        plates.create(&pg_sql_order_repository).await?;
+        let patch = OrderPatch {
+            quantity: Some(3),
+            ..Default::default()
+        };
+        update_order(&pg_sql_order_repository, plates.id, &patch).await?;
+        transition_order(&pg_sql_order_repository, plates.id, OrderStatus::Cooking).await?;
+        // Splitting a plate off also changes the quantity of the order.
+        let plate = cancel_order_item_from_table(
+            &pg_sql_order_repository,
+            TableItemKey::from(&plates),
+            &Cancellation::default(),
+        )
+        .await?
+        .unwrap();
+        let cancelled = cancel_order(
+            &pg_sql_order_repository,
+            plates.id,
+            &Cancellation::default(),
+        )
+        .await?
+        .unwrap();
+        let mut changes = vec![];
+        for _ in 0..6 {
+            let notification = listener.recv().await?;
+            assert!(order_event(notification.payload(), &this_server)?.is_none());
+            changes.push(order_event(notification.payload(), "another-server")?.unwrap());
+        }
+        let kinds: Vec<(OrderEventKind, Uuid, OrderStatus)> = changes
+            .iter()
+            .map(|event| (event.kind, event.order_id, event.status))
+            .collect();
+        assert_eq!(
+            vec![
+                (
+                    OrderEventKind::OrderCreated,
+                    plates.id,
+                    OrderStatus::Ordered
+                ),
+                (
+                    OrderEventKind::OrderUpdated,
+                    plates.id,
+                    OrderStatus::Ordered
+                ),
+                (
+                    OrderEventKind::OrderStatusChanged,
+                    plates.id,
+                    OrderStatus::Cooking
+                ),
+                (
+                    OrderEventKind::OrderCancelled,
+                    plate.order_id,
+                    OrderStatus::Cancelled
+                ),
+                (
+                    OrderEventKind::OrderUpdated,
+                    plates.id,
+                    OrderStatus::Cooking
+                ),
+                (
+                    OrderEventKind::OrderCancelled,
+                    plates.id,
+                    OrderStatus::Cancelled
+                ),
+            ],
+            kinds
+        );
+        assert_eq!(
+            (table_num, 3),
+            (changes[0].table_number, changes[0].menu_item_id)
+        );
+        assert_eq!(cancelled.created_at, changes[0].at);
+        assert_eq!((3, 2), (changes[1].quantity, changes[4].quantity));
+        assert_eq!(cancelled.cancelled_at, Some(changes[5].at));
         Ok(())
     }
 
     /// Helper function to setup connection to the test PostgreSQL DB.
     ///
-    /// It resets clean all tables and populates with initial types of food dishes.
-    async fn setup_pg_test_db() -> Result<PgPool, sqlx::Error> {
+    /// It resets clean all tables and populates with initial types of food dishes. The tests share
+    /// the database, so each one holds the returned guard until it's done.
+    async fn setup_pg_test_db() -> Result<(MutexGuard<'static, ()>, PgPool), sqlx::Error> {
+        static TEST_DB: OnceLock<Mutex<()>> = OnceLock::new();
+        let guard = TEST_DB.get_or_init(|| Mutex::new(())).lock().await;
         dotenvy::dotenv().ok(); // Loads the .env file with the test database secrets.
 
         // Connect to the db
@@ -90,6 +987,9 @@ mod pg_sql_tests {
             .await?;
         println!("OK");
 
+        // Bring the schema up to date
+        sqlx::migrate!().run(&pool).await?;
+
         // Clean db
         sqlx::query!("TRUNCATE orders CASCADE")
             .execute(&pool)
@@ -98,8 +998,24 @@ mod pg_sql_tests {
         sqlx::query!("TRUNCATE menu_items CASCADE")
             .execute(&pool)
             .await?;
+
+        sqlx::query!("TRUNCATE tables CASCADE")
+            .execute(&pool)
+            .await?;
         println!(r#"Database reset ... OK"#,);
 
+        // Populate the floor plan
+        sqlx::query!(
+            "INSERT INTO tables (table_number, seats, section)
+             SELECT table_number, $3, $4 FROM generate_series($1::INT, $2::INT) AS table_number",
+            DEFAULT_MIN_TABLE_NUMBER,
+            DEFAULT_MAX_TABLE_NUMBER,
+            DEFAULT_TABLE_SEATS,
+            DEFAULT_TABLE_SECTION,
+        )
+        .execute(&pool)
+        .await?;
+
         // Populate food menu item types
         print!("Populating DB with food menu items ... ");
         let mut rng = rand::thread_rng();
@@ -108,8 +1024,13 @@ mod pg_sql_tests {
             futures.push(insert_menu_item(&pool, i, item, rng.gen_range(5..15)));
         }
         try_join_all(futures).await?;
+        sqlx::query!(
+            "SELECT setval(pg_get_serial_sequence('menu_items', 'id'), MAX(id)) FROM menu_items"
+        )
+        .fetch_one(&pool)
+        .await?;
         println!("OK");
-        Ok(pool)
+        Ok((guard, pool))
     }
 
     /// Helper function to insert a new food menu item into the database.
@@ -130,3 +1051,1699 @@ mod pg_sql_tests {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod in_memory_tests {
+    use crate::billing::BillingRates;
+    use crate::constants::{MAX_CANCELLATION_TEXT_LENGTH, MAX_IDEMPOTENCY_KEY_LENGTH};
+    use crate::domain::*;
+    use crate::repository::{InMemoryOrderRepository, RepositoryError};
+    use chrono::Utc;
+    use rand::Rng;
+    use std::error::Error as stdErr;
+    use uuid::Uuid;
+
+    /// Same CRUD scenario as the PostgreSQL tests, without a database.
+    #[actix_web::test]
+    async fn test_create_read_delete_orders() -> Result<(), Box<dyn stdErr>> {
+        const TABLES_QTY: i32 = 100;
+        const TOTAL_REQS_PER_CLIENT: i32 = 50;
+
+        let repo = InMemoryOrderRepository::new();
+        let mut rng = rand::thread_rng();
+
+        let orders = read_orders_by_table(&repo, 1).await?;
+        assert_eq!(0, orders.len());
+
+        //CREATE and READ
+        for table_num in 1..=TABLES_QTY {
+            for _ in 1..=TOTAL_REQS_PER_CLIENT {
+                let order = Order::new(table_num, rng.gen_range(1..50));
+                let uuid = order.create(&repo).await?;
+                assert_eq!(4, uuid.get_version_num());
+            }
+            let orders = read_orders_by_table(&repo, table_num).await?;
+            assert_eq!(TOTAL_REQS_PER_CLIENT, orders.len() as i32);
+            for order in orders {
+                let order = read_order_item_from_table(&repo, TableItemKey::from(&order)).await?;
+                assert!(order.is_some());
+            }
+        }
+
+        //DELETE
+        for table_num in 1..=TABLES_QTY {
+            let orders = read_orders_by_table(&repo, table_num).await?;
+            assert_eq!(TOTAL_REQS_PER_CLIENT, orders.len() as i32);
+            for order in orders {
+                let cancelled = cancel_order_item_from_table(
+                    &repo,
+                    TableItemKey::from(&order),
+                    &Cancellation::default(),
+                )
+                .await?;
+                assert!(cancelled.is_some());
+            }
+            assert_eq!(0, read_orders_by_table(&repo, table_num).await?.len());
+        }
+
+        Ok(())
+    }
+
+    /// Reading and cancelling by menu item and table must target the latest `created_at`.
+    #[actix_web::test]
+    async fn test_latest_order_semantics() -> Result<(), Box<dyn stdErr>> {
+        let repo = InMemoryOrderRepository::new();
+        let mut older = Order::new(3, 7);
+        older.created_at -= chrono::Duration::minutes(10);
+        let newer = Order::new(3, 7);
+        // Insert the newest first so insertion order can't be mistaken for recency.
+        newer.create(&repo).await?;
+        older.create(&repo).await?;
+
+        let key = TableItemKey::from(&newer);
+        let latest = read_order_item_from_table(&repo, key).await?.unwrap();
+        assert_eq!(newer.id, latest.order_id);
+        let by_id = read_order_by_id(&repo, older.id).await?.unwrap();
+        assert_eq!(older.created_at, by_id.created_at);
+        let listed: Vec<Uuid> = read_orders_by_table(&repo, 3)
+            .await?
+            .iter()
+            .map(|order| order.order_id)
+            .collect();
+        assert_eq!(vec![older.id, newer.id], listed);
+
+        assert!(
+            cancel_order_item_from_table(&repo, key, &Cancellation::default())
+                .await?
+                .is_some()
+        );
+        let remaining = read_orders_by_table(&repo, 3).await?;
+        assert_eq!(1, remaining.len());
+        assert_eq!(older.id, remaining[0].order_id);
+
+        assert!(cancel_order(&repo, older.id, &Cancellation::default())
+            .await?
+            .is_some());
+        let result = cancel_order(&repo, older.id, &Cancellation::default()).await;
+        assert!(matches!(result, Err(RepositoryError::IllegalTransition(_))));
+        assert!(
+            cancel_order(&repo, Uuid::new_v4(), &Cancellation::default())
+                .await?
+                .is_none()
+        );
+        assert!(read_orders_by_table(&repo, 3).await?.is_empty());
+
+        // Orders created at the same time are told apart by id, reads and cancels agree on the latest.
+        let first = Order::new(4, 7);
+        let twins = [
+            Order {
+                created_at: first.created_at,
+                ..Order::new(4, 7)
+            },
+            first,
+        ];
+        create_orders(&repo, &twins).await?;
+        let key = TableItemKey {
+            table_number: 4,
+            menu_item_id: 7,
+        };
+        let latest = read_order_item_from_table(&repo, key).await?.unwrap();
+        assert_eq!(twins.iter().map(|o| o.id).max(), Some(latest.order_id));
+        cancel_order_item_from_table(&repo, key, &Cancellation::default()).await?;
+        let cancelled = read_order_by_id(&repo, latest.order_id).await?.unwrap();
+        assert_eq!(OrderStatus::Cancelled, cancelled.status);
+
+        // Once the party left, their orders are no longer the latest of the table.
+        let rates = BillingRates {
+            service_charge_bps: 0,
+            tax_rate_bps: 0,
+        };
+        record_payment(&repo, &repo, &repo, 4, Tender::Voucher, 1_000_000, rates).await?;
+        close_table_session(&repo, 4, rates, Utc::now()).await?;
+        assert!(read_order_item_from_table(&repo, key).await?.is_none());
+        assert!(
+            cancel_order_item_from_table(&repo, key, &Cancellation::default())
+                .await?
+                .is_none()
+        );
+        let next_party = Order::new(4, 7);
+        next_party.create(&repo).await?;
+        let latest = read_order_item_from_table(&repo, key).await?.unwrap();
+        assert_eq!(next_party.id, latest.order_id);
+        Ok(())
+    }
+
+    /// Pages follow each other without overlap, whatever the sort, and filters apply before the limit.
+    #[actix_web::test]
+    async fn test_order_pages() -> Result<(), Box<dyn stdErr>> {
+        let repo = InMemoryOrderRepository::new();
+        let orders: Vec<Order> = (0..10)
+            .map(|i| {
+                let mut order = Order::new(6, i % 3);
+                order.created_at -= chrono::Duration::minutes(10 - i as i64);
+                order
+            })
+            .collect();
+        create_orders(&repo, &orders).await?;
+
+        let mut filter = TableOrdersFilter::default();
+        let first = read_orders_page(&repo, 6, &filter, 4).await?;
+        assert!(first.has_more);
+        assert_eq!(orders[0].id, first.orders[0].order_id);
+        filter.cursor = Some(first.next_cursor.unwrap().parse().unwrap());
+        let second = read_orders_page(&repo, 6, &filter, 4).await?;
+        assert_eq!(orders[4].id, second.orders[0].order_id);
+        filter.cursor = Some(second.next_cursor.unwrap().parse().unwrap());
+        let last = read_orders_page(&repo, 6, &filter, 4).await?;
+        assert_eq!(2, last.orders.len());
+        assert!(!last.has_more);
+        assert!(last.next_cursor.is_none());
+
+        let filter = TableOrdersFilter {
+            menu_item_id: Some(1),
+            created_from: Some(orders[4].created_at),
+            sort: OrderSort::CookingTime,
+            ..Default::default()
+        };
+        let page = read_orders_page(&repo, 6, &filter, 10).await?;
+        let ids: Vec<Uuid> = page.orders.iter().map(|order| order.order_id).collect();
+        assert_eq!(vec![orders[4].id, orders[7].id], ids);
+
+        assert!("not-a-cursor".parse::<OrderCursor>().is_err());
+        assert!(validate_order_listing(50, None, None).is_empty());
+        let violations =
+            validate_order_listing(0, Some(orders[1].created_at), Some(orders[0].created_at));
+        assert_eq!(
+            vec!["limit", "created_to"],
+            violations
+                .iter()
+                .map(|v| v.field.as_str())
+                .collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
+    /// Search spans every table and combines its filters, item names match case-insensitively and literally.
+    #[actix_web::test]
+    async fn test_order_search() -> Result<(), Box<dyn stdErr>> {
+        let menu = ["Shoyu ramen", "Miso ramen", "Gyoza", "100% matcha"]
+            .iter()
+            .enumerate()
+            .map(|(id, item_name)| MenuItem {
+                id: id as i32,
+                item_name: item_name.to_string(),
+                cooking_time: 10 - id as i32,
+                price: 1000,
+                retired_at: None,
+            })
+            .collect();
+        let repo = InMemoryOrderRepository::with_menu(menu);
+        let orders: Vec<Order> = (0..8).map(|i| Order::new(10 + i, i % 4)).collect();
+        for order in &orders {
+            order.create(&repo).await?;
+        }
+        transition_order(&repo, orders[1].id, OrderStatus::Cooking).await?;
+
+        let search = OrderSearch {
+            item_name: Some("RAMEN".to_string()),
+            table_from: Some(11),
+            ..Default::default()
+        };
+        let page = search_orders(&repo, &search, 50).await?;
+        let tables: Vec<i32> = page.orders.iter().map(|o| o.table_number).collect();
+        assert_eq!(vec![11, 14, 15], tables);
+
+        let search = OrderSearch {
+            status: Some(OrderStatus::Cooking),
+            ..Default::default()
+        };
+        let page = search_orders(&repo, &search, 50).await?;
+        assert_eq!(1, page.orders.len());
+        assert_eq!(orders[1].id, page.orders[0].order_id);
+
+        let search = OrderSearch {
+            item_name: Some("0% m".to_string()),
+            table_to: Some(13),
+            sort: OrderSort::CookingTime,
+            ..Default::default()
+        };
+        let page = search_orders(&repo, &search, 50).await?;
+        assert_eq!(
+            vec![orders[3].id],
+            page.orders.iter().map(|o| o.order_id).collect::<Vec<_>>()
+        );
+
+        let search = OrderSearch {
+            table_from: Some(20),
+            table_to: Some(10),
+            item_name: Some(" ".to_string()),
+            ..Default::default()
+        };
+        let fields: Vec<String> = validate_order_search(&search, 50)
+            .into_iter()
+            .map(|v| v.field)
+            .collect();
+        assert_eq!(vec!["table_to", "item_name"], fields);
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_update_order() -> Result<(), Box<dyn stdErr>> {
+        let repo = InMemoryOrderRepository::new();
+        let order = Order::new(1, 10);
+        order.create(&repo).await?;
+
+        let patch = OrderPatch {
+            table_number: Some(4),
+            menu_item_id: Some(11),
+            quantity: Some(3),
+        };
+        let updated = update_order(&repo, order.id, &patch).await?.unwrap();
+        assert_eq!(order.id, updated.order_id);
+        assert_eq!(4, updated.table_number);
+        assert_eq!(11, updated.menu_item_id);
+        assert_eq!(3, updated.quantity);
+        assert_eq!(order.created_at, updated.created_at);
+        assert_eq!(0, read_orders_by_table(&repo, 1).await?.len());
+
+        let patch = OrderPatch {
+            menu_item_id: Some(999),
+            ..Default::default()
+        };
+        let result = update_order(&repo, order.id, &patch).await;
+        assert!(matches!(
+            result,
+            Err(RepositoryError::MenuItemNotFound(999))
+        ));
+
+        let missing = update_order(&repo, uuid::Uuid::new_v4(), &OrderPatch::default()).await?;
+        assert!(missing.is_none());
+
+        // Only orders the kitchen hasn't started can change.
+        let move_to_5 = OrderPatch {
+            table_number: Some(5),
+            ..Default::default()
+        };
+        transition_order(&repo, order.id, OrderStatus::Cooking).await?;
+        let result = update_order(&repo, order.id, &move_to_5).await;
+        assert!(matches!(
+            result,
+            Err(RepositoryError::IllegalTransition(IllegalTransition {
+                from: OrderStatus::Cooking,
+                to: OrderStatus::Ordered
+            }))
+        ));
+        let cancelled = Order::new(4, 10);
+        cancelled.create(&repo).await?;
+        cancel_order(&repo, cancelled.id, &Cancellation::default()).await?;
+        let result = update_order(&repo, cancelled.id, &move_to_5).await;
+        assert!(matches!(result, Err(RepositoryError::IllegalTransition(_))));
+
+        // The bill of a closed session is final.
+        let closed = Order::new(6, 10);
+        closed.create(&repo).await?;
+        let rates = BillingRates {
+            service_charge_bps: 0,
+            tax_rate_bps: 0,
+        };
+        record_payment(&repo, &repo, &repo, 6, Tender::Voucher, 1_000_000, rates).await?;
+        let session = close_table_session(&repo, 6, rates, Utc::now())
+            .await?
+            .unwrap();
+        let result = update_order(&repo, closed.id, &move_to_5).await;
+        assert!(matches!(result, Err(RepositoryError::SessionClosed(6))));
+        let result = update_order(&repo, closed.id, &OrderPatch::from(&order)).await;
+        assert!(matches!(result, Err(RepositoryError::SessionClosed(6))));
+
+        // An order moved to a table whose party left joins a new session.
+        let moved = Order::new(7, 10);
+        moved.create(&repo).await?;
+        let patch = OrderPatch {
+            table_number: Some(6),
+            ..Default::default()
+        };
+        let moved = update_order(&repo, moved.id, &patch).await?.unwrap();
+        assert!(moved.session_id.is_some());
+        assert_ne!(Some(session.id), moved.session_id);
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_order_quantity() -> Result<(), Box<dyn stdErr>> {
+        let repo = InMemoryOrderRepository::new();
+        let order = Order::with_quantity(2, 29, 4);
+        order.create(&repo).await?;
+        assert_eq!(
+            4,
+            read_order_by_id(&repo, order.id).await?.unwrap().quantity
+        );
+
+        // Cancelling by table and menu item takes one plate off at a time.
+        let key = TableItemKey {
+            table_number: 2,
+            menu_item_id: 29,
+        };
+        for remaining in (1..4).rev() {
+            assert!(
+                cancel_order_item_from_table(&repo, key, &Cancellation::default())
+                    .await?
+                    .is_some()
+            );
+            let orders = read_orders_by_table(&repo, 2).await?;
+            assert_eq!(remaining, orders[0].quantity);
+        }
+        assert!(
+            cancel_order_item_from_table(&repo, key, &Cancellation::default())
+                .await?
+                .is_some()
+        );
+        assert_eq!(0, read_orders_by_table(&repo, 2).await?.len());
+        assert!(
+            cancel_order_item_from_table(&repo, key, &Cancellation::default())
+                .await?
+                .is_none()
+        );
+
+        let result = Order::with_quantity(2, 29, 0).create(&repo).await;
+        assert!(matches!(result, Err(RepositoryError::InvalidQuantity(0))));
+        Ok(())
+    }
+
+    /// Cancelled orders keep their reason and actor, leave the listings and show up in the audit.
+    #[actix_web::test]
+    async fn test_order_cancellations() -> Result<(), Box<dyn stdErr>> {
+        let repo = InMemoryOrderRepository::new();
+        let plates = Order::with_quantity(5, 12, 3);
+        let served = Order::new(5, 13);
+        let elsewhere = Order::new(6, 12);
+        create_orders(&repo, &[plates.clone(), served.clone(), elsewhere.clone()]).await?;
+        for status in [
+            OrderStatus::Cooking,
+            OrderStatus::Ready,
+            OrderStatus::Served,
+        ] {
+            transition_order(&repo, served.id, status).await?;
+        }
+
+        // A single plate is split off, the rest of the order stays on the table.
+        let cancellation = Cancellation {
+            reason: Some("Allergy".to_string()),
+            actor: Some("waiter-7".to_string()),
+        };
+        let key = TableItemKey::from(&plates);
+        let plate = cancel_order_item_from_table(&repo, key, &cancellation)
+            .await?
+            .unwrap();
+        assert_ne!(plates.id, plate.order_id);
+        assert_eq!(1, plate.quantity);
+        assert_eq!(OrderStatus::Cancelled, plate.status);
+        assert_eq!(cancellation.reason, plate.cancel_reason);
+        assert_eq!(cancellation.actor, plate.cancelled_by);
+        let kept = read_order_by_id(&repo, plates.id).await?.unwrap();
+        assert_eq!((2, OrderStatus::Ordered), (kept.quantity, kept.status));
+
+        // Served orders can't be taken back.
+        let key = TableItemKey::from(&served);
+        let result = cancel_order_item_from_table(&repo, key, &cancellation).await;
+        assert!(matches!(result, Err(RepositoryError::IllegalTransition(_))));
+
+        let whole = cancel_order(&repo, elsewhere.id, &Cancellation::default())
+            .await?
+            .unwrap();
+        assert!(whole.cancelled_at.is_some() && whole.cancel_reason.is_none());
+        assert!(read_orders_by_table(&repo, 6).await?.is_empty());
+        let table = read_orders_by_table(&repo, 5).await?;
+        assert!(table.iter().all(|o| o.status != OrderStatus::Cancelled));
+        assert_eq!(2, table.len());
+
+        // Searches skip cancelled orders unless they ask for them.
+        let search = OrderSearch {
+            menu_item_id: Some(12),
+            ..Default::default()
+        };
+        assert_eq!(1, search_orders(&repo, &search, 10).await?.orders.len());
+        let search = OrderSearch {
+            status: Some(OrderStatus::Cancelled),
+            ..search
+        };
+        assert_eq!(2, search_orders(&repo, &search, 10).await?.orders.len());
+
+        // The audit lists the latest cancellation first.
+        let all = read_cancellations(&repo, &CancellationFilter::default()).await?;
+        let ids: Vec<Uuid> = all.iter().map(|o| o.order_id).collect();
+        assert_eq!(vec![elsewhere.id, plate.order_id], ids);
+        let filter = CancellationFilter {
+            table_number: Some(5),
+            ..Default::default()
+        };
+        assert_eq!(1, read_cancellations(&repo, &filter).await?.len());
+        let filter = CancellationFilter {
+            until: plate.cancelled_at,
+            ..Default::default()
+        };
+        assert!(read_cancellations(&repo, &filter).await?.is_empty());
+        let filter = CancellationFilter {
+            since: whole.cancelled_at,
+            ..Default::default()
+        };
+        assert_eq!(1, read_cancellations(&repo, &filter).await?.len());
+
+        assert!(validate_cancellation(&cancellation).is_empty());
+        let invalid = Cancellation {
+            reason: Some(" ".to_string()),
+            actor: Some("x".repeat(MAX_CANCELLATION_TEXT_LENGTH + 1)),
+        };
+        let fields: Vec<String> = validate_cancellation(&invalid)
+            .into_iter()
+            .map(|violation| violation.field)
+            .collect();
+        assert_eq!(vec!["reason", "actor"], fields);
+        Ok(())
+    }
+
+    /// A retried creation with the same key gets the first order back until the key leaves the window.
+    #[actix_web::test]
+    async fn test_idempotent_create() -> Result<(), Box<dyn stdErr>> {
+        let repo = InMemoryOrderRepository::new();
+        let since = Utc::now() - chrono::Duration::hours(1);
+        let order = Order::with_quantity(8, 3, 2);
+        assert_eq!(None, replay_order(&repo, "retry-1", &order, since).await?);
+        assert_eq!(
+            (order.id, SyncOutcome::Created),
+            create_order_once(&repo, "retry-1", &order, since).await?
+        );
+
+        // The retry mints a new id, the key maps it back to the first one.
+        let retry = Order::with_quantity(8, 3, 2);
+        assert_eq!(
+            Some(order.id),
+            replay_order(&repo, "retry-1", &retry, since).await?
+        );
+        assert_eq!(
+            (order.id, SyncOutcome::Unchanged),
+            create_order_once(&repo, "retry-1", &retry, since).await?
+        );
+        assert_eq!(1, read_orders_by_table(&repo, 8).await?.len());
+
+        let other = Order::with_quantity(8, 3, 1);
+        let result = replay_order(&repo, "retry-1", &other, since).await;
+        assert!(matches!(
+            result,
+            Err(RepositoryError::IdempotencyKeyReused(IdempotencyKeyReused { key })) if key == "retry-1"
+        ));
+
+        // Once out of the window, the key is free for a new order.
+        let later = Utc::now() + chrono::Duration::seconds(1);
+        assert_eq!(None, replay_order(&repo, "retry-1", &other, later).await?);
+        assert_eq!(
+            (other.id, SyncOutcome::Created),
+            create_order_once(&repo, "retry-1", &other, later).await?
+        );
+        assert_eq!(2, read_orders_by_table(&repo, 8).await?.len());
+
+        // An order already synced under its id isn't created again with a new key.
+        assert_eq!(
+            (other.id, SyncOutcome::Unchanged),
+            create_order_once(&repo, "synced", &other, since).await?
+        );
+        assert_eq!(2, read_orders_by_table(&repo, 8).await?.len());
+
+        // A rejected order doesn't hold the key.
+        let unknown = Order::new(8, 999);
+        assert!(create_order_once(&repo, "retry-2", &unknown, since)
+            .await
+            .is_err());
+        assert_eq!(None, replay_order(&repo, "retry-2", &unknown, since).await?);
+
+        assert!(validate_idempotency_key("retry-1").is_empty());
+        assert_eq!(1, validate_idempotency_key(" ").len());
+        assert_eq!(
+            1,
+            validate_idempotency_key(&"k".repeat(MAX_IDEMPOTENCY_KEY_LENGTH + 1)).len()
+        );
+        Ok(())
+    }
+
+    /// Ids minted by devices can be sent again: the same order is a no-op, a different one a conflict.
+    #[actix_web::test]
+    async fn test_client_order_ids() -> Result<(), Box<dyn stdErr>> {
+        let repo = InMemoryOrderRepository::new();
+        let id = Uuid::new_v4();
+        let order = Order {
+            id,
+            ..Order::with_quantity(9, 4, 2)
+        };
+        assert_eq!(SyncOutcome::Created, sync_order(&repo, &order).await?);
+        let resent = Order {
+            id,
+            ..Order::with_quantity(9, 4, 2)
+        };
+        assert_eq!(SyncOutcome::Unchanged, sync_order(&repo, &resent).await?);
+        assert_eq!(id, resent.create(&repo).await?);
+        assert_eq!(1, read_orders_by_table(&repo, 9).await?.len());
+
+        let different = Order {
+            id,
+            ..Order::with_quantity(9, 4, 3)
+        };
+        let result = sync_order(&repo, &different).await;
+        assert!(
+            matches!(result, Err(RepositoryError::OrderIdConflict(conflict)) if conflict == id)
+        );
+        let stored = read_order_by_id(&repo, id).await?.unwrap();
+        assert_eq!(2, stored.quantity);
+
+        // Re-syncing compares with the order as first sent, not as it was changed since.
+        let key = TableItemKey::from(&order);
+        let plate = cancel_order_item_from_table(&repo, key, &Cancellation::default())
+            .await?
+            .unwrap();
+        assert_eq!(1, read_order_by_id(&repo, id).await?.unwrap().quantity);
+        assert_eq!(SyncOutcome::Unchanged, sync_order(&repo, &resent).await?);
+        let result = sync_order(&repo, &different).await;
+        assert!(matches!(result, Err(RepositoryError::OrderIdConflict(_))));
+        // The plate split off was never sent by a device.
+        let split = Order {
+            id: plate.order_id,
+            ..Order::new(9, 4)
+        };
+        let result = sync_order(&repo, &split).await;
+        assert!(matches!(result, Err(RepositoryError::OrderIdConflict(_))));
+        let patch = OrderPatch {
+            quantity: Some(5),
+            ..Default::default()
+        };
+        update_order(&repo, id, &patch).await?.unwrap();
+        assert_eq!(SyncOutcome::Unchanged, sync_order(&repo, &resent).await?);
+        update_order(&repo, id, &OrderPatch::from(&order))
+            .await?
+            .unwrap();
+
+        // Batches skip the orders already stored, and a conflict anywhere rejects all of them.
+        let fresh = Order::new(9, 5);
+        let batch = [resent.clone(), fresh.clone(), fresh.clone()];
+        assert_eq!(
+            vec![
+                SyncOutcome::Unchanged,
+                SyncOutcome::Created,
+                SyncOutcome::Unchanged
+            ],
+            create_orders(&repo, &batch).await?
+        );
+        assert_eq!(2, read_orders_by_table(&repo, 9).await?.len());
+        let conflicting = Order {
+            id: fresh.id,
+            ..Order::new(9, 6)
+        };
+        let batch = [Order::new(9, 7), conflicting];
+        let result = create_orders(&repo, &batch).await;
+        assert!(matches!(result, Err(RepositoryError::OrderIdConflict(_))));
+        assert_eq!(2, read_orders_by_table(&repo, 9).await?.len());
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_create_orders_is_atomic() -> Result<(), Box<dyn stdErr>> {
+        let repo = InMemoryOrderRepository::new();
+        let orders = vec![Order::new(5, 1), Order::with_quantity(5, 2, 3)];
+        let outcomes = create_orders(&repo, &orders).await?;
+        assert_eq!(vec![SyncOutcome::Created; 2], outcomes);
+        assert_eq!(2, read_orders_by_table(&repo, 5).await?.len());
+
+        let orders = vec![Order::new(6, 1), Order::new(6, 999), Order::new(6, 2)];
+        let result = create_orders(&repo, &orders).await;
+        assert!(matches!(
+            result,
+            Err(RepositoryError::MenuItemNotFound(999))
+        ));
+        assert_eq!(0, read_orders_by_table(&repo, 6).await?.len());
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_order_lifecycle() -> Result<(), Box<dyn stdErr>> {
+        let repo = InMemoryOrderRepository::new();
+        let order = Order::new(1, 3);
+        order.create(&repo).await?;
+        let created = read_order_by_id(&repo, order.id).await?.unwrap();
+        assert_eq!(OrderStatus::Ordered, created.status);
+
+        // Can't serve an order that isn't ready.
+        let result = transition_order(&repo, order.id, OrderStatus::Served).await;
+        assert!(matches!(
+            result,
+            Err(RepositoryError::IllegalTransition(IllegalTransition {
+                from: OrderStatus::Ordered,
+                to: OrderStatus::Served
+            }))
+        ));
+
+        for status in [
+            OrderStatus::Cooking,
+            OrderStatus::Ready,
+            OrderStatus::Served,
+        ] {
+            let updated = transition_order(&repo, order.id, status).await?.unwrap();
+            assert_eq!(status, updated.status);
+        }
+        let served = read_order_by_id(&repo, order.id).await?.unwrap();
+        assert!(served.cooking_at.is_some() && served.ready_at.is_some());
+        assert!(served.served_at.is_some() && served.cancelled_at.is_none());
+
+        // Served orders are final.
+        let result = transition_order(&repo, order.id, OrderStatus::Cancelled).await;
+        assert!(matches!(result, Err(RepositoryError::IllegalTransition(_))));
+
+        let missing = transition_order(&repo, uuid::Uuid::new_v4(), OrderStatus::Cooking).await?;
+        assert!(missing.is_none());
+        Ok(())
+    }
+
+    /// Bumping walks an order through the kitchen one status at a time, like the REST transitions.
+    #[actix_web::test]
+    async fn test_bump_order() -> Result<(), Box<dyn stdErr>> {
+        let repo = InMemoryOrderRepository::new();
+        let order = Order::new(1, 3);
+        order.create(&repo).await?;
+        for status in [
+            OrderStatus::Cooking,
+            OrderStatus::Ready,
+            OrderStatus::Served,
+        ] {
+            let bumped = bump_order(&repo, order.id).await?.unwrap();
+            assert_eq!(status, bumped.status);
+        }
+        let result = bump_order(&repo, order.id).await;
+        assert!(matches!(
+            result,
+            Err(RepositoryError::IllegalTransition(IllegalTransition {
+                from: OrderStatus::Served,
+                to: OrderStatus::Served
+            }))
+        ));
+
+        let cancelled = Order::new(2, 3);
+        cancelled.create(&repo).await?;
+        cancel_order(&repo, cancelled.id, &Cancellation::default()).await?;
+        let result = bump_order(&repo, cancelled.id).await;
+        assert!(matches!(result, Err(RepositoryError::IllegalTransition(_))));
+
+        assert!(bump_order(&repo, uuid::Uuid::new_v4()).await?.is_none());
+        assert_eq!(None, OrderStatus::Cancelled.next());
+        Ok(())
+    }
+
+    #[test]
+    fn test_order_status_transitions() {
+        use OrderStatus::*;
+        assert!(Ordered.can_transition_to(Cooking));
+        assert!(Ordered.can_transition_to(Cancelled));
+        assert!(Ready.can_transition_to(Cancelled));
+        assert!(!Ordered.can_transition_to(Ready));
+        assert!(!Served.can_transition_to(Cancelled));
+        assert!(!Cancelled.can_transition_to(Ordered));
+        assert!(!Cooking.can_transition_to(Cooking));
+    }
+
+    #[actix_web::test]
+    async fn test_estimated_ready_time() -> Result<(), Box<dyn stdErr>> {
+        let menu = vec![MenuItem {
+            id: 1,
+            item_name: "Ramen".to_string(),
+            cooking_time: 10,
+            price: 900,
+            retired_at: None,
+        }];
+        let repo = InMemoryOrderRepository::with_menu(menu);
+        let mut order = Order::new(1, 1);
+        order.created_at -= chrono::Duration::minutes(4);
+        order.create(&repo).await?;
+
+        let created = read_order_by_id(&repo, order.id).await?.unwrap();
+        let estimated_ready_at = order.created_at + chrono::Duration::minutes(10);
+        assert_eq!(Some(estimated_ready_at), created.estimated_ready_at);
+
+        let remaining =
+            read_remaining_time_by_table(&repo, 1, order.created_at + chrono::Duration::minutes(4))
+                .await?;
+        assert_eq!(1, remaining.len());
+        assert_eq!(6, remaining[0].remaining_minutes);
+        let late = read_remaining_time_by_table(
+            &repo,
+            1,
+            estimated_ready_at + chrono::Duration::minutes(3),
+        )
+        .await?;
+        assert_eq!(0, late[0].remaining_minutes);
+
+        // Once ready, the actual time replaces the estimate and nothing is left to wait for.
+        transition_order(&repo, order.id, OrderStatus::Cooking).await?;
+        let ready = transition_order(&repo, order.id, OrderStatus::Ready)
+            .await?
+            .unwrap();
+        assert_eq!(ready.ready_at, ready.estimated_ready_at);
+        assert!(read_remaining_time_by_table(&repo, 1, chrono::Utc::now())
+            .await?
+            .is_empty());
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_menu_management() -> Result<(), Box<dyn stdErr>> {
+        let repo = InMemoryOrderRepository::new();
+        let menu_len = read_menu_items(&repo, false).await?.len();
+
+        let item = NewMenuItem {
+            item_name: "Katsu Curry".to_string(),
+            cooking_time: 12,
+            price: 1200,
+        };
+        let item = create_menu_item(&repo, &item).await?;
+        assert_eq!(menu_len as i32, item.id);
+        assert_eq!(menu_len + 1, read_menu_items(&repo, false).await?.len());
+
+        let patch = MenuItemPatch {
+            item_name: Some("Katsu Kare".to_string()),
+            ..Default::default()
+        };
+        let renamed = update_menu_item(&repo, item.id, &patch).await?.unwrap();
+        assert_eq!("Katsu Kare", renamed.item_name);
+        assert_eq!(1200, renamed.price);
+
+        // Orders placed before retirement keep the item's details.
+        let order = Order::new(1, item.id);
+        order.create(&repo).await?;
+        retire_menu_item(&repo, item.id).await?.unwrap();
+        assert_eq!(menu_len, read_menu_items(&repo, false).await?.len());
+        assert_eq!(menu_len + 1, read_menu_items(&repo, true).await?.len());
+        let orders = read_orders_by_table(&repo, 1).await?;
+        assert_eq!("Katsu Kare", orders[0].item_name);
+
+        let result = Order::new(1, item.id).create(&repo).await;
+        assert!(matches!(result, Err(RepositoryError::MenuItemNotFound(_))));
+        assert!(retire_menu_item(&repo, 999).await?.is_none());
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_unknown_menu_item_is_rejected() {
+        let repo = InMemoryOrderRepository::new();
+        let result = Order::new(1, 999).create(&repo).await;
+        assert!(matches!(
+            result,
+            Err(RepositoryError::MenuItemNotFound(999))
+        ));
+        assert_eq!(0, read_orders_by_table(&repo, 1).await.unwrap().len());
+    }
+
+    #[actix_web::test]
+    async fn test_floor_plan() -> Result<(), Box<dyn stdErr>> {
+        let repo = InMemoryOrderRepository::new();
+        assert_eq!(500, read_tables(&repo).await?.len());
+
+        let result = Order::new(501, 1).create(&repo).await;
+        assert!(matches!(result, Err(RepositoryError::TableNotFound(501))));
+        let terrace = RestaurantTable {
+            table_number: 501,
+            seats: 2,
+            section: "terrace".to_string(),
+        };
+        define_table(&repo, &terrace).await?;
+        let order = Order::new(501, 1);
+        order.create(&repo).await?;
+
+        let result = remove_table(&repo, 501).await;
+        assert!(matches!(result, Err(RepositoryError::TableInUse(501))));
+        // Its sessions are kept as history, so the table stays in use.
+        cancel_order(&repo, order.id, &Cancellation::default()).await?;
+        let result = remove_table(&repo, 501).await;
+        assert!(matches!(result, Err(RepositoryError::TableInUse(501))));
+        let unused = RestaurantTable {
+            table_number: 502,
+            ..terrace
+        };
+        define_table(&repo, &unused).await?;
+        assert_eq!(1, remove_table(&repo, 502).await?);
+        assert_eq!(0, remove_table(&repo, 502).await?);
+
+        let invalid = RestaurantTable {
+            table_number: 0,
+            seats: 0,
+            section: " ".to_string(),
+        };
+        assert_eq!(3, validate_table(&(1..=500), &invalid).len());
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_table_sessions() -> Result<(), Box<dyn stdErr>> {
+        let repo = InMemoryOrderRepository::new();
+        assert!(read_current_session(&repo, 5).await?.is_none());
+
+        // The first order opens a session, seating the guests sets its party size.
+        let first = Order::new(5, 1);
+        first.create(&repo).await?;
+        let session = read_current_session(&repo, 5).await?.unwrap();
+        assert_eq!(None, session.party_size);
+        let seated = open_table_session(&repo, 5, 3, Utc::now()).await?;
+        assert_eq!(session.id, seated.id);
+        assert_eq!(Some(3), seated.party_size);
+
+        // A moved order joins the session of its new table.
+        let moved = Order::new(5, 2);
+        moved.create(&repo).await?;
+        let patch = OrderPatch {
+            table_number: Some(6),
+            ..Default::default()
+        };
+        let updated = update_order(&repo, moved.id, &patch).await?.unwrap();
+        let other = read_current_session(&repo, 6).await?.unwrap();
+        assert_eq!(Some(other.id), updated.session_id);
+        assert_eq!(1, read_orders_by_table(&repo, 5).await?.len());
+
+        // Closing is refused until the bill is paid.
+        let rates = BillingRates {
+            service_charge_bps: 0,
+            tax_rate_bps: 0,
+        };
+        let result = close_table_session(&repo, 5, rates, Utc::now()).await;
+        assert!(matches!(
+            result,
+            Err(RepositoryError::Payment(PaymentError::NotCovered {
+                paid: 0,
+                ..
+            }))
+        ));
+        record_payment(&repo, &repo, &repo, 5, Tender::Voucher, 1_000_000, rates).await?;
+
+        // Closing clears the table, the past session stays queryable.
+        close_table_session(&repo, 5, rates, Utc::now())
+            .await?
+            .unwrap();
+        assert!(close_table_session(&repo, 5, rates, Utc::now())
+            .await?
+            .is_none());
+
+        // A table where nothing was ordered can be closed right away.
+        open_table_session(&repo, 7, 2, Utc::now()).await?;
+        assert!(close_table_session(&repo, 7, rates, Utc::now())
+            .await?
+            .is_some());
+        assert!(read_orders_by_table(&repo, 5).await?.is_empty());
+        let history = read_orders_by_session(&repo, session.id).await?;
+        assert_eq!(first.id, history[0].order_id);
+
+        Order::new(5, 3).create(&repo).await?;
+        let sessions = read_table_sessions(&repo, 5).await?;
+        assert_eq!(2, sessions.len());
+        assert_eq!(session.id, sessions[1].id);
+        assert_eq!(1, read_orders_by_table(&repo, 5).await?.len());
+
+        let result = open_table_session(&repo, 999, 2, Utc::now()).await;
+        assert!(matches!(result, Err(RepositoryError::TableNotFound(999))));
+        assert_eq!(1, validate_party_size(0).len());
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_order_validation() -> Result<(), Box<dyn stdErr>> {
+        let repo = InMemoryOrderRepository::new();
+        let tables = 1..=20;
+        let valid = Order::with_quantity(20, 4, 2);
+        assert!(
+            validate_order(&repo, &repo, &tables, &OrderPatch::from(&valid))
+                .await?
+                .is_empty()
+        );
+
+        let invalid = Order::with_quantity(-3, 999, 0);
+        let violations = validate_order(&repo, &repo, &tables, &OrderPatch::from(&invalid)).await?;
+        let fields: Vec<&str> = violations.iter().map(|v| v.field.as_str()).collect();
+        assert_eq!(vec!["table_number", "quantity", "menu_item_id"], fields);
+
+        // Within the range, but not a table of the floor plan.
+        let patch = OrderPatch {
+            table_number: Some(550),
+            ..Default::default()
+        };
+        let violations = validate_order(&repo, &repo, &(1..=600), &patch).await?;
+        assert_eq!("Table 550 isn't on the floor plan.", violations[0].message);
+
+        // Retired items can't be ordered, and fields left unset aren't checked.
+        retire_menu_item(&repo, 4).await?;
+        let patch = OrderPatch {
+            menu_item_id: Some(4),
+            ..Default::default()
+        };
+        let violations = validate_order(&repo, &repo, &tables, &patch).await?;
+        assert_eq!(1, violations.len());
+        assert_eq!("[2].menu_item_id", violations[0].clone().at_index(2).field);
+        Ok(())
+    }
+
+    #[test]
+    fn test_menu_item_validation() {
+        let valid = NewMenuItem {
+            item_name: "Gyoza".to_string(),
+            cooking_time: 8,
+            price: 0,
+        };
+        assert!(validate_menu_item(&MenuItemPatch::from(&valid)).is_empty());
+
+        let fields = |patch: MenuItemPatch| -> Vec<String> {
+            validate_menu_item(&patch)
+                .into_iter()
+                .map(|v| v.field)
+                .collect()
+        };
+        let blank_name = NewMenuItem {
+            item_name: "  ".to_string(),
+            ..valid.clone()
+        };
+        assert_eq!(vec!["item_name"], fields(MenuItemPatch::from(&blank_name)));
+        let no_cooking_time = NewMenuItem {
+            cooking_time: 0,
+            ..valid.clone()
+        };
+        assert_eq!(
+            vec!["cooking_time"],
+            fields(MenuItemPatch::from(&no_cooking_time))
+        );
+        let negative_price = NewMenuItem {
+            price: -1,
+            ..valid.clone()
+        };
+        let violations = validate_menu_item(&MenuItemPatch::from(&negative_price));
+        assert_eq!("price", violations[0].field);
+        assert_eq!("Price can't be negative, got -1.", violations[0].message);
+
+        // Patches are checked the same way, and fields left unset aren't checked.
+        let patch = MenuItemPatch {
+            cooking_time: Some(-5),
+            ..Default::default()
+        };
+        assert_eq!(vec!["cooking_time"], fields(patch));
+        assert!(fields(MenuItemPatch::default()).is_empty());
+    }
+}
+
+#[cfg(test)]
+mod kitchen_tests {
+    use crate::domain::*;
+    use crate::kitchen::{schedule, KitchenScheduler};
+    use crate::repository::InMemoryOrderRepository;
+    use chrono::{Duration, Utc};
+    use std::error::Error as stdErr;
+
+    fn complete_order(minutes_ago: i64, cooking_time: i32, status: OrderStatus) -> CompleteOrder {
+        let created_at = Utc::now() - Duration::minutes(minutes_ago);
+        CompleteOrder {
+            order_id: uuid::Uuid::new_v4(),
+            table_number: 1,
+            session_id: None,
+            menu_item_id: 1,
+            created_at,
+            quantity: 1,
+            status,
+            cooking_at: (status == OrderStatus::Cooking).then_some(created_at),
+            ready_at: None,
+            served_at: None,
+            cancelled_at: None,
+            cancel_reason: None,
+            cancelled_by: None,
+            item_name: "Ramen".to_string(),
+            cooking_time,
+            price: 0,
+            estimated_ready_at: None,
+        }
+    }
+
+    /// With two slots, a third waiting order starts when the first slot frees up.
+    #[test]
+    fn test_schedule_respects_cooking_slots() {
+        let now = Utc::now();
+        let orders = vec![
+            complete_order(2, 10, OrderStatus::Ordered),
+            complete_order(1, 5, OrderStatus::Ordered),
+            complete_order(0, 8, OrderStatus::Ordered),
+        ];
+        let scheduled = schedule(&orders, 2, now);
+        assert_eq!(3, scheduled.len());
+        assert_eq!(now, scheduled[0].starts_at);
+        assert_eq!(now, scheduled[1].starts_at);
+        assert_eq!(scheduled[1].ready_at, scheduled[2].starts_at);
+        assert_eq!(scheduled[1].slot, scheduled[2].slot);
+        assert_eq!(now + Duration::minutes(13), scheduled[2].ready_at);
+    }
+
+    /// Orders already cooking keep their start time and hold their slot.
+    #[test]
+    fn test_schedule_keeps_cooking_orders() {
+        let now = Utc::now();
+        let cooking = complete_order(4, 10, OrderStatus::Cooking);
+        let orders = vec![
+            complete_order(3, 5, OrderStatus::Ordered),
+            cooking.clone(),
+            complete_order(1, 5, OrderStatus::Served),
+        ];
+        let scheduled = schedule(&orders, 1, now);
+        assert_eq!(2, scheduled.len());
+        assert_eq!(cooking.order_id, scheduled[0].order_id);
+        assert_eq!(cooking.created_at, scheduled[0].starts_at);
+        assert_eq!(scheduled[0].ready_at, scheduled[1].starts_at);
+    }
+
+    #[actix_web::test]
+    async fn test_scheduler_refresh() -> Result<(), Box<dyn stdErr>> {
+        let repo = InMemoryOrderRepository::new();
+        let kitchen = KitchenScheduler::new(2);
+        let order = Order::new(1, 4);
+        create_orders(&repo, &[order.clone(), Order::new(2, 5)]).await?;
+        assert!(kitchen.queue(Utc::now()).orders.is_empty());
+
+        kitchen.refresh(&repo).await?;
+        assert_eq!(2, kitchen.queue(Utc::now()).orders.len());
+
+        cancel_order(&repo, order.id, &Cancellation::default()).await?;
+        kitchen.refresh(&repo).await?;
+        let queue = kitchen.queue(Utc::now());
+        assert_eq!(2, queue.cooking_slots);
+        assert_eq!(1, queue.orders.len());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod events_tests {
+    use crate::change_feed::catch_up;
+    use crate::constants::EVENT_HISTORY_SIZE;
+    use crate::domain::*;
+    use crate::events::{EventBus, EventFilter, OrderEvent, OrderEventKind};
+    use crate::kitchen::KitchenScheduler;
+    use crate::repository::InMemoryOrderRepository;
+    use futures::StreamExt;
+    use std::error::Error as stdErr;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_publish_and_resume() {
+        let bus = EventBus::new();
+        let (replay, _receiver) = bus.subscribe(None);
+        assert!(replay.is_empty());
+        let ids: Vec<u64> = (0..3)
+            .map(|table| bus.publish(OrderEvent::created(&Order::new(table, 1))))
+            .collect();
+        assert_eq!(vec![1, 2, 3], ids);
+
+        let (replay, _receiver) = bus.subscribe(Some(1));
+        let replayed: Vec<u64> = replay.iter().map(|event| event.id).collect();
+        assert_eq!(vec![2, 3], replayed);
+        assert!(bus.subscribe(Some(3)).0.is_empty());
+        // An id from before a restart replays everything that's kept.
+        assert_eq!(3, bus.subscribe(Some(1000)).0.len());
+
+        for _ in 0..EVENT_HISTORY_SIZE {
+            bus.publish(OrderEvent::created(&Order::new(1, 1)));
+        }
+        let (replay, _receiver) = bus.subscribe(Some(0));
+        assert_eq!(EVENT_HISTORY_SIZE, replay.len());
+        assert_eq!(4, replay[0].id);
+    }
+
+    #[test]
+    fn test_event_filter_and_format() {
+        let order = Order::with_quantity(3, 7, 2);
+        let mut event = OrderEvent::created(&order);
+        event.id = 42;
+        assert!(EventFilter::default().matches(&event));
+        let filter = EventFilter {
+            table_number: Some(3),
+            menu_item_id: Some(7),
+        };
+        assert!(filter.matches(&event));
+        let filter = EventFilter {
+            menu_item_id: Some(8),
+            ..filter
+        };
+        assert!(!filter.matches(&event));
+
+        let sse = event.to_sse();
+        assert!(sse.starts_with("id: 42\nevent: order_created\ndata: {"));
+        assert!(sse.ends_with("}\n\n"));
+        assert!(sse.contains(&format!("\"order_id\":\"{}\"", order.id)));
+    }
+
+    /// Clients get the events they missed, then the live ones, only for what they filter on.
+    #[actix_web::test]
+    async fn test_event_stream() -> Result<(), Box<dyn stdErr>> {
+        let repo = InMemoryOrderRepository::new();
+        let bus = EventBus::new();
+        let first = Order::new(1, 4);
+        first.create(&repo).await?;
+        bus.publish(OrderEvent::created(&first));
+        bus.publish(OrderEvent::created(&Order::new(2, 4)));
+
+        let filter = EventFilter {
+            table_number: Some(1),
+            ..Default::default()
+        };
+        let mut stream = Box::pin(bus.events(filter, Some(0)));
+        let replayed = stream.next().await.flatten().unwrap();
+        assert_eq!((1, first.id), (replayed.id, replayed.order_id));
+
+        let cancelled = cancel_order(&repo, first.id, &Cancellation::default())
+            .await?
+            .unwrap();
+        bus.publish(OrderEvent::created(&Order::new(2, 5)));
+        bus.publish(OrderEvent::cancelled(&cancelled));
+        let live = stream.next().await.flatten().unwrap();
+        assert_eq!(OrderEventKind::OrderCancelled, live.kind);
+        assert_eq!((4, OrderStatus::Cancelled), (live.id, live.status));
+        assert_eq!(cancelled.cancelled_at, Some(live.at));
+        Ok(())
+    }
+    /// After the change feed reconnects, the kitchen catches up with what other servers changed meanwhile.
+    #[actix_web::test]
+    async fn test_catch_up() -> Result<(), Box<dyn stdErr>> {
+        let repo = InMemoryOrderRepository::new();
+        let (cooked, unchanged, cancelled) = (Order::new(1, 4), Order::new(1, 5), Order::new(2, 4));
+        let moved = Order::new(2, 5);
+        create_orders(
+            &repo,
+            &[
+                cooked.clone(),
+                unchanged.clone(),
+                cancelled.clone(),
+                moved.clone(),
+            ],
+        )
+        .await?;
+        let kitchen = KitchenScheduler::new(2);
+        kitchen.refresh(&repo).await?;
+        let bus = EventBus::new();
+
+        // Changes another server made while the feed was down.
+        let created = Order::new(3, 6);
+        created.create(&repo).await?;
+        transition_order(&repo, cooked.id, OrderStatus::Cooking).await?;
+        cancel_order(&repo, cancelled.id, &Cancellation::default()).await?;
+        let to_table_5 = OrderPatch {
+            table_number: Some(5),
+            ..Default::default()
+        };
+        update_order(&repo, moved.id, &to_table_5).await?;
+
+        catch_up(&repo, &kitchen, &bus).await;
+        let mut open: Vec<Uuid> = kitchen.open_orders().iter().map(|o| o.order_id).collect();
+        let mut expected = vec![cooked.id, unchanged.id, created.id, moved.id];
+        open.sort();
+        expected.sort();
+        assert_eq!(expected, open);
+        let mut events: Vec<(Uuid, OrderEventKind)> = bus
+            .subscribe(Some(0))
+            .0
+            .iter()
+            .map(|event| (event.order_id, event.kind))
+            .collect();
+        let mut expected = vec![
+            (cooked.id, OrderEventKind::OrderStatusChanged),
+            (created.id, OrderEventKind::OrderCreated),
+            (cancelled.id, OrderEventKind::OrderCancelled),
+            (moved.id, OrderEventKind::OrderUpdated),
+        ];
+        events.sort_by_key(|(order_id, _)| *order_id);
+        expected.sort_by_key(|(order_id, _)| *order_id);
+        assert_eq!(expected, events);
+
+        // Nothing changed since.
+        catch_up(&repo, &kitchen, &bus).await;
+        assert_eq!(4, bus.subscribe(Some(0)).0.len());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod kitchen_socket_tests {
+    use crate::domain::*;
+    use crate::error::AppError;
+    use crate::events::OrderEvent;
+    use crate::kitchen_socket::{parse_command, KitchenCommand, KitchenMessage};
+    use crate::repository::InMemoryOrderRepository;
+    use serde_json::{json, Value};
+    use std::error::Error as stdErr;
+
+    #[test]
+    fn test_kitchen_commands() {
+        let command = parse_command(r#"{"type": "subscribe", "table_number": 4}"#).unwrap();
+        assert_eq!(
+            KitchenCommand::Subscribe {
+                table_number: Some(4),
+                menu_item_id: None,
+                last_event_id: None
+            },
+            command
+        );
+        let order_id = uuid::Uuid::new_v4();
+        let command = parse_command(&json!({"type": "bump", "order_id": order_id}).to_string());
+        assert_eq!(KitchenCommand::Bump { order_id }, command.unwrap());
+
+        for text in [
+            r#"{"type": "bump"}"#,
+            r#"{"type": "serve", "order_id": 1}"#,
+            "not json",
+        ] {
+            assert!(matches!(parse_command(text), Err(AppError::BadRequest(_))));
+        }
+    }
+
+    /// Every message is tagged by `type`, events keep the fields they have over SSE.
+    #[actix_web::test]
+    async fn test_kitchen_messages() -> Result<(), Box<dyn stdErr>> {
+        let repo = InMemoryOrderRepository::new();
+        let order = Order::new(2, 5);
+        order.create(&repo).await?;
+        let mut event = OrderEvent::created(&order);
+        event.id = 7;
+        let message: Value = serde_json::from_str(&KitchenMessage::from(event).to_json())?;
+        assert_eq!("order_created", message["type"]);
+        assert_eq!(json!(7), message["id"]);
+        assert_eq!(json!(2), message["table_number"]);
+
+        let bumped = bump_order(&repo, order.id).await?.unwrap();
+        let message: Value = serde_json::from_str(
+            &KitchenMessage::from(OrderEvent::status_changed(&bumped)).to_json(),
+        )?;
+        assert_eq!("order_status_changed", message["type"]);
+        assert_eq!("cooking", message["status"]);
+        assert_eq!(json!(bumped.cooking_at), message["at"]);
+        let message: Value =
+            serde_json::from_str(&KitchenMessage::from(OrderEvent::updated(&bumped)).to_json())?;
+        assert_eq!("order_updated", message["type"]);
+        assert_eq!(json!(bumped.quantity), message["quantity"]);
+
+        let message: Value =
+            serde_json::from_str(&KitchenMessage::Bumped { order: bumped }.to_json())?;
+        assert_eq!("bumped", message["type"]);
+        assert_eq!(json!(order.id), message["order"]["order_id"]);
+
+        let error = AppError::NotFound("No order found.".to_string());
+        let message: Value = serde_json::from_str(&KitchenMessage::from(error).to_json())?;
+        assert_eq!(
+            json!({"type": "error", "code": "not_found", "message": "No order found.", "details": null}),
+            message
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod error_tests {
+    use crate::domain::*;
+    use crate::error::AppError;
+    use crate::repository::RepositoryError;
+    use actix_web::{body::to_bytes, http::StatusCode, ResponseError};
+    use serde_json::{json, Value};
+    use std::error::Error as stdErr;
+
+    #[test]
+    fn test_error_classification() {
+        let cases = [
+            (
+                AppError::from(RepositoryError::MenuItemNotFound(99)),
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "menu_item_not_found",
+            ),
+            (
+                AppError::from(RepositoryError::InvalidQuantity(0)),
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "invalid_quantity",
+            ),
+            (
+                AppError::from(RepositoryError::IllegalTransition(IllegalTransition {
+                    from: OrderStatus::Served,
+                    to: OrderStatus::Cooking,
+                })),
+                StatusCode::CONFLICT,
+                "illegal_transition",
+            ),
+            (
+                AppError::from(RepositoryError::IdempotencyKeyReused(
+                    IdempotencyKeyReused {
+                        key: "retry-1".to_string(),
+                    },
+                )),
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "idempotency_key_reused",
+            ),
+            (
+                AppError::from(RepositoryError::OrderIdConflict(uuid::Uuid::nil())),
+                StatusCode::CONFLICT,
+                "order_id_conflict",
+            ),
+            (
+                AppError::from(RepositoryError::SessionClosed(4)),
+                StatusCode::CONFLICT,
+                "session_closed",
+            ),
+            (
+                AppError::from(RepositoryError::Database(sqlx::Error::RowNotFound)),
+                StatusCode::NOT_FOUND,
+                "not_found",
+            ),
+            (
+                AppError::from(sqlx::Error::PoolTimedOut),
+                StatusCode::SERVICE_UNAVAILABLE,
+                "service_unavailable",
+            ),
+            (
+                AppError::from(sqlx::Error::WorkerCrashed),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "internal_error",
+            ),
+        ];
+        for (error, status, code) in cases {
+            assert_eq!(status, error.status_code());
+            assert_eq!(code, error.code());
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_error_body() -> Result<(), Box<dyn stdErr>> {
+        let response = AppError::from(RepositoryError::MenuItemNotFound(99)).error_response();
+        let body: Value = serde_json::from_slice(&to_bytes(response.into_body()).await?)?;
+        assert_eq!(json!("menu_item_not_found"), body["code"]);
+        assert_eq!(json!("Menu item 99 doesn't exist."), body["message"]);
+        assert_eq!(json!({ "menu_item_id": 99 }), body["details"]);
+
+        // Driver messages stay in the logs.
+        let response = AppError::Internal("connection reset".to_string()).error_response();
+        let body: Value = serde_json::from_slice(&to_bytes(response.into_body()).await?)?;
+        assert_eq!(json!("internal_error"), body["code"]);
+        assert_eq!(json!("Internal server error."), body["message"]);
+        assert_eq!(Value::Null, body["details"]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod billing_tests {
+    use crate::billing::{build_bill, split, tender_payment, validate_split, BillingRates};
+    use crate::domain::*;
+    use crate::repository::{InMemoryOrderRepository, OrderRepository, RepositoryError};
+    use chrono::Utc;
+    use std::error::Error as stdErr;
+
+    fn menu() -> Vec<MenuItem> {
+        [("Gyoza", 650), ("Ramen", 1250), ("Mochi", 333)]
+            .iter()
+            .enumerate()
+            .map(|(id, (item_name, price))| MenuItem {
+                id: id as i32,
+                item_name: item_name.to_string(),
+                cooking_time: 10,
+                price: *price,
+                retired_at: None,
+            })
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn test_table_bill() -> Result<(), Box<dyn stdErr>> {
+        let repo = InMemoryOrderRepository::with_menu(menu());
+        let orders = vec![
+            Order::with_quantity(3, 0, 2),
+            Order::new(3, 1),
+            Order::new(3, 2),
+        ];
+        create_orders(&repo, &orders).await?;
+        transition_order(&repo, orders[2].id, OrderStatus::Cancelled).await?;
+
+        let rates = BillingRates {
+            service_charge_bps: 1000,
+            tax_rate_bps: 825,
+        };
+        let bill = build_bill(3, &read_orders_by_table(&repo, 3).await?, rates, None);
+        // The cancelled mochi isn't billed.
+        assert_eq!(2, bill.lines.len());
+        assert_eq!(1300, bill.lines[0].amount);
+        assert_eq!(2550, bill.subtotal);
+        assert_eq!(255, bill.service_charge);
+        // 8.25% of 2805 is 231.4125, rounded to the nearest minor unit.
+        assert_eq!(231, bill.tax);
+        assert_eq!(3036, bill.total);
+        assert!(bill.split.is_none());
+        assert!(bill.session_id.is_some());
+
+        let bill = build_bill(3, &read_orders_by_table(&repo, 3).await?, rates, Some(4));
+        assert_eq!(Some(vec![759, 759, 759, 759]), bill.split);
+        Ok(())
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(vec![334, 333, 333], split(1000, 3));
+        assert_eq!(1000, split(1000, 7).iter().sum::<i64>());
+        assert_eq!(vec![1000], split(1000, 1));
+        assert!(validate_split(1).is_empty());
+        assert_eq!("split", validate_split(0)[0].field);
+        let empty = build_bill(
+            1,
+            &[],
+            BillingRates {
+                service_charge_bps: 1000,
+                tax_rate_bps: 1000,
+            },
+            Some(2),
+        );
+        assert_eq!(0, empty.total);
+    }
+
+    #[test]
+    fn test_tender_payment() {
+        // Cash gives change, vouchers are capped without change, cards can't exceed what's due.
+        assert_eq!(Ok((800, 200)), tender_payment(800, Tender::Cash, 1000));
+        assert_eq!(Ok((300, 0)), tender_payment(800, Tender::Cash, 300));
+        assert_eq!(Ok((800, 0)), tender_payment(800, Tender::Voucher, 1000));
+        assert_eq!(
+            Err(PaymentError::Overpayment {
+                due: 800,
+                amount: 1000
+            }),
+            tender_payment(800, Tender::Card, 1000)
+        );
+        assert_eq!(
+            Err(PaymentError::NothingDue),
+            tender_payment(0, Tender::Cash, 100)
+        );
+        assert!(validate_amount(1).is_empty());
+        assert_eq!("amount", validate_amount(0)[0].field);
+    }
+
+    #[actix_web::test]
+    async fn test_payments_and_settlement() -> Result<(), Box<dyn stdErr>> {
+        let repo = InMemoryOrderRepository::with_menu(menu());
+        let rates = BillingRates {
+            service_charge_bps: 0,
+            tax_rate_bps: 0,
+        };
+        let result = record_payment(&repo, &repo, &repo, 4, Tender::Cash, 100, rates).await;
+        assert!(matches!(
+            result,
+            Err(RepositoryError::Payment(PaymentError::NoOpenSession(4)))
+        ));
+
+        // Gyoza and ramen, 1900 in total.
+        let gyoza = Order::new(4, 0);
+        create_orders(&repo, &[gyoza.clone(), Order::new(4, 1)]).await?;
+        let card = record_payment(&repo, &repo, &repo, 4, Tender::Card, 1000, rates).await?;
+        let result = settle_table(&repo, &repo, 4, rates).await;
+        assert!(matches!(
+            result,
+            Err(RepositoryError::Payment(PaymentError::NotCovered {
+                total: 1900,
+                paid: 1000
+            }))
+        ));
+        let cash = record_payment(&repo, &repo, &repo, 4, Tender::Cash, 2000, rates).await?;
+        assert_eq!((900, 2000, 1100), (cash.amount, cash.tendered, cash.change));
+        let result = record_payment(&repo, &repo, &repo, 4, Tender::Cash, 100, rates).await;
+        assert!(matches!(
+            result,
+            Err(RepositoryError::Payment(PaymentError::NothingDue))
+        ));
+
+        // Refunds can't give back more than what's left of the payment, and reopen the balance.
+        refund_payment(&repo, card.id, 600).await?.unwrap();
+        let result = refund_payment(&repo, card.id, 500).await;
+        assert!(matches!(
+            result,
+            Err(RepositoryError::Payment(
+                PaymentError::RefundExceedsPayment {
+                    refundable: 400,
+                    amount: 500
+                }
+            ))
+        ));
+        let payments = read_session_payments(&repo, &repo, &repo, 4, None, rates)
+            .await?
+            .unwrap();
+        assert_eq!(3, payments.payments.len());
+        assert_eq!(1300, payments.balance.paid);
+        assert_eq!(600, payments.balance.due);
+        record_payment(&repo, &repo, &repo, 4, Tender::Voucher, 1000, rates).await?;
+
+        let settled = settle_table(&repo, &repo, 4, rates).await?;
+        assert!(settled.settled_at.is_some());
+        assert!(read_current_session(&repo, 4).await?.is_none());
+        // Its bill is final.
+        let result = refund_payment(&repo, card.id, 100).await;
+        assert!(matches!(result, Err(RepositoryError::SessionClosed(4))));
+        let result = cancel_order(&repo, gyoza.id, &Cancellation::default()).await;
+        assert!(matches!(result, Err(RepositoryError::SessionClosed(4))));
+        let result = repo
+            .cancel_plate(
+                gyoza.id,
+                OrderStatus::Ordered,
+                &Cancellation::default(),
+                Utc::now(),
+            )
+            .await;
+        assert!(matches!(result, Err(RepositoryError::SessionClosed(4))));
+        // The settled session stays queryable.
+        let payments = read_session_payments(&repo, &repo, &repo, 4, Some(settled.id), rates)
+            .await?
+            .unwrap();
+        assert_eq!(0, payments.balance.due);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod handler_tests {
+    use crate::config::Config;
+    use crate::events::EventBus;
+    use crate::kitchen::KitchenScheduler;
+    use crate::repository::InMemoryOrderRepository;
+    use crate::routes;
+    use actix_web::dev::ServiceResponse;
+    use actix_web::{test, web, App};
+    use serde_json::{json, Value};
+    use uuid::Uuid;
+
+    type Repo = InMemoryOrderRepository;
+
+    /// Registers the routes over an in-memory repository, like `new_http_server` does.
+    fn api(repo: Repo) -> impl FnOnce(&mut web::ServiceConfig) {
+        move |cfg| {
+            routes::<Repo, Repo, Repo, Repo>(cfg);
+            cfg.app_data(web::Data::new(repo))
+                .app_data(web::Data::new(KitchenScheduler::new(2)))
+                .app_data(web::Data::new(EventBus::new()))
+                .app_data(web::Data::new(Config::default()));
+        }
+    }
+
+    /// The status of an error response and the `code` of its body.
+    async fn error_of(response: ServiceResponse) -> (u16, Value) {
+        let status = response.status().as_u16();
+        let body: Value = test::read_body_json(response).await;
+        (status, body["code"].clone())
+    }
+
+    #[actix_web::test]
+    async fn test_bad_requests() {
+        let app = test::init_service(App::new().configure(api(Repo::new()))).await;
+        let request = test::TestRequest::post()
+            .uri("/v1/orders")
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload("{")
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!((400, json!("bad_request")), error_of(response).await);
+
+        let request = test::TestRequest::post()
+            .uri("/v1/orders")
+            .set_json(json!({ "table_number": 0, "menu_item_id": 1 }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!((400, json!("validation_failed")), error_of(response).await);
+
+        let request = test::TestRequest::post()
+            .uri("/v1/menu_items")
+            .set_json(json!({ "item_name": " ", "cooking_time": 0, "price": -1 }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!("validation_failed", body["code"]);
+        assert_eq!(3, body["details"]["violations"].as_array().unwrap().len());
+    }
+
+    #[actix_web::test]
+    async fn test_not_found() {
+        let app = test::init_service(App::new().configure(api(Repo::new()))).await;
+        let request = test::TestRequest::get()
+            .uri(&format!("/v1/orders/{}", Uuid::new_v4()))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!((404, json!("not_found")), error_of(response).await);
+
+        let request = test::TestRequest::post()
+            .uri("/v1/tables/5/sessions/current/close")
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!((404, json!("not_found")), error_of(response).await);
+    }
+
+    #[actix_web::test]
+    async fn test_conflicts() {
+        let app = test::init_service(App::new().configure(api(Repo::new()))).await;
+        let request = test::TestRequest::post()
+            .uri("/v1/orders")
+            .set_json(json!({ "table_number": 5, "menu_item_id": 1 }))
+            .to_request();
+        let order_id: Uuid = test::call_and_read_body_json(&app, request).await;
+
+        let request = test::TestRequest::post()
+            .uri(&format!("/v1/orders/{}/transitions", order_id))
+            .set_json(json!({ "status": "served" }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!((409, json!("illegal_transition")), error_of(response).await);
+
+        // The bill isn't paid: the session can be neither settled nor closed.
+        for action in ["settle", "sessions/current/close"] {
+            let request = test::TestRequest::post()
+                .uri(&format!("/v1/tables/5/{}", action))
+                .to_request();
+            let response = test::call_service(&app, request).await;
+            assert_eq!((409, json!("bill_not_covered")), error_of(response).await);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_unprocessable_requests() {
+        let app = test::init_service(App::new().configure(api(Repo::new()))).await;
+        let key = Uuid::new_v4().to_string();
+        let create = |menu_item_id: i32| {
+            test::TestRequest::post()
+                .uri("/v1/orders")
+                .insert_header(("Idempotency-Key", key.as_str()))
+                .set_json(json!({ "table_number": 5, "menu_item_id": menu_item_id }))
+                .to_request()
+        };
+        let response = test::call_service(&app, create(1)).await;
+        assert_eq!(200, response.status().as_u16());
+        let response = test::call_service(&app, create(2)).await;
+        assert_eq!(
+            (422, json!("idempotency_key_reused")),
+            error_of(response).await
+        );
+
+        let request = test::TestRequest::post()
+            .uri("/v1/tables/5/payments")
+            .set_json(json!({ "tender": "card", "amount": 1_000_000 }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!((422, json!("overpayment")), error_of(response).await);
+    }
+}
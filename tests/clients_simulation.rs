@@ -2,7 +2,10 @@
 mod clients_simulation {
     use actix_web::rt::time::sleep;
     use rand::Rng;
+    use restaurant::config::Config;
+    use restaurant::new_http_server;
     use restaurant::presentation::OrderRequest;
+    use restaurant::repository::InMemoryOrderRepository;
     use std::{collections::HashMap, time::Duration};
 
     // Test parameters
@@ -11,33 +14,104 @@ mod clients_simulation {
     const TOTAL_REQS_PER_CLIENT: usize = 25;
 
     // Connection parameters
-    const SERVER_ADDRS: &str = "http://127.0.0.1:8080"; // Address to spawn the test server into
+    const SERVER_SOCKET_ADDRS: &str = "127.0.0.1:8080"; // Address to spawn the test server into
+    const SERVER_ADDRS: &str = "http://127.0.0.1:8080";
 
     /// Integration test that spawns the HTTP REST API Server
     /// and simulates concurrent clients randomly executing requests.
     ///
     #[actix_web::test]
     async fn simulate_client_behavior() {
+        // The server runs on an in-memory repository so the simulation doesn't need PostgreSQL.
+        let repo = InMemoryOrderRepository::new();
+        let server = new_http_server(
+            SERVER_SOCKET_ADDRS,
+            repo.clone(),
+            repo.clone(),
+            repo.clone(),
+            repo,
+            Config::default(),
+        )
+        .await
+        .expect("Failed to bind the test server");
+        let server_handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        // A create retried with the same idempotency key returns the same order and doesn't create another.
+        let client = reqwest::Client::new();
+        let request = OrderRequest {
+            id: None,
+            table_number: RESTAURANT_TABLES_QTY as i32,
+            menu_item_id: 1,
+            quantity: 2,
+        };
+        let key = uuid::Uuid::new_v4().to_string();
+        let mut uuids = vec![];
+        for replayed in [None, Some("true")] {
+            let response = client
+                .post(format!("{}/v1/orders", SERVER_ADDRS))
+                .header("Idempotency-Key", &key)
+                .json(&request)
+                .send()
+                .await
+                .expect("Failed to create the order");
+            assert_eq!(200, response.status().as_u16());
+            assert_eq!(
+                replayed,
+                response
+                    .headers()
+                    .get("Idempotent-Replayed")
+                    .map(|value| value.to_str().unwrap())
+            );
+            uuids.push(response.json::<uuid::Uuid>().await.unwrap());
+        }
+        assert_eq!(uuids[0], uuids[1]);
+        let page = client
+            .get(format!(
+                "{}/v1/tables/{}/orders",
+                SERVER_ADDRS, RESTAURANT_TABLES_QTY
+            ))
+            .send()
+            .await
+            .expect("Failed to read the orders of the table")
+            .json::<serde_json::Value>()
+            .await
+            .unwrap();
+        let orders = page["orders"].as_array().unwrap();
+        assert_eq!(1, orders.len());
+        assert_eq!(uuids[0].to_string(), orders[0]["order_id"]);
+
         let client_simulation = |_client_number| async {
             let client = reqwest::Client::new();
             let menu_item_id = rand::random::<usize>() % TOTAL_MENU_ITEMS_QTY;
-            let table_number = rand::random::<usize>() % RESTAURANT_TABLES_QTY;
+            let table_number = rand::random::<usize>() % RESTAURANT_TABLES_QTY + 1;
             let mut rng = rand::thread_rng();
 
             for _ in 1..=TOTAL_REQS_PER_CLIENT {
                 // Randomly choose from server api request per client simulation
                 match rng.gen_range(1..=4) {
                     1 => {
-                        // simulate create
-                        let response = client
-                            .post(format!("{}/v1/orders", SERVER_ADDRS))
-                            .json(&OrderRequest {
-                                table_number: table_number as i32,
-                                menu_item_id: menu_item_id as i32,
-                            })
-                            .send()
-                            .await;
-                        assert!(response.is_ok());
+                        // simulate create, retried with the same idempotency key like after a timeout
+                        let request = OrderRequest {
+                            id: None,
+                            table_number: table_number as i32,
+                            menu_item_id: menu_item_id as i32,
+                            quantity: rng.gen_range(1..=3),
+                        };
+                        let key = uuid::Uuid::new_v4().to_string();
+                        let mut uuids = vec![];
+                        for _ in 0..2 {
+                            let response = client
+                                .post(format!("{}/v1/orders", SERVER_ADDRS))
+                                .header("Idempotency-Key", &key)
+                                .json(&request)
+                                .send()
+                                .await
+                                .expect("Failed to create the order");
+                            assert_eq!(200, response.status().as_u16());
+                            uuids.push(response.json::<uuid::Uuid>().await.unwrap());
+                        }
+                        assert_eq!(uuids[0], uuids[1]);
                     }
                     2 => {
                         // simulate query all items from table request
@@ -52,31 +126,35 @@ mod clients_simulation {
 
                         // Delete request.
                         // After this querying all items, run 50% probability to execute 1 delete query by id
-                        let orders_json_data = response
+                        let page_json_data = response
                             .unwrap()
-                            .json::<Vec<HashMap<String, serde_json::Value>>>()
+                            .json::<HashMap<String, serde_json::Value>>()
                             .await;
-                        match orders_json_data {
-                            Err(_) => continue, // If error, then "No orders found". Continue the loop.
-                            _ => (),
-                        }
+                        let Some(orders_json_data) = page_json_data
+                            .ok()
+                            .and_then(|page| page.get("orders").cloned())
+                            .and_then(|orders| {
+                                serde_json::from_value::<Vec<HashMap<String, serde_json::Value>>>(
+                                    orders,
+                                )
+                                .ok()
+                            })
+                        else {
+                            continue; // If error, then no page was returned. Continue the loop.
+                        };
 
-                        for order_json in orders_json_data.unwrap() {
-                            let order_id = order_json.get("order_id");
-                            match order_id {
-                                Some(id) => {
-                                    let response = client
-                                        .get(format!(
-                                            "{}/v1/orders/{}",
-                                            SERVER_ADDRS,
-                                            id.to_string()
-                                        ))
-                                        .send()
-                                        .await;
-                                    assert!(response.is_ok());
-                                    break; // finish this deletion test after deleting 1 order from the list
-                                }
-                                None => (),
+                        for order_json in orders_json_data {
+                            if let Some(id) = order_json.get("order_id").and_then(|id| id.as_str())
+                            {
+                                let response = client
+                                    .get(format!("{}/v1/orders/{}", SERVER_ADDRS, id))
+                                    .send()
+                                    .await;
+                                assert!(response.is_ok());
+                                // The order may have been deleted by another client in the meantime.
+                                let status = response.unwrap().status();
+                                assert!(status.is_success() || status.as_u16() == 404);
+                                break; // finish this deletion test after deleting 1 order from the list
                             }
                         }
                     }
@@ -111,13 +189,13 @@ mod clients_simulation {
 
         // Simulate multiple clients
         let client_tasks: Vec<_> = (1..=CONCURRENT_CLIENTS_QTY)
-            .map(|i| client_simulation(i))
+            .map(client_simulation)
             .collect();
 
         // Run all client tasks concurrently
         let _ = futures::future::join_all(client_tasks).await;
 
-        return ();
+        server_handle.stop(true).await;
     }
 
     // Total registered items when setting up the TABLE 'menu_items'.